@@ -3,11 +3,20 @@ use std::env;
 use std::fs;
 use std::io;//
 
-use zip;
+use sha2::{Digest, Sha256};
 
 const MMT: &str = "multimonitortool.zip";
 const MMT_X64: &str = "multimonitortool-x64.zip";
 const ADC: &str = "AudioDeviceCmdlets.dll";
+const MMT_EXE: &str = "MultiMonitorTool.exe";
+
+// Pinned SHA-256 checksums of the bundled assets. Update these together with
+// the files in assets/.
+const MMT_SHA256: &str = "400837d4e6cba4bf4e3292bfa74b245107cbe40f10c138be5dde67c5e1431ec5";
+const MMT_X64_SHA256: &str = "2cf23f292ed38c946a8c7e1b904f89d3f6af9d3a5ecb43cef681bb07a3702715";
+const MMT_EXE_SHA256: &str = "0af295da9ad3ac11b119179867f8064818540803e7941a8a8e4bf6f7491f20e1";
+const MMT_X64_EXE_SHA256: &str = "525bd5f5db594bbb3b97fcb7d2a6f2df0e12456ccb6853245c6f70bb3cf172e2";
+const ADC_SHA256: &str = "ba6c7669dc6355ccdb549c03cb4872e04fba5d6c181e60c4636eb70fafce1bc5";
 
 fn unzip(zip_path: &Path, output_path: &Path) {
     let file: fs::File = fs::File::open(zip_path).unwrap();
@@ -22,9 +31,22 @@ fn unzip(zip_path: &Path, output_path: &Path) {
     }
 }
 
+fn sha256_hex(path: &Path) -> String {
+    let bytes: Vec<u8> = fs::read(path).unwrap();
+    Sha256::digest(&bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn verify_sha256(path: &Path, expected: &str) {
+    let actual: String = sha256_hex(path);
+    if actual != expected {
+        panic!("{} has SHA-256 {}, expected the pinned {}", path.display(), actual, expected)
+    }
+}
+
 fn main() {
+    // MultiMonitorTool and AudioDeviceCmdlets are only bundled for Windows
     if env::var("CARGO_CFG_TARGET_OS").unwrap() != "windows" {
-        panic!("steamwig only supports Windows os build targets")
+        return
     }
 
     let root_path: PathBuf = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
@@ -35,15 +57,30 @@ fn main() {
 
     std::fs::create_dir_all(&output_path).unwrap();
 
-    match &env::var("CARGO_CFG_TARGET_ARCH").unwrap()[..] {
+    let mmt_exe_sha256: &str = match &env::var("CARGO_CFG_TARGET_ARCH").unwrap()[..] {
         "x86" => {
+            verify_sha256(&asset_path.join(MMT), MMT_SHA256);
             unzip(&asset_path.join(MMT), &output_path);
+            MMT_EXE_SHA256
         },
         "x86_64" => {
+            verify_sha256(&asset_path.join(MMT_X64), MMT_X64_SHA256);
             unzip(&asset_path.join(MMT_X64), &output_path);
+            MMT_X64_EXE_SHA256
         },
         _ => panic!("steamwig only supports i686 and x86_64 architecture build targets"),
-    }
+    };
+    verify_sha256(&output_path.join(MMT_EXE), mmt_exe_sha256);
+
+    verify_sha256(&asset_path.join(ADC), ADC_SHA256);
+    fs::copy(asset_path.join(ADC), output_path.join(ADC)).unwrap();
 
-    fs::copy(&asset_path.join(ADC), &output_path.join(ADC)).unwrap();
+    // Embedded in the binary, and extracted at runtime if no assets are
+    // found next to the executable
+    let out_dir: PathBuf = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::copy(output_path.join(MMT_EXE), out_dir.join(MMT_EXE)).unwrap();
+    fs::copy(output_path.join(ADC), out_dir.join(ADC)).unwrap();
+    println!("cargo:rustc-env=STEAMWIG_MMT_SHA256={}", mmt_exe_sha256);
+    println!("cargo:rustc-env=STEAMWIG_ADC_SHA256={}", ADC_SHA256);
+    println!("cargo:rerun-if-changed=assets");
 }
\ No newline at end of file
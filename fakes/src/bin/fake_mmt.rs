@@ -0,0 +1,252 @@
+//! Mimics the parts of MultiMonitorTool.exe that steamwig's `MMTModifier` uses:
+//! `/scomma`, `/enable`, `/disable`, `/SetPrimary`, `/SetMonitors`,
+//! `/SaveConfig` and `/LoadConfig`.
+//!
+//! Monitors are read from, and written back to, the JSON file named by
+//! `STEAMWIG_FAKE_MMT_STATE`:
+//!
+//! ```json
+//! { "monitors": [
+//!     { "id": "MONITOR\\GSM5B08\\{4d36e96e-e325-11ce-bfc1-08002be10318}\\0001",
+//!       "name": "\\\\.\\DISPLAY1", "enabled": true, "primary": true,
+//!       "x": 0, "y": 0, "width": 2560, "height": 1440 }
+//! ] }
+//! ```
+//!
+//! Monitors are matched by ID or name. Unknown monitors and commands exit
+//! with 1 and a message on stderr.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use serde::{Deserialize, Serialize};
+
+static STATE_ENV: &str = "STEAMWIG_FAKE_MMT_STATE";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct State {
+    monitors: Vec<Monitor>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Monitor {
+    id: String,
+    name: String,
+    enabled: bool,
+    primary: bool,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl State {
+    fn monitor_mut(&mut self, id: &str) -> Result<&mut Monitor, String> {
+        self.monitors.iter_mut()
+            .find(|m| m.id == id || m.name == id)
+            .ok_or(format!("no monitor {id}"))
+    }
+
+    // Windows keeps the primary monitor at 0, 0, and moves the others with it
+    fn set_primary(&mut self, id: &str) -> Result<(), String> {
+        let (x, y) = {
+            let monitor: &mut Monitor = self.monitor_mut(id)?;
+            if !monitor.enabled {
+                return Err(format!("monitor {id} is disabled"))
+            }
+            (monitor.x, monitor.y)
+        };
+        for monitor in &mut self.monitors {
+            monitor.primary = monitor.id == id || monitor.name == id;
+            monitor.x -= x;
+            monitor.y -= y;
+        }
+        Ok(())
+    }
+
+    // Enabled monitors are placed to the right of the others
+    fn enable(&mut self, id: &str) -> Result<(), String> {
+        let right: i32 = self.monitors.iter()
+            .filter(|m| m.enabled)
+            .map(|m| m.x + m.width)
+            .max()
+            .unwrap_or(0);
+        let monitor: &mut Monitor = self.monitor_mut(id)?;
+        if !monitor.enabled {
+            monitor.enabled = true;
+            monitor.x = right;
+            monitor.y = 0;
+        }
+        Ok(())
+    }
+
+    fn disable(&mut self, id: &str) -> Result<(), String> {
+        let monitor: &mut Monitor = self.monitor_mut(id)?;
+        let was_primary: bool = monitor.primary;
+        monitor.enabled = false;
+        monitor.primary = false;
+        if was_primary {
+            let next_id: Option<String> = self.monitors.iter().find(|m| m.enabled).map(|m| m.id.clone());
+            if let Some(next_id) = next_id {
+                self.set_primary(&next_id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn state_path() -> Result<PathBuf, String> {
+    env::var_os(STATE_ENV).map(PathBuf::from).ok_or(format!("{STATE_ENV} is not set"))
+}
+
+fn read_state() -> Result<State, String> {
+    let state_str: String = fs::read_to_string(state_path()?).map_err(|e| e.to_string())?;
+    serde_json::from_str(&state_str).map_err(|e| e.to_string())
+}
+
+fn write_state(state: &State) -> Result<(), String> {
+    let state_str: String = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+    fs::write(state_path()?, state_str).map_err(|e| e.to_string())
+}
+
+fn csv_field(s: &str) -> String {
+    match s.contains(',') || s.contains('"') {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
+
+fn yes_no(b: bool) -> &'static str {
+    match b {
+        true => "Yes",
+        false => "No",
+    }
+}
+
+// The same columns as MultiMonitorTool, of which steamwig reads Left - Top,
+// Active, Primary and Monitor ID
+fn scomma(state: &State) -> String {
+    let mut csv: String = String::from(
+        "Resolution,Left - Top,Right - Bottom,Active,Disconnected,Primary,Colors,Frequency,Orientation,Maximum Resolution,Name,Adapter,Device ID,Device Key,Monitor ID\r\n",
+    );
+    for monitor in &state.monitors {
+        let fields: Vec<String> = vec![
+            format!("{} X {}", monitor.width, monitor.height),
+            format!("{}, {}", monitor.x, monitor.y),
+            format!("{}, {}", monitor.x + monitor.width, monitor.y + monitor.height),
+            yes_no(monitor.enabled).to_string(),
+            "No".to_string(),
+            yes_no(monitor.primary).to_string(),
+            "32".to_string(),
+            "60".to_string(),
+            "Default".to_string(),
+            format!("{} X {}", monitor.width, monitor.height),
+            monitor.name.clone(),
+            "Fake Adapter".to_string(),
+            String::new(),
+            String::new(),
+            monitor.id.clone(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn save_config(state: &State) -> String {
+    let mut config: String = String::new();
+    for (i, monitor) in state.monitors.iter().enumerate() {
+        let (bits, width, height) = match monitor.enabled {
+            true => (32, monitor.width, monitor.height),
+            false => (0, 0, 0),
+        };
+        config.push_str(&format!(
+            "[Monitor{i}]\r\nName={}\r\nMonitorID={}\r\nBitsPerPixel={bits}\r\nWidth={width}\r\nHeight={height}\r\nDisplayFlags=0\r\nDisplayFrequency=60\r\nDisplayOrientation=0\r\nPositionX={}\r\nPositionY={}\r\n",
+            monitor.name,
+            monitor.id,
+            monitor.x,
+            monitor.y,
+        ));
+    }
+    config
+}
+
+fn load_config(state: &mut State, config: &str) -> Result<(), String> {
+    let mut sections: Vec<Vec<(String, String)>> = Vec::new();
+    for line in config.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        if line.starts_with('[') {
+            sections.push(Vec::new());
+        } else if let (Some(section), Some((key, value))) = (sections.last_mut(), line.split_once('=')) {
+            section.push((key.to_string(), value.to_string()));
+        }
+    }
+    for section in sections {
+        let property = |key: &str| section.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone()).unwrap_or_default();
+        let integer = |key: &str| property(key).parse::<i32>().map_err(|_| format!("{key} should be an integer"));
+        let monitor: &mut Monitor = state.monitor_mut(&property("MonitorID"))?;
+        let (width, height) = (integer("Width")?, integer("Height")?);
+        monitor.enabled = width > 0 && height > 0;
+        if monitor.enabled {
+            monitor.width = width;
+            monitor.height = height;
+            monitor.x = integer("PositionX")?;
+            monitor.y = integer("PositionY")?;
+        }
+        monitor.primary = monitor.enabled && monitor.x == 0 && monitor.y == 0;
+    }
+    Ok(())
+}
+
+// Each argument is "Name=<monitor> PositionX=<x> PositionY=<y>"
+fn set_monitors(state: &mut State, args: &[String]) -> Result<(), String> {
+    for arg in args {
+        let properties: Vec<(&str, &str)> = arg.split_whitespace().filter_map(|p| p.split_once('=')).collect();
+        let property = |key: &str| properties.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+        let id: &str = property("Name").ok_or(format!("{arg} has no Name"))?;
+        let monitor: &mut Monitor = state.monitor_mut(id)?;
+        if let Some(x) = property("PositionX") {
+            monitor.x = x.parse().map_err(|_| format!("PositionX {x} should be an integer"))?;
+        }
+        if let Some(y) = property("PositionY") {
+            monitor.y = y.parse().map_err(|_| format!("PositionY {y} should be an integer"))?;
+        }
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (command, command_args) = args.split_first().ok_or("no command given")?;
+    let mut state: State = read_state()?;
+    match command.as_str() {
+        "/scomma" => {
+            let path: &String = command_args.first().ok_or("/scomma needs a file")?;
+            return fs::write(path, scomma(&state)).map_err(|e| e.to_string())
+        },
+        "/SaveConfig" => {
+            let path: &String = command_args.first().ok_or("/SaveConfig needs a file")?;
+            return fs::write(path, save_config(&state)).map_err(|e| e.to_string())
+        },
+        "/LoadConfig" => {
+            let path: &String = command_args.first().ok_or("/LoadConfig needs a file")?;
+            let config: String = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            load_config(&mut state, &config)?;
+        },
+        "/enable" => for id in command_args { state.enable(id)? },
+        "/disable" => for id in command_args { state.disable(id)? },
+        "/SetPrimary" => state.set_primary(command_args.first().ok_or("/SetPrimary needs a monitor")?)?,
+        "/SetMonitors" => set_monitors(&mut state, command_args)?,
+        _ => return Err(format!("unknown command {command}")),
+    }
+    write_state(&state)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("fake-mmt: {e}");
+        process::exit(1);
+    }
+}
\ No newline at end of file
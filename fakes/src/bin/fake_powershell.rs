@@ -0,0 +1,221 @@
+//! Mimics a PowerShell process with the AudioDeviceCmdlets module imported,
+//! speaking the line protocol of steamwig's `PowershellSession`. It understands
+//! `Import-Module`, `Get-AudioDevice -List`, steamwig's JSON state query and
+//! `Set-AudioDevice -ID`, `-PlaybackVolume` and `-PlaybackMute`.
+//!
+//! Run with `-Command <script>`, as `PowershellSession::with_runner` does, it
+//! runs the one script after its `Import-Module` and exits.
+//!
+//! Devices are read from, and written back to, the JSON file named by
+//! `STEAMWIG_FAKE_AUDIO_STATE`:
+//!
+//! ```json
+//! { "volume": 50, "muted": false, "devices": [
+//!     { "index": 1, "default": true, "type": "Playback", "name": "Speakers",
+//!       "id": "{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}" }
+//! ] }
+//! ```
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+static STATE_ENV: &str = "STEAMWIG_FAKE_AUDIO_STATE";
+
+// The markers of PowershellSession's protocol. Each request is a single line,
+// "try { <script> | Out-String ...; '<ok>' } catch { ...; '<error>' }"
+static RESPONSE_OK: &str = "<<steamwig:ok>>";
+static RESPONSE_ERROR: &str = "<<steamwig:error>>";
+static REQUEST_PREFIX: &str = "try { ";
+static REQUEST_SUFFIX: &str = " | Out-String";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct State {
+    volume: f64,
+    muted: bool,
+    devices: Vec<Device>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Device {
+    index: u32,
+    default: bool,
+    #[serde(rename = "type")]
+    device_type: String,
+    name: String,
+    id: String,
+}
+
+fn state_path() -> Result<PathBuf, String> {
+    env::var_os(STATE_ENV).map(PathBuf::from).ok_or(format!("{STATE_ENV} is not set"))
+}
+
+fn read_state() -> Result<State, String> {
+    let state_str: String = fs::read_to_string(state_path()?).map_err(|e| e.to_string())?;
+    serde_json::from_str(&state_str).map_err(|e| e.to_string())
+}
+
+fn write_state(state: &State) -> Result<(), String> {
+    let state_str: String = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+    fs::write(state_path()?, state_str).map_err(|e| e.to_string())
+}
+
+fn device_list(state: &State) -> String {
+    let mut list: String = String::new();
+    for device in &state.devices {
+        list.push_str(&format!(
+            "\nIndex   : {}\nDefault : {}\nType    : {}\nName    : {}\nID      : {}\nDevice  : CoreAudioApi.MMDevice\n",
+            device.index,
+            match device.default {
+                true => "True",
+                false => "False",
+            },
+            device.device_type,
+            device.name,
+            device.id,
+        ));
+    }
+    list.push('\n');
+    list
+}
+
+fn state_json(state: &State) -> String {
+    let default_id: &str = state.devices.iter()
+        .find(|d| d.default && d.device_type == "Playback")
+        .map_or("", |d| d.id.as_str());
+    let devices: Vec<serde_json::Value> = state.devices.iter()
+        .map(|d| json!({
+            "Index": d.index,
+            "Default": d.default,
+            "Type": d.device_type,
+            "Name": d.name,
+            "ID": d.id,
+        }))
+        .collect();
+    let state_json: serde_json::Value = json!({
+        "Default": default_id,
+        "Volume": state.volume,
+        "Muted": state.muted,
+        "Devices": devices,
+    });
+    format!("{}\n", state_json)
+}
+
+fn set_default(state: &mut State, id: &str) -> Result<(), String> {
+    let device_type: String = state.devices.iter()
+        .find(|d| d.id == id)
+        .map(|d| d.device_type.clone())
+        .ok_or(format!("No AudioDevice with that ID: {id}"))?;
+    for device in state.devices.iter_mut().filter(|d| d.device_type == device_type) {
+        device.default = device.id == id;
+    }
+    Ok(())
+}
+
+fn is_single_quote(c: char) -> bool {
+    matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}')
+}
+
+// Reads a single-quoted string literal, in which any two single quotes,
+// typographic ones included, are the second of them
+fn unquote(literal: &str) -> Result<String, String> {
+    let inner: &str = literal.strip_prefix('\'')
+        .and_then(|l| l.strip_suffix('\''))
+        .ok_or(format!("{literal} should be a single-quoted string"))?;
+    let mut unquoted: String = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match is_single_quote(c) {
+            true => match chars.next() {
+                Some(escaped) if is_single_quote(escaped) => unquoted.push(escaped),
+                _ => return Err(format!("{literal} has an unescaped single quote")),
+            },
+            false => unquoted.push(c),
+        }
+    }
+    Ok(unquoted)
+}
+
+// Returns what the script writes to stdout
+fn run_script(script: &str) -> Result<String, String> {
+    if script.contains("Import-Module") {
+        return Ok(String::new())
+    }
+    let mut state: State = read_state()?;
+    if script.contains("ConvertTo-Json") {
+        return Ok(state_json(&state))
+    }
+    if let Some(literal) = script.strip_prefix("Set-AudioDevice -ID ") {
+        set_default(&mut state, &unquote(literal)?)?;
+        write_state(&state)?;
+        return Ok(String::new())
+    }
+    let words: Vec<&str> = script.split_whitespace().collect();
+    match words.as_slice() {
+        ["Get-AudioDevice", "-List"] => return Ok(device_list(&state)),
+        ["Set-AudioDevice", "-PlaybackVolume", volume] => {
+            state.volume = volume.parse().map_err(|_| format!("volume {volume} should be a number"))?
+        },
+        ["Set-AudioDevice", "-PlaybackMute", "$true"] => state.muted = true,
+        ["Set-AudioDevice", "-PlaybackMute", "$false"] => state.muted = false,
+        _ => return Err(format!("fake-powershell does not understand: {script}")),
+    }
+    write_state(&state)?;
+    Ok(String::new())
+}
+
+// "$ErrorActionPreference = 'Stop'; Import-Module '<path>'; <script> | Out-String ..."
+fn command_script(command: &str) -> Result<&str, String> {
+    let literal: &str = command.split_once("Import-Module ")
+        .map(|(_, rest)| rest)
+        .ok_or("the command should import a module")?;
+    // The module path ends at the first single quote which is not doubled
+    let mut chars = literal.char_indices().skip(1).peekable();
+    let end: usize = loop {
+        match chars.next() {
+            Some((_, '\'')) if chars.peek().is_some_and(|(_, c)| *c == '\'') => { chars.next(); },
+            Some((i, '\'')) => break i,
+            Some(_) => (),
+            None => return Err("the module path is not terminated".to_string()),
+        }
+    };
+    let script: &str = literal[end + 1..].strip_prefix("; ").ok_or("the script should follow the import")?;
+    Ok(script.split_once(REQUEST_SUFFIX).map_or(script, |(s, _)| s))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(command) = args.iter().skip_while(|a| *a != "-Command").nth(1).filter(|c| *c != "-") {
+        match command_script(command).and_then(run_script) {
+            Ok(output) => print!("{output}"),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            },
+        }
+        return
+    }
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    for line in stdin.lock().lines() {
+        let line: String = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        let script: &str = match (line.find(REQUEST_PREFIX), line.find(REQUEST_SUFFIX)) {
+            (Some(start), Some(end)) if start + REQUEST_PREFIX.len() <= end => &line[start + REQUEST_PREFIX.len()..end],
+            _ => line.as_str(),
+        };
+        let response: String = match run_script(script) {
+            Ok(output) => format!("{output}{RESPONSE_OK}\n"),
+            Err(e) => format!("{e}\n{RESPONSE_ERROR}\n"),
+        };
+        if stdout.write_all(response.as_bytes()).and_then(|_| stdout.flush()).is_err() {
+            break
+        }
+    }
+}
\ No newline at end of file
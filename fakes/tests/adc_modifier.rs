@@ -0,0 +1,71 @@
+//! Runs steamwig's `ADCModifier` against fake-powershell, reading back the
+//! audio state after each change.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use steamwig::{ADCModifier, AudioDevice, AudioDeviceType, AudioModifier, AudioState, ProcessRunner, RecordingRunner, ReplayRunner};
+
+static SPEAKERS: &str = "{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}";
+static TV: &str = "{0.0.0.00000000}.{8C2D9E4F-1A3B-4C5D-8E7F-6A5B4C3D2E1F}";
+static MICROPHONE: &str = "{0.0.1.00000000}.{0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d}";
+
+fn state() -> serde_json::Value {
+    serde_json::json!({ "volume": 48.5, "muted": false, "devices": [
+        { "index": 1, "default": true, "type": "Playback", "name": "Speakers (Realtek High Definition Audio)", "id": SPEAKERS },
+        { "index": 2, "default": false, "type": "Playback", "name": "LG TV (NVIDIA High Definition Audio)", "id": TV },
+        { "index": 3, "default": true, "type": "Recording", "name": "Microphone (USB Audio)", "id": MICROPHONE },
+    ] })
+}
+
+fn fake_adc_modifier() -> ADCModifier {
+    ADCModifier::with_powershell_path(PathBuf::from("AudioDeviceCmdlets.dll"), PathBuf::from(env!("CARGO_BIN_EXE_fake-powershell")))
+}
+
+// A single test, as the fake reads its state file from the environment
+#[test]
+fn modifies_and_reads_back_audio() {
+    let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let state_path: PathBuf = dir.path().join("audio.json");
+    fs::write(&state_path, state().to_string()).unwrap();
+    std::env::set_var("STEAMWIG_FAKE_AUDIO_STATE", &state_path);
+    let adc_modifier: ADCModifier = fake_adc_modifier();
+
+    assert_eq!(adc_modifier.get_system_state().unwrap(), AudioState::from_parts(SPEAKERS.to_string(), Some(49), Some(false)));
+    assert_eq!(adc_modifier.get_device_ids().unwrap(), vec![SPEAKERS, TV]);
+    let devices: Vec<AudioDevice> = adc_modifier.get_devices().unwrap();
+    assert_eq!(devices.len(), 3);
+    assert_eq!(devices[2].device_type(), AudioDeviceType::Recording);
+
+    let tv_state: AudioState = AudioState::from_parts(TV.to_string(), Some(80), Some(true));
+    assert!(adc_modifier.check_and_modify(&tv_state).unwrap());
+    assert_eq!(adc_modifier.get_system_state().unwrap(), tv_state);
+    assert!(!adc_modifier.check_and_modify(&tv_state).unwrap());
+
+    // The recording default is left alone
+    let (_, devices) = adc_modifier.get_state_and_devices().unwrap();
+    assert!(devices.iter().any(|d| d.id() == MICROPHONE && d.default()));
+    assert!(devices.iter().any(|d| d.id() == TV && d.default()));
+    assert!(!devices.iter().any(|d| d.id() == SPEAKERS && d.default()));
+
+    assert!(matches!(
+        adc_modifier.set_primary_device("{0.0.0.00000000}.{00000000-0000-0000-0000-000000000000}"),
+        Err(steamwig::Error::PowershellSessionError(_)),
+    ));
+
+    // Requests run as commands of their own are recorded, then replayed
+    let desk_state: AudioState = AudioState::from_parts(SPEAKERS.to_string(), Some(30), Some(false));
+    let recording_runner: Arc<RecordingRunner<ProcessRunner>> = Arc::new(RecordingRunner::new(ProcessRunner::new()));
+    let mut recorded_modifier: ADCModifier = fake_adc_modifier();
+    recorded_modifier.with_runner(recording_runner.clone());
+    assert!(recorded_modifier.check_and_modify(&desk_state).unwrap());
+    assert_eq!(adc_modifier.get_system_state().unwrap(), desk_state);
+    assert_eq!(recording_runner.records().len(), 4);
+
+    let replay_runner: Arc<ReplayRunner> = Arc::new(ReplayRunner::new(recording_runner.records()));
+    let mut replayed_modifier: ADCModifier = fake_adc_modifier();
+    replayed_modifier.with_runner(replay_runner.clone());
+    assert!(replayed_modifier.check_and_modify(&desk_state).unwrap());
+    assert!(replay_runner.remaining().is_empty());
+}
\ No newline at end of file
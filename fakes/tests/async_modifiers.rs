@@ -0,0 +1,63 @@
+//! Runs a task with `AsyncInnerSystemModifier` against fake-mmt and
+//! fake-powershell, through the async `MMTModifier` and `ADCModifier`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use steamwig::{ADCModifier, AsyncAudioModifier, AsyncDisplayModifier, AsyncInnerSystemModifier, AudioState, Blocking, DisplayState, MMTModifier, MockSteamModifier, SteamState, Task};
+
+static DESK: &str = "MONITOR\\GSM5B08\\{4d36e96e-e325-11ce-bfc1-08002be10318}\\0001";
+static TV: &str = "MONITOR\\GSM0001\\{4d36e96e-e325-11ce-bfc1-08002be10318}\\0002";
+static SPEAKERS: &str = "{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}";
+static TV_AUDIO: &str = "{0.0.0.00000000}.{8c2d9e4f-1a3b-4c5d-8e7f-6a5b4c3d2e1f}";
+
+fn mmt_state() -> serde_json::Value {
+    serde_json::json!({ "monitors": [
+        { "id": DESK, "name": "\\\\.\\DISPLAY1", "enabled": true, "primary": true, "x": 0, "y": 0, "width": 2560, "height": 1440 },
+        { "id": TV, "name": "\\\\.\\DISPLAY2", "enabled": false, "primary": false, "x": 0, "y": 0, "width": 3840, "height": 2160 },
+    ] })
+}
+
+fn audio_state() -> serde_json::Value {
+    serde_json::json!({ "volume": 48.5, "muted": false, "devices": [
+        { "index": 1, "default": true, "type": "Playback", "name": "Speakers (Realtek High Definition Audio)", "id": SPEAKERS },
+        { "index": 2, "default": false, "type": "Playback", "name": "LG TV (NVIDIA High Definition Audio)", "id": TV_AUDIO },
+    ] })
+}
+
+// A single test, as the fakes read their state files from the environment
+#[tokio::test]
+async fn runs_task_against_fakes() {
+    let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mmt_state_path: PathBuf = dir.path().join("mmt.json");
+    let audio_state_path: PathBuf = dir.path().join("audio.json");
+    fs::write(&mmt_state_path, mmt_state().to_string()).unwrap();
+    fs::write(&audio_state_path, audio_state().to_string()).unwrap();
+    std::env::set_var("STEAMWIG_FAKE_MMT_STATE", &mmt_state_path);
+    std::env::set_var("STEAMWIG_FAKE_AUDIO_STATE", &audio_state_path);
+    let mut system_modifier = AsyncInnerSystemModifier::new(
+        MMTModifier::new(PathBuf::from(env!("CARGO_BIN_EXE_fake-mmt"))),
+        ADCModifier::with_powershell_path(PathBuf::from("AudioDeviceCmdlets.dll"), PathBuf::from(env!("CARGO_BIN_EXE_fake-powershell"))),
+        Blocking::new(MockSteamModifier::new(SteamState::NotRunning)),
+    );
+    system_modifier.with_sleep_interval(Duration::from_millis(10));
+
+    let mut task: Task = Task::new();
+    task.set_primary_display(TV.to_string());
+    task.enable_display(TV.to_string());
+    task.disable_display(DESK.to_string());
+    task.set_primary_audio(TV_AUDIO.to_string());
+    task.set_volume(80);
+    task.set_muted(true);
+    system_modifier.run(&task).await.unwrap();
+
+    let display_state: DisplayState = system_modifier.display_modifier().get_system_state().await.unwrap();
+    assert_eq!(display_state.primary_device_id(), TV);
+    assert_eq!(display_state.enabled_device_ids(), [TV]);
+    assert_eq!(display_state.disabled_device_ids(), [DESK]);
+    assert_eq!(
+        system_modifier.audio_modifier().get_system_state().await.unwrap(),
+        AudioState::from_parts(TV_AUDIO.to_string(), Some(80), Some(true)),
+    );
+}
\ No newline at end of file
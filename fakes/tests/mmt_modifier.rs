@@ -0,0 +1,79 @@
+//! Runs steamwig's `MMTModifier` against fake-mmt, reading back the monitors
+//! after each change.
+
+use std::fs;
+use std::path::PathBuf;
+
+use steamwig::{DisplayModifier, DisplayPosition, DisplayState, MMTModifier};
+
+static DESK: &str = "MONITOR\\GSM5B08\\{4d36e96e-e325-11ce-bfc1-08002be10318}\\0001";
+static TV: &str = "MONITOR\\GSM0001\\{4d36e96e-e325-11ce-bfc1-08002be10318}\\0002";
+static SIDE: &str = "MONITOR\\DEL41A8\\{4d36e96e-e325-11ce-bfc1-08002be10318}\\0003";
+
+fn state() -> serde_json::Value {
+    serde_json::json!({ "monitors": [
+        { "id": DESK, "name": "\\\\.\\DISPLAY1", "enabled": true, "primary": true, "x": 0, "y": 0, "width": 2560, "height": 1440 },
+        { "id": TV, "name": "\\\\.\\DISPLAY2", "enabled": false, "primary": false, "x": 0, "y": 0, "width": 3840, "height": 2160 },
+        { "id": SIDE, "name": "\\\\.\\DISPLAY3", "enabled": true, "primary": false, "x": 2560, "y": 0, "width": 1920, "height": 1080 },
+    ] })
+}
+
+fn display_state(primary: &str, enabled: &[&str], disabled: &[&str]) -> DisplayState {
+    DisplayState::from_device_ids(
+        primary.to_string(),
+        enabled.iter().map(|s| s.to_string()).collect(),
+        disabled.iter().map(|s| s.to_string()).collect(),
+    )
+}
+
+fn assert_displays(mmt_modifier: &MMTModifier, primary: &str, enabled: &[&str], disabled: &[&str]) {
+    let system_state: DisplayState = mmt_modifier.get_system_state().unwrap();
+    assert_eq!(system_state.primary_device_id(), primary);
+    assert_eq!(system_state.enabled_device_ids(), enabled);
+    assert_eq!(system_state.disabled_device_ids(), disabled);
+}
+
+// A single test, as the fake reads its state file from the environment
+#[test]
+fn modifies_and_reads_back_monitors() {
+    let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let state_path: PathBuf = dir.path().join("mmt.json");
+    fs::write(&state_path, state().to_string()).unwrap();
+    std::env::set_var("STEAMWIG_FAKE_MMT_STATE", &state_path);
+    let mmt_modifier: MMTModifier = MMTModifier::new(PathBuf::from(env!("CARGO_BIN_EXE_fake-mmt")));
+
+    assert_displays(&mmt_modifier, DESK, &[DESK, SIDE], &[TV]);
+    assert_eq!(mmt_modifier.get_device_ids().unwrap(), vec![DESK, SIDE, TV]);
+
+    // Switch to the TV alone
+    let tv_state: DisplayState = display_state(TV, &[TV], &[DESK, SIDE]);
+    assert!(mmt_modifier.check_and_modify(&tv_state).unwrap());
+    assert_displays(&mmt_modifier, TV, &[TV], &[DESK, SIDE]);
+    assert!(!mmt_modifier.check_and_modify(&tv_state).unwrap());
+
+    // Back to the desk, with the side monitor moved to its left
+    let layout_path: PathBuf = dir.path().join("tv.cfg");
+    mmt_modifier.save_layout(&layout_path).unwrap();
+    let mut desk_state: DisplayState = display_state(DESK, &[DESK, SIDE], &[TV]);
+    desk_state.with_positions(vec![DisplayPosition::new(SIDE.to_string(), -1920, 0)]);
+    assert!(mmt_modifier.check_and_modify(&desk_state).unwrap());
+    assert_displays(&mmt_modifier, DESK, &[DESK, SIDE], &[TV]);
+    // Monitors being enabled are moved on the next pass, once they have a position
+    assert!(mmt_modifier.check_and_modify(&desk_state).unwrap());
+    let system_state: DisplayState = mmt_modifier.get_system_state().unwrap();
+    assert_eq!(system_state.position(SIDE).map(|p| (p.x(), p.y())), Some((-1920, 0)));
+    assert_eq!(system_state.position(DESK).map(|p| (p.x(), p.y())), Some((0, 0)));
+    assert!(!mmt_modifier.check_and_modify(&desk_state).unwrap());
+
+    // The saved layout brings back the TV
+    let layout_state: DisplayState = mmt_modifier.read_layout(&layout_path).unwrap();
+    assert_eq!(layout_state.primary_device_id(), TV);
+    assert!(mmt_modifier.load_layout_observed(&layout_path, &layout_state, &system_state).unwrap());
+    assert_displays(&mmt_modifier, TV, &[TV], &[DESK, SIDE]);
+
+    // Unknown monitors fail with fake-mmt's message
+    match mmt_modifier.set_primary_device("\\\\.\\DISPLAY9") {
+        Err(steamwig::Error::CommandFailed(e)) => assert!(e.stderr().contains("no monitor")),
+        r => panic!("expected CommandFailed, got {r:?}"),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,69 @@
+//! Drives steamwig's `PowershellSession` and `ADCModifier` against fake-powershell.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use steamwig::{ADCModifier, AudioModifier, PowershellSession};
+
+static STATE: &str = r#"{ "volume": 50, "muted": false, "devices": [
+    { "index": 1, "default": true, "type": "Playback", "name": "Speakers", "id": "{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}" },
+    { "index": 2, "default": false, "type": "Playback", "name": "Bob's TV", "id": "{0.0.0.00000000}.{bob's tv}" },
+    { "index": 3, "default": false, "type": "Playback", "name": "John‘s AirPods", "id": "{0.0.0.00000000}.{john‘s airpods}" },
+    { "index": 4, "default": false, "type": "Playback", "name": "John’s AirPods", "id": "{0.0.0.00000000}.{john’s airpods}" },
+    { "index": 5, "default": false, "type": "Playback", "name": "John‚s AirPods", "id": "{0.0.0.00000000}.{john‚s airpods}" },
+    { "index": 6, "default": false, "type": "Playback", "name": "John‛s AirPods", "id": "{0.0.0.00000000}.{john‛s airpods}" }
+] }"#;
+
+fn fake_powershell() -> PathBuf {
+    PathBuf::from(env!("CARGO_BIN_EXE_fake-powershell"))
+}
+
+static SPEAKERS: &str = "{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}";
+
+// The fakes read their state file from the environment, which every test
+// here shares.
+fn state_file() -> tempfile::NamedTempFile {
+    let state_file: tempfile::NamedTempFile = tempfile::NamedTempFile::new().unwrap();
+    fs::write(state_file.path(), STATE).unwrap();
+    std::env::set_var("STEAMWIG_FAKE_AUDIO_STATE", state_file.path());
+    state_file
+}
+
+fn default_device(state_path: &Path) -> String {
+    let state: serde_json::Value = serde_json::from_str(&fs::read_to_string(state_path).unwrap()).unwrap();
+    state["devices"].as_array().unwrap().iter()
+        .find(|d| d["default"] == true)
+        .map(|d| d["id"].as_str().unwrap().to_string())
+        .unwrap()
+}
+
+#[test]
+fn session_runs_requests_and_escapes_ids() {
+    let state_file: tempfile::NamedTempFile = state_file();
+
+    let session: PowershellSession = PowershellSession::new(fake_powershell(), PathBuf::from("AudioDeviceCmdlets.dll"));
+    let list: String = session.run("Get-AudioDevice -List").unwrap();
+    assert!(list.contains("Name    : Bob's TV"));
+    assert!(matches!(session.run("Get-Nothing"), Err(steamwig::Error::PowershellSessionError(_))));
+    // The session survives a failed request
+    assert!(session.run("Get-AudioDevice -List").is_ok());
+
+    let adc_modifier: ADCModifier = ADCModifier::with_powershell_path(PathBuf::from("AudioDeviceCmdlets.dll"), fake_powershell());
+    adc_modifier.set_primary_device("{0.0.0.00000000}.{bob's tv}").unwrap();
+    assert_eq!(default_device(state_file.path()), "{0.0.0.00000000}.{bob's tv}");
+    adc_modifier.set_primary_device(SPEAKERS).unwrap();
+    assert_eq!(default_device(state_file.path()), SPEAKERS);
+    // PowerShell also ends single-quoted strings on typographic quotes
+    for quote in ['\u{2018}', '\u{2019}', '\u{201A}', '\u{201B}'] {
+        let id: String = format!("{{0.0.0.00000000}}.{{john{quote}s airpods}}");
+        adc_modifier.set_primary_device(&id).unwrap();
+        assert_eq!(default_device(state_file.path()), id);
+    }
+    adc_modifier.set_primary_device(SPEAKERS).unwrap();
+
+    assert!(matches!(
+        adc_modifier.set_primary_device("{0.0.0.00000000}.{bob's tv}'\nSet-AudioDevice -PlaybackMute $true"),
+        Err(steamwig::Error::PowershellSessionError(_)),
+    ));
+    assert_eq!(default_device(state_file.path()), SPEAKERS);
+}
\ No newline at end of file
@@ -1,11 +1,100 @@
+/// Errors returned by steamwig and its backends.
+///
+/// Custom backends report their own failures as `BackendError`. New variants
+/// may be added in minor versions, and removed ones are deprecated first.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
+    /// No longer returned: `ADCModifier` runs PowerShell through a `PowershellSession`,
+    /// which reports `PowershellSessionError`.
+    #[deprecated(note = "PowerShell failures are reported as `Error::PowershellSessionError`")]
     PowershellError(powershell_script::PsError),
     CommandError(std::io::Error),
+    CommandFailed(CommandFailedError),
+    PowershellSessionError(PowershellSessionError),
     TempDirError(std::io::Error),
     MMTParseError(MMTParseError),
     ADCParseError(ADCParseError),
     CsvError(csv::Error),
+    DisplaySafetyError(DisplaySafetyError),
+    DisplayRevertFailed(DisplayRevertError),
+    InvalidTask(Vec<crate::internal::task::TaskDiagnostic>),
+    BackendError(Box<dyn std::error::Error + Send + Sync>),
+    BackendUnavailable(&'static str),
+    XrandrParseError(XrandrParseError),
+    PactlParseError(PactlParseError),
+    ConfigReadError(std::io::Error),
+    ConfigParseError(toml::de::Error),
+    #[cfg(feature = "serde")]
+    TaskParseError(serde_json::Error),
+    LayoutIoError(std::io::Error),
+    MMTLayoutParseError(MMTLayoutParseError),
+    AssetIoError(std::io::Error),
+    ChecksumMismatch(ChecksumMismatchError),
+    Timeout(TimeoutError),
+    Cancelled,
+    UnexpectedCommand(UnexpectedCommandError),
+}
+
+#[allow(deprecated)]
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::PowershellError(e) => write!(f, "powershell failed: {e}"),
+            Error::CommandError(e) => write!(f, "could not run command: {e}"),
+            Error::CommandFailed(e) => e.fmt(f),
+            Error::PowershellSessionError(e) => e.fmt(f),
+            Error::TempDirError(e) => write!(f, "could not create temporary directory: {e}"),
+            Error::MMTParseError(e) => e.fmt(f),
+            Error::ADCParseError(e) => e.fmt(f),
+            Error::CsvError(e) => write!(f, "could not read csv: {e}"),
+            Error::DisplaySafetyError(e) => e.fmt(f),
+            Error::DisplayRevertFailed(e) => e.fmt(f),
+            Error::InvalidTask(diagnostics) => {
+                write!(f, "invalid task")?;
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    write!(f, "{} {diagnostic}", if i == 0 { ":" } else { ";" })?;
+                }
+                Ok(())
+            },
+            Error::BackendError(e) => write!(f, "backend failed: {e}"),
+            Error::BackendUnavailable(description) => description.fmt(f),
+            Error::XrandrParseError(e) => e.fmt(f),
+            Error::PactlParseError(e) => e.fmt(f),
+            Error::ConfigReadError(e) => write!(f, "could not read config: {e}"),
+            Error::ConfigParseError(e) => write!(f, "could not parse config: {e}"),
+            #[cfg(feature = "serde")]
+            Error::TaskParseError(e) => write!(f, "could not parse task: {e}"),
+            Error::LayoutIoError(e) => write!(f, "could not access layout: {e}"),
+            Error::MMTLayoutParseError(e) => e.fmt(f),
+            Error::AssetIoError(e) => write!(f, "could not access bundled tool: {e}"),
+            Error::ChecksumMismatch(e) => e.fmt(f),
+            Error::Timeout(e) => e.fmt(f),
+            Error::Cancelled => "cancelled".fmt(f),
+            Error::UnexpectedCommand(e) => e.fmt(f),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::PowershellError(e) => Some(e),
+            Error::CommandError(e) => Some(e),
+            Error::TempDirError(e) => Some(e),
+            Error::CsvError(e) => Some(e),
+            Error::DisplayRevertFailed(e) => e.run_error().map(|e| e as &(dyn std::error::Error + 'static)),
+            Error::BackendError(e) => Some(&**e),
+            Error::ConfigReadError(e) => Some(e),
+            Error::ConfigParseError(e) => Some(e),
+            #[cfg(feature = "serde")]
+            Error::TaskParseError(e) => Some(e),
+            Error::LayoutIoError(e) => Some(e),
+            Error::AssetIoError(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -16,9 +105,282 @@ pub struct MMTParseError {
     pub (crate) description: &'static str,
 }
 
+impl std::fmt::Display for MMTParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not parse MultiMonitorTool output in field {}: {}", self.index, self.description)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct MMTLayoutParseError {
+    pub (crate) output: String,
+    pub (crate) line: Option<usize>,
+    pub (crate) description: &'static str,
+}
+
+impl std::fmt::Display for MMTLayoutParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "could not parse MultiMonitorTool layout on line {line}: {}", self.description),
+            None => write!(f, "could not parse MultiMonitorTool layout: {}", self.description),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct ADCParseError {
+    pub (crate) output: String,
+    pub (crate) line: Option<usize>,
+    // Only for output which is not valid JSON
+    pub (crate) column: Option<usize>,
+    pub (crate) description: &'static str,
+}
+
+impl std::fmt::Display for ADCParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(
+                f,
+                "could not parse AudioDeviceCmdlets output on line {line}, column {column}: {}",
+                self.description,
+            ),
+            (Some(line), None) => write!(f, "could not parse AudioDeviceCmdlets output on line {line}: {}", self.description),
+            (None, _) => write!(f, "could not parse AudioDeviceCmdlets output: {}", self.description),
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct DisplaySafetyError {
+    pub (crate) primary_device_id: String,
+    pub (crate) enabled_device_ids: Vec<String>,
+    pub (crate) description: &'static str,
+}
+
+impl std::fmt::Display for DisplaySafetyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "refused display change which {}", self.description)
+    }
+}
+
+/// `run_with_confirm` could not restore the display state from before the run.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct DisplayRevertError {
+    pub (crate) previous_state: crate::internal::display::DisplayState,
+    pub (crate) run_error: Option<Box<Error>>,
+}
+
+impl DisplayRevertError {
+    /// The display state the revert was trying to restore.
+    pub fn previous_state(&self) -> &crate::internal::display::DisplayState {
+        &self.previous_state
+    }
+
+    /// The error which ended the run, if it failed before the revert.
+    pub fn run_error(&self) -> Option<&Error> {
+        self.run_error.as_deref()
+    }
+}
+
+impl std::fmt::Display for DisplayRevertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not restore the previous display state")
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct XrandrParseError {
+    pub (crate) output: String,
+    pub (crate) description: &'static str,
+}
+
+impl std::fmt::Display for XrandrParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not parse xrandr output: {}", self.description)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct PactlParseError {
+    pub (crate) output: String,
+    pub (crate) description: &'static str,
+}
+
+impl std::fmt::Display for PactlParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not parse pactl output: {}", self.description)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct PowershellSessionError {
+    pub (crate) script: String,
     pub (crate) output: String,
     pub (crate) description: &'static str,
+}
+
+impl std::fmt::Display for PowershellSessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "powershell {}: {}", self.description, self.script)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct CommandFailedError {
+    pub (crate) program: String,
+    pub (crate) args: Vec<String>,
+    pub (crate) status: std::process::ExitStatus,
+    pub (crate) stderr: String,
+}
+
+impl CommandFailedError {
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn status(&self) -> std::process::ExitStatus {
+        self.status
+    }
+
+    pub fn stderr(&self) -> &str {
+        &self.stderr
+    }
+}
+
+impl std::fmt::Display for CommandFailedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} exited with {}", self.program, self.args.join(" "), self.status)?;
+        if !self.stderr.trim().is_empty() {
+            write!(f, ": {}", self.stderr.trim())?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct ChecksumMismatchError {
+    pub (crate) path: std::path::PathBuf,
+    pub (crate) expected: String,
+    pub (crate) actual: String,
+}
+
+impl ChecksumMismatchError {
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// The expected SHA-256, in lowercase hex.
+    pub fn expected(&self) -> &str {
+        &self.expected
+    }
+
+    /// The SHA-256 of the file, in lowercase hex.
+    pub fn actual(&self) -> &str {
+        &self.actual
+    }
+}
+
+impl std::fmt::Display for ChecksumMismatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} has SHA-256 {}, expected {}", self.path.display(), self.actual, self.expected)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct TimeoutError {
+    pub (crate) operation: String,
+    pub (crate) timeout: std::time::Duration,
+}
+
+impl TimeoutError {
+    /// The command line or PowerShell script which timed out, or "run" for
+    /// a run which passed its deadline.
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        self.timeout
+    }
+}
+
+impl std::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} did not finish within {:?}", self.operation, self.timeout)
+    }
+}
+
+/// A `ReplayRunner` was asked to run a command other than the next one recorded.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct UnexpectedCommandError {
+    pub (crate) expected: Option<Box<crate::internal::command_runner::CommandSpec>>,
+    pub (crate) actual: Box<crate::internal::command_runner::CommandSpec>,
+}
+
+impl UnexpectedCommandError {
+    /// The next recorded command, or `None` if every recording was replayed.
+    pub fn expected(&self) -> Option<&crate::internal::command_runner::CommandSpec> {
+        self.expected.as_deref()
+    }
+
+    pub fn actual(&self) -> &crate::internal::command_runner::CommandSpec {
+        &self.actual
+    }
+}
+
+impl std::fmt::Display for UnexpectedCommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.expected {
+            Some(expected) => write!(f, "expected command {}, got {}", expected.command_line(), self.actual.command_line()),
+            None => write!(f, "unexpected command {} after every recording was replayed", self.actual.command_line()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::error::Error as _;
+
+    #[test]
+    fn displays_nested_errors() {
+        let error: Error = Error::Timeout(TimeoutError {
+            operation: "xrandr --query".to_string(),
+            timeout: std::time::Duration::from_secs(2),
+        });
+        assert_eq!(error.to_string(), "xrandr --query did not finish within 2s");
+        let error: Error = Error::InvalidTask(vec![
+            crate::internal::task::TaskDiagnostic::VolumeOutOfRange(101),
+            crate::internal::task::TaskDiagnostic::UnknownDisplay("X".to_string()),
+        ]);
+        assert_eq!(error.to_string(), "invalid task: volume 101 is greater than 100; display X does not match any display");
+    }
+
+    #[test]
+    fn exposes_sources() {
+        let error: Error = Error::ConfigReadError(std::io::Error::new(std::io::ErrorKind::NotFound, "missing"));
+        assert_eq!(error.source().unwrap().to_string(), "missing");
+        assert!(Error::Cancelled.source().is_none());
+        let error: Error = Error::DisplayRevertFailed(DisplayRevertError {
+            previous_state: crate::internal::display::DisplayState::new(),
+            run_error: Some(Box::new(Error::Cancelled)),
+        });
+        assert_eq!(error.source().unwrap().to_string(), "cancelled");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,72 @@
+use super::config::cache_dir;
+use super::checksum::verify_sha256;
+
+use std::path::PathBuf;
+use std::fs;
+use std::io;
+
+static ASSETS_DIR_NAME: &str = "assets";
+
+/// A third-party tool bundled with steamwig, embedded by build.rs.
+#[derive(Debug)]
+pub (crate) struct Asset {
+    pub (crate) file_name: &'static str,
+    pub (crate) bytes: &'static [u8],
+    pub (crate) sha256: &'static str,
+}
+
+pub (crate) static MMT: Asset = Asset {
+    file_name: "MultiMonitorTool.exe",
+    bytes: include_bytes!(concat!(env!("OUT_DIR"), "/MultiMonitorTool.exe")),
+    sha256: env!("STEAMWIG_MMT_SHA256"),
+};
+
+pub (crate) static ADC: Asset = Asset {
+    file_name: "AudioDeviceCmdlets.dll",
+    bytes: include_bytes!(concat!(env!("OUT_DIR"), "/AudioDeviceCmdlets.dll")),
+    sha256: env!("STEAMWIG_ADC_SHA256"),
+};
+
+// Looks for the asset in an assets directory next to the executable, where
+// build.rs puts them, then in the cache directory. Failing both, the embedded
+// copy is extracted into the cache directory.
+pub (crate) fn asset_path(asset: &Asset) -> Result<PathBuf, crate::Error> {
+    if let Some(path) = exe_assets_dir().map(|p| p.join(asset.file_name)) {
+        if path.is_file() {
+            return Ok(path)
+        }
+    }
+    let cache_assets_dir: PathBuf = match cache_dir() {
+        Some(p) => p.join(ASSETS_DIR_NAME),
+        None => return Err(crate::Error::AssetIoError(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no cache directory to extract assets to",
+        ))),
+    };
+    let path: PathBuf = cache_assets_dir.join(asset.file_name);
+    if path.is_file() && verify_sha256(&path, asset.sha256).is_ok() {
+        return Ok(path)
+    }
+    tracing::debug!(path = %path.display(), "extracting bundled asset");
+    fs::create_dir_all(&cache_assets_dir).map_err(|e| crate::Error::AssetIoError(e))?;
+    fs::write(&path, asset.bytes).map_err(|e| crate::Error::AssetIoError(e))?;
+    verify_sha256(&path, asset.sha256)?;
+    Ok(path)
+}
+
+/// Like `asset_path`, but falls back to the path next to the executable, so
+/// that a missing asset is reported when the backend first runs it.
+pub (crate) fn asset_path_or_default(asset: &Asset) -> PathBuf {
+    match asset_path(asset) {
+        Ok(p) => p,
+        Err(e) => {
+            tracing::warn!(error = ?e, asset = asset.file_name, "bundled asset unavailable");
+            exe_assets_dir().unwrap_or_else(|| PathBuf::from(ASSETS_DIR_NAME)).join(asset.file_name)
+        },
+    }
+}
+
+fn exe_assets_dir() -> Option<PathBuf> {
+    let exe_path: PathBuf = std::env::current_exe().ok()?;
+    exe_path.parent().map(|p| p.join(ASSETS_DIR_NAME))
+}
\ No newline at end of file
@@ -0,0 +1,362 @@
+use super::display::{DisplayState, DisplayPosition, DisplayModifier, DisplayModifierObjectives, DisplayAction};
+use super::audio::{AudioState, AudioModifier, AudioModifierObjectives};
+use super::steam::{SteamState, SteamStatus, SteamOptions, SteamModifier, SteamAction};
+use super::steam::{SHUTDOWN_POLL_INTERVAL_MILLIS, DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS};
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time;
+
+/// The async counterpart of `DisplayModifier`, for `AsyncInnerSystemModifier`.
+///
+/// Native implementations run their commands with `CommandRunner::run_async`,
+/// and `ProcessRunner` kills them when the future is dropped. Wrap other
+/// backends in `Blocking`.
+pub trait AsyncDisplayModifier: Send + Sync {
+    fn get_system_state(&self) -> impl Future<Output = Result<DisplayState, crate::Error>> + Send;
+
+    fn enable_monitors(&self, device_ids: &[&str]) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn disable_monitors(&self, device_ids: &[&str]) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn set_primary_device(&self, id: &str) -> impl Future<Output = Result<(), crate::Error>> + Send;
+
+    /// Moves enabled displays. Backends which cannot position displays keep this default.
+    fn set_positions(&self, _positions: &[DisplayPosition]) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        async { Err(crate::Error::BackendUnavailable("display backend cannot set positions")) }
+    }
+
+    /// Applies a display configuration saved by `DisplayModifier::save_layout`.
+    fn load_layout(&self, _path: &Path) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        async { Err(crate::Error::BackendUnavailable("display backend cannot load layouts")) }
+    }
+
+    /// The display state a saved layout describes.
+    fn read_layout(&self, _path: &Path) -> impl Future<Output = Result<DisplayState, crate::Error>> + Send {
+        async { Err(crate::Error::BackendUnavailable("display backend cannot read layouts")) }
+    }
+
+    /// Loads the layout at `path`, which describes `layout_state`, unless
+    /// `system_state` already matches it. Returns true if it was loaded.
+    fn load_layout_observed(
+        &self,
+        path: &Path,
+        layout_state: &DisplayState,
+        system_state: &DisplayState,
+    ) -> impl Future<Output = Result<bool, crate::Error>> + Send {
+        async move {
+            tracing::debug!(observed = ?system_state, layout = ?layout_state, path = %path.display(), "display layout");
+            if super::display::get_objectives(layout_state, system_state).is_none() {
+                return Ok(false)
+            }
+            self.load_layout(path).await?;
+            Ok(true)
+        }
+    }
+
+    /// Returns true if any action was issued, false if the system state already matches.
+    fn check_and_modify(&self, desired_state: &DisplayState) -> impl Future<Output = Result<bool, crate::Error>> + Send {
+        async move {
+            if desired_state.is_empty() {
+                return Ok(false)
+            }
+            let system_state: DisplayState = self.get_system_state().await?;
+            self.modify_observed(desired_state, &system_state).await
+        }
+    }
+
+    /// Issues the actions needed to bring `system_state` to `desired_state`.
+    /// Returns true if any action was issued.
+    fn modify_observed(
+        &self,
+        desired_state: &DisplayState,
+        system_state: &DisplayState,
+    ) -> impl Future<Output = Result<bool, crate::Error>> + Send {
+        async move {
+            let objectives: DisplayModifierObjectives = match super::display::plan(desired_state, system_state)? {
+                Some(o) => o,
+                None => return Ok(false),
+            };
+            for action in objectives.actions() {
+                match action {
+                    DisplayAction::EnableMonitors(device_ids) => self.enable_monitors(device_ids).await?,
+                    DisplayAction::DisableMonitors(device_ids) => self.disable_monitors(device_ids).await?,
+                    DisplayAction::SetPrimaryDevice(id) => self.set_primary_device(id).await?,
+                    DisplayAction::SetPositions(positions) => self.set_positions(positions).await?,
+                }
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// The async counterpart of `AudioModifier`, for `AsyncInnerSystemModifier`.
+pub trait AsyncAudioModifier: Send + Sync {
+    fn get_system_state(&self) -> impl Future<Output = Result<AudioState, crate::Error>> + Send;
+
+    fn set_primary_device(&self, id: &str) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn set_volume(&self, volume: u8) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn set_muted(&self, muted: bool) -> impl Future<Output = Result<(), crate::Error>> + Send;
+
+    /// Returns true if any action was issued, false if the system state already matches.
+    fn check_and_modify(&self, desired_state: &AudioState) -> impl Future<Output = Result<bool, crate::Error>> + Send {
+        async move {
+            if desired_state.is_empty() {
+                return Ok(false)
+            }
+            let system_state: AudioState = self.get_system_state().await?;
+            self.modify_observed(desired_state, &system_state).await
+        }
+    }
+
+    /// Issues the actions needed to bring `system_state` to `desired_state`.
+    /// Returns true if any action was issued.
+    fn modify_observed(
+        &self,
+        desired_state: &AudioState,
+        system_state: &AudioState,
+    ) -> impl Future<Output = Result<bool, crate::Error>> + Send {
+        async move {
+            tracing::debug!(observed = ?system_state, desired = ?desired_state, "audio state");
+            let objectives: AudioModifierObjectives = match super::audio::get_objectives(desired_state, system_state) {
+                Some(o) => o,
+                None => return Ok(false),
+            };
+            if !objectives.primary_device_id.is_empty() {
+                self.set_primary_device(objectives.primary_device_id).await?;
+            }
+            if let Some(volume) = objectives.volume {
+                self.set_volume(volume).await?;
+            }
+            if let Some(muted) = objectives.muted {
+                self.set_muted(muted).await?;
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// The async counterpart of `SteamModifier`, for `AsyncInnerSystemModifier`.
+pub trait AsyncSteamModifier: Send + Sync {
+    fn get_system_state(&self) -> impl Future<Output = Result<SteamState, crate::Error>> + Send;
+    fn get_running_app(&self) -> impl Future<Output = Result<Option<u32>, crate::Error>> + Send;
+
+    fn request_shutdown(&self) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn kill_steam(&self) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn launch_steam(&self, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn launch_big_picture(&self, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn launch_app(&self, app_id: u32, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn enable_big_picture(&self, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send;
+    fn disable_big_picture(&self, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send;
+
+    /// The Steam state and running app. The running app is only queried while Steam runs.
+    fn get_status(&self) -> impl Future<Output = Result<SteamStatus, crate::Error>> + Send {
+        async move {
+            let state: SteamState = self.get_system_state().await?;
+            let running_app: Option<u32> = match state {
+                SteamState::NotRunning => None,
+                _ => self.get_running_app().await?,
+            };
+            Ok(SteamStatus::new(state, running_app))
+        }
+    }
+
+    /// Asks Steam to exit, waiting up to `grace_period` before killing it.
+    fn shutdown_steam(&self, grace_period: Option<time::Duration>) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        async move {
+            let grace_period: time::Duration = grace_period
+                .unwrap_or(time::Duration::from_secs(DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS));
+            self.request_shutdown().await?;
+            let start: time::Instant = time::Instant::now();
+            while start.elapsed() < grace_period {
+                if self.get_system_state().await? == SteamState::NotRunning {
+                    return Ok(())
+                }
+                tokio::time::sleep(time::Duration::from_millis(SHUTDOWN_POLL_INTERVAL_MILLIS)).await;
+            }
+            self.kill_steam().await
+        }
+    }
+
+    /// Returns true if any action was issued, false if the system state already matches.
+    fn check_and_modify(
+        &self,
+        desired_state: &SteamState,
+        options: &SteamOptions,
+    ) -> impl Future<Output = Result<bool, crate::Error>> + Send {
+        async move {
+            if desired_state.is_empty() && options.app_id.is_none() {
+                return Ok(false)
+            }
+            let system_status: SteamStatus = self.get_status().await?;
+            self.modify_observed(desired_state, options, &system_status).await
+        }
+    }
+
+    /// Issues the actions needed to bring `system_status` to `desired_state`
+    /// running `options.app_id`. Returns true if any action was issued.
+    fn modify_observed(
+        &self,
+        desired_state: &SteamState,
+        options: &SteamOptions,
+        system_status: &SteamStatus,
+    ) -> impl Future<Output = Result<bool, crate::Error>> + Send {
+        async move {
+            let action: SteamAction = match super::steam::plan(desired_state, options, system_status) {
+                Some(a) => a,
+                None => return Ok(false),
+            };
+            match action {
+                SteamAction::Shutdown => self.shutdown_steam(options.shutdown_grace_period).await?,
+                SteamAction::LaunchSteam => self.launch_steam(&options.args).await?,
+                SteamAction::LaunchBigPicture => self.launch_big_picture(&options.args).await?,
+                SteamAction::EnableBigPicture => self.enable_big_picture(&options.args).await?,
+                SteamAction::DisableBigPicture => self.disable_big_picture(&options.args).await?,
+                SteamAction::LaunchApp(app_id) => self.launch_app(app_id, &options.args).await?,
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Runs a blocking backend on tokio's blocking thread pool, for backends
+/// without an async implementation, such as `U32Modifier` and the mocks.
+///
+/// Dropping the future does not stop a call already running on the pool.
+/// Give the backend a `ProcessRunner` with a timeout to bound its commands.
+#[derive(Debug)]
+pub struct Blocking<T> {
+    inner: Arc<T>,
+}
+
+impl<T> Blocking<T> {
+    pub fn new(inner: T) -> Self {
+        Blocking { inner: Arc::new(inner) }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+// Calls `f` with the backend on the blocking pool, resuming its panics
+async fn call_blocking<T, R, F>(inner: &Arc<T>, f: F) -> Result<R, crate::Error>
+where
+    T: Send + Sync + 'static,
+    R: Send + 'static,
+    F: FnOnce(&T) -> Result<R, crate::Error> + Send + 'static,
+{
+    let inner: Arc<T> = inner.clone();
+    match tokio::task::spawn_blocking(move || f(&inner)).await {
+        Ok(result) => result,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(e) => Err(crate::Error::BackendError(Box::new(e))),
+        },
+    }
+}
+
+fn owned(strs: &[&str]) -> Vec<String> {
+    strs.iter().map(|s| s.to_string()).collect()
+}
+
+impl<T: DisplayModifier + 'static> AsyncDisplayModifier for Blocking<T> {
+    fn get_system_state(&self) -> impl Future<Output = Result<DisplayState, crate::Error>> + Send {
+        call_blocking(&self.inner, |m| m.get_system_state())
+    }
+
+    fn enable_monitors(&self, device_ids: &[&str]) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let device_ids: Vec<String> = owned(device_ids);
+        call_blocking(&self.inner, move |m| m.enable_monitors(&device_ids.iter().map(|s| s.as_str()).collect::<Vec<&str>>()))
+    }
+
+    fn disable_monitors(&self, device_ids: &[&str]) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let device_ids: Vec<String> = owned(device_ids);
+        call_blocking(&self.inner, move |m| m.disable_monitors(&device_ids.iter().map(|s| s.as_str()).collect::<Vec<&str>>()))
+    }
+
+    fn set_primary_device(&self, id: &str) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let id: String = id.to_string();
+        call_blocking(&self.inner, move |m| m.set_primary_device(&id))
+    }
+
+    fn set_positions(&self, positions: &[DisplayPosition]) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let positions: Vec<DisplayPosition> = positions.to_vec();
+        call_blocking(&self.inner, move |m| m.set_positions(&positions))
+    }
+
+    fn load_layout(&self, path: &Path) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let path: PathBuf = path.to_path_buf();
+        call_blocking(&self.inner, move |m| m.load_layout(&path))
+    }
+
+    fn read_layout(&self, path: &Path) -> impl Future<Output = Result<DisplayState, crate::Error>> + Send {
+        let path: PathBuf = path.to_path_buf();
+        call_blocking(&self.inner, move |m| m.read_layout(&path))
+    }
+}
+
+impl<T: AudioModifier + 'static> AsyncAudioModifier for Blocking<T> {
+    fn get_system_state(&self) -> impl Future<Output = Result<AudioState, crate::Error>> + Send {
+        call_blocking(&self.inner, |m| m.get_system_state())
+    }
+
+    fn set_primary_device(&self, id: &str) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let id: String = id.to_string();
+        call_blocking(&self.inner, move |m| m.set_primary_device(&id))
+    }
+
+    fn set_volume(&self, volume: u8) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        call_blocking(&self.inner, move |m| m.set_volume(volume))
+    }
+
+    fn set_muted(&self, muted: bool) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        call_blocking(&self.inner, move |m| m.set_muted(muted))
+    }
+}
+
+impl<T: SteamModifier + 'static> AsyncSteamModifier for Blocking<T> {
+    fn get_system_state(&self) -> impl Future<Output = Result<SteamState, crate::Error>> + Send {
+        call_blocking(&self.inner, |m| m.get_system_state())
+    }
+
+    fn get_running_app(&self) -> impl Future<Output = Result<Option<u32>, crate::Error>> + Send {
+        call_blocking(&self.inner, |m| m.get_running_app())
+    }
+
+    fn get_status(&self) -> impl Future<Output = Result<SteamStatus, crate::Error>> + Send {
+        call_blocking(&self.inner, |m| m.get_status())
+    }
+
+    fn request_shutdown(&self) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        call_blocking(&self.inner, |m| m.request_shutdown())
+    }
+
+    fn kill_steam(&self) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        call_blocking(&self.inner, |m| m.kill_steam())
+    }
+
+    fn launch_steam(&self, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let args: Vec<String> = args.to_vec();
+        call_blocking(&self.inner, move |m| m.launch_steam(&args))
+    }
+
+    fn launch_big_picture(&self, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let args: Vec<String> = args.to_vec();
+        call_blocking(&self.inner, move |m| m.launch_big_picture(&args))
+    }
+
+    fn launch_app(&self, app_id: u32, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let args: Vec<String> = args.to_vec();
+        call_blocking(&self.inner, move |m| m.launch_app(app_id, &args))
+    }
+
+    fn enable_big_picture(&self, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let args: Vec<String> = args.to_vec();
+        call_blocking(&self.inner, move |m| m.enable_big_picture(&args))
+    }
+
+    fn disable_big_picture(&self, args: &[String]) -> impl Future<Output = Result<(), crate::Error>> + Send {
+        let args: Vec<String> = args.to_vec();
+        call_blocking(&self.inner, move |m| m.disable_big_picture(&args))
+    }
+}
\ No newline at end of file
@@ -0,0 +1,235 @@
+use super::display::DisplayState;
+use super::audio::AudioState;
+use super::steam::{SteamState, SteamOptions};
+use super::async_modifier::{AsyncDisplayModifier, AsyncAudioModifier, AsyncSteamModifier};
+use super::system_modifier_inner::{ObservedState, layout_file, DEFAULT_MAX_ATTEMPTS, DEFAULT_SLEEP_INTERVAL_SECS};
+use super::task::{Task, TaskDiagnostic};
+use super::observer::{RunObserver, RunEvent, Subsystem, Convergence};
+use crate::error::TimeoutError;
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time;
+
+use tracing::Instrument;
+
+/// Runs tasks against async display, audio and Steam backends, on tokio.
+///
+/// `run` is cancelled by dropping its future, which kills any command a
+/// native backend is running. Calls on `Blocking` backends run to completion
+/// on the blocking pool.
+#[derive(Debug, Clone)]
+pub struct AsyncInnerSystemModifier<D, A, S> {
+    pub (crate) display_modifier: D,
+    pub (crate) audio_modifier: A,
+    pub (crate) steam_modifier: S,
+    pub (crate) max_attempts: usize,
+    pub (crate) sleep_interval: time::Duration,
+    pub (crate) run_timeout: Option<time::Duration>,
+    pub (crate) observer: Option<Arc<dyn RunObserver>>,
+}
+
+impl<D, A, S> AsyncInnerSystemModifier<D, A, S>
+where
+    D: AsyncDisplayModifier,
+    A: AsyncAudioModifier,
+    S: AsyncSteamModifier,
+{
+    pub fn new(display_modifier: D, audio_modifier: A, steam_modifier: S) -> AsyncInnerSystemModifier<D, A, S> {
+        AsyncInnerSystemModifier {
+            display_modifier: display_modifier,
+            audio_modifier: audio_modifier,
+            steam_modifier: steam_modifier,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            sleep_interval: time::Duration::from_secs(DEFAULT_SLEEP_INTERVAL_SECS),
+            run_timeout: None,
+            observer: None,
+        }
+    }
+
+    pub fn with_max_attempts(&mut self, max_attempts: usize) {
+        self.max_attempts = max_attempts
+    }
+
+    pub fn with_sleep_interval(&mut self, sleep_interval: time::Duration) {
+        self.sleep_interval = sleep_interval
+    }
+
+    /// Fails `run` with `Error::Timeout` if the system state has not matched
+    /// the task after `run_timeout`.
+    pub fn with_run_timeout(&mut self, run_timeout: time::Duration) {
+        self.run_timeout = Some(run_timeout)
+    }
+
+    /// Reports the progress of runs to `observer`, from the task polling `run`.
+    pub fn with_observer(&mut self, observer: Arc<dyn RunObserver>) {
+        self.observer = Some(observer)
+    }
+
+    pub fn finalize(self) -> AsyncInnerSystemModifier<D, A, S> {
+        self
+    }
+
+    pub fn display_modifier(&self) -> &D {
+        &self.display_modifier
+    }
+
+    pub fn audio_modifier(&self) -> &A {
+        &self.audio_modifier
+    }
+
+    pub fn steam_modifier(&self) -> &S {
+        &self.steam_modifier
+    }
+
+    /// Runs the task until the system state matches it, or `max_attempts` is reached.
+    /// Returns false if the system state never matched.
+    ///
+    /// Tasks with contradictions are refused with `Error::InvalidTask`.
+    pub async fn run(&self, task: &Task) -> Result<bool, crate::Error> {
+        let diagnostics: Vec<TaskDiagnostic> = task.check_consistency();
+        let result: Result<bool, crate::Error> = match (diagnostics.is_empty(), self.run_timeout) {
+            (false, _) => Err(crate::Error::InvalidTask(diagnostics)),
+            (true, Some(timeout)) => match tokio::time::timeout(timeout, self.run_attempts(task)).await {
+                Ok(result) => result,
+                Err(_) => Err(crate::Error::Timeout(TimeoutError {
+                    operation: "run".to_string(),
+                    timeout: timeout,
+                })),
+            },
+            (true, None) => self.run_attempts(task).await,
+        };
+        self.emit(RunEvent::RunFinished { result: result.as_ref().map(|b| *b).map_err(|e| format!("{e:?}")) });
+        result
+    }
+
+    async fn run_attempts(&self, task: &Task) -> Result<bool, crate::Error> {
+        let mut convergence: Convergence = Convergence::default();
+        for attempt in 0..self.max_attempts {
+            let span: tracing::Span = tracing::info_span!("attempt", attempt = attempt + 1);
+            self.emit(RunEvent::AttemptStarted { attempt: attempt + 1, max_attempts: self.max_attempts });
+            let continue_run: bool = self.check_and_modify(task, &mut convergence).instrument(span).await?;
+            if !continue_run {
+                return Ok(true)
+            }
+            tokio::time::sleep(self.sleep_interval).await;
+        }
+        Ok(false)
+    }
+
+    // As in `InnerSystemModifier`, the subsystems are queried concurrently,
+    // then modified one at a time, displays first.
+    async fn check_and_modify(&self, task: &Task, convergence: &mut Convergence) -> Result<bool, crate::Error> {
+        let audio_state: &AudioState = &task.audio_state;
+        let steam_state: &SteamState = &task.steam_state;
+        let steam_options: &SteamOptions = &task.steam_options;
+        let layout: Option<(PathBuf, DisplayState)> = match &task.display_layout {
+            Some(name) => {
+                let path: PathBuf = layout_file(name)?;
+                let layout_state: DisplayState = self.display_modifier.read_layout(&path).await?;
+                Some((path, layout_state))
+            },
+            None => None,
+        };
+        let observed: ObservedState = self.observe(
+            layout.is_some() || !task.display_state.is_empty(),
+            !audio_state.is_empty(),
+            !steam_state.is_empty() || steam_options.app_id.is_some(),
+        ).await?;
+        self.emit(RunEvent::StateObserved {
+            display: observed.display.clone(),
+            audio: observed.audio.clone(),
+            steam: observed.steam,
+        });
+
+        let start: time::Instant = time::Instant::now();
+        let display_result: bool = match (&layout, &observed.display) {
+            (Some((path, layout_state)), Some(s)) => self.modified(
+                convergence,
+                Subsystem::Display,
+                self.display_modifier.load_layout_observed(path, layout_state, s).await,
+            )?,
+            (None, Some(s)) => self.modified(
+                convergence,
+                Subsystem::Display,
+                self.display_modifier.modify_observed(&task.display_state, s).await,
+            )?,
+            (_, None) => false,
+        };
+        let display_ms: u64 = start.elapsed().as_millis() as u64;
+        let audio_result: bool = match &observed.audio {
+            Some(s) => self.modified(convergence, Subsystem::Audio, self.audio_modifier.modify_observed(audio_state, s).await)?,
+            None => false,
+        };
+        let audio_ms: u64 = start.elapsed().as_millis() as u64 - display_ms;
+        let steam_result: bool = match &observed.steam {
+            Some(s) => self.modified(
+                convergence,
+                Subsystem::Steam,
+                self.steam_modifier.modify_observed(steam_state, steam_options, s).await,
+            )?,
+            None => false,
+        };
+        let steam_ms: u64 = start.elapsed().as_millis() as u64 - display_ms - audio_ms;
+        tracing::debug!(display_ms, audio_ms, steam_ms, "modified system state");
+
+        match (display_result, audio_result, steam_result) {
+            (false, false, false) => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    fn emit(&self, event: RunEvent) {
+        if let Some(observer) = &self.observer {
+            observer.on_event(&event)
+        }
+    }
+
+    // Reports the result of modifying a subsystem, and passes it on
+    fn modified(
+        &self,
+        convergence: &mut Convergence,
+        subsystem: Subsystem,
+        result: Result<bool, crate::Error>,
+    ) -> Result<bool, crate::Error> {
+        if let Some(event) = convergence.event(subsystem, &result) {
+            self.emit(event)
+        }
+        result
+    }
+
+    async fn observe(&self, query_display: bool, query_audio: bool, query_steam: bool) -> Result<ObservedState, crate::Error> {
+        let start: time::Instant = time::Instant::now();
+        let (display_query, audio_query, steam_query) = tokio::join!(
+            timed_query(query_display, self.display_modifier.get_system_state()),
+            timed_query(query_audio, self.audio_modifier.get_system_state()),
+            timed_query(query_steam, self.steam_modifier.get_status()),
+        );
+        tracing::debug!(
+            display_ms = display_query.as_ref().map(|(_, ms)| *ms),
+            audio_ms = audio_query.as_ref().map(|(_, ms)| *ms),
+            steam_ms = steam_query.as_ref().map(|(_, ms)| *ms),
+            total_ms = start.elapsed().as_millis() as u64,
+            "queried system state",
+        );
+        Ok(ObservedState {
+            display: display_query.map(|(r, _)| r).transpose()?,
+            audio: audio_query.map(|(r, _)| r).transpose()?,
+            steam: steam_query.map(|(r, _)| r).transpose()?,
+        })
+    }
+}
+
+// Awaits `query` if it is enabled, timing it in milliseconds
+async fn timed_query<T>(
+    enabled: bool,
+    query: impl Future<Output = Result<T, crate::Error>>,
+) -> Option<(Result<T, crate::Error>, u64)> {
+    if !enabled {
+        return None
+    }
+    let start: time::Instant = time::Instant::now();
+    let result: Result<T, crate::Error> = query.await;
+    Some((result, start.elapsed().as_millis() as u64))
+}
\ No newline at end of file
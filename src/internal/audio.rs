@@ -1,4 +1,9 @@
+/// The default playback device, its volume and whether it is muted.
+///
+/// As a desired state, empty fields are left as they are on the system.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default = "AudioState::new"))]
 pub struct AudioState {
     pub (crate) primary_device_id: String,
     pub (crate) volume: Option<u8>,
@@ -14,8 +19,30 @@ impl AudioState {
         }
     }
 
+    /// Used by backends to report the system state.
+    pub fn from_parts(primary_device_id: String, volume: Option<u8>, muted: Option<bool>) -> AudioState {
+        AudioState {
+            primary_device_id: primary_device_id,
+            volume: volume,
+            muted: muted,
+        }
+    }
+
+    /// Empty if the default device is unknown or unspecified.
+    pub fn primary_device_id(&self) -> &str {
+        &self.primary_device_id
+    }
+
+    pub fn volume(&self) -> Option<u8> {
+        self.volume
+    }
+
+    pub fn muted(&self) -> Option<bool> {
+        self.muted
+    }
+
     pub fn is_empty(&self) -> bool {
-        if self.primary_device_id.is_empty() && self.volume == None && self.muted == None {
+        if self.primary_device_id.is_empty() && self.volume.is_none() && self.muted.is_none() {
             true
         } else {
             false 
@@ -23,57 +50,154 @@ impl AudioState {
     }
 }
 
+/// An audio device as listed by a backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioDevice {
+    pub (crate) index: u32,
+    pub (crate) default: bool,
+    pub (crate) device_type: AudioDeviceType,
+    pub (crate) name: String,
+    pub (crate) id: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AudioDeviceType {
+    Playback,
+    Recording,
+}
+
+impl AudioDevice {
+    pub fn new(index: u32, default: bool, device_type: AudioDeviceType, name: String, id: String) -> AudioDevice {
+        AudioDevice {
+            index: index,
+            default: default,
+            device_type: device_type,
+            name: name,
+            id: id,
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn default(&self) -> bool {
+        self.default
+    }
+
+    pub fn device_type(&self) -> AudioDeviceType {
+        self.device_type
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
 #[derive(Debug)]
-struct AudioModifierObjectives<'a> {
-    primary_device_id: &'a str,
-    volume: Option<u8>,
-    muted: Option<bool>,
+pub (crate) struct AudioModifierObjectives<'a> {
+    pub (crate) primary_device_id: &'a str,
+    pub (crate) volume: Option<u8>,
+    pub (crate) muted: Option<bool>,
 }
 
-pub trait AudioModifier {
+/// An audio backend.
+///
+/// Implementors provide the queries and actions; `check_and_modify` compares
+/// the desired state with the system state and issues only the actions needed.
+/// Backends are shared across threads, so that subsystems can be queried concurrently.
+pub trait AudioModifier: Send + Sync {
     fn get_id_readout(&self) -> Result<String, crate::Error>;
 
     fn get_system_state(&self) -> Result<AudioState, crate::Error>;
 
+    fn get_device_ids(&self) -> Result<Vec<String>, crate::Error>;
+
     fn set_primary_device(&self, id: &str) -> Result<(), crate::Error>;
     fn set_volume(&self, volume: u8) -> Result<(), crate::Error>;
     fn set_muted(&self, muted: bool) -> Result<(), crate::Error>;
 
+    /// Returns true if any action was issued, false if the system state already matches.
     fn check_and_modify(&self, desired_state: &AudioState) -> Result<bool, crate::Error> {
         if desired_state.is_empty() {
             return Ok(false)
         }
         let system_state: AudioState = self.get_system_state()?;
-        let objectives: AudioModifierObjectives = match get_objectives(&desired_state, &system_state) {
+        self.modify_observed(desired_state, &system_state)
+    }
+
+    /// Issues the actions needed to bring `system_state`, as queried by
+    /// `get_system_state`, to `desired_state`. Returns true if any action was issued.
+    fn modify_observed(&self, desired_state: &AudioState, system_state: &AudioState) -> Result<bool, crate::Error> {
+        tracing::debug!(observed = ?system_state, desired = ?desired_state, "audio state");
+        let objectives: AudioModifierObjectives = match get_objectives(desired_state, system_state) {
             Some(o) => o,
             None => return Ok(false),
         };
         if !objectives.primary_device_id.is_empty() {
-            self.set_primary_device(&objectives.primary_device_id)?;
+            self.set_primary_device(objectives.primary_device_id)?;
         }
-        if !objectives.volume.is_none() {
-            self.set_volume(objectives.volume.unwrap())?;
+        if let Some(volume) = objectives.volume {
+            self.set_volume(volume)?;
         }
-        if !objectives.muted.is_none() {
-            self.set_muted(objectives.muted.unwrap())?;
+        if let Some(muted) = objectives.muted {
+            self.set_muted(muted)?;
         }
         Ok(true)
     }
 }
 
-fn get_objectives<'a>(
+impl<T: AudioModifier + ?Sized> AudioModifier for Box<T> {
+    fn get_id_readout(&self) -> Result<String, crate::Error> {
+        (**self).get_id_readout()
+    }
+
+    fn get_system_state(&self) -> Result<AudioState, crate::Error> {
+        (**self).get_system_state()
+    }
+
+    fn get_device_ids(&self) -> Result<Vec<String>, crate::Error> {
+        (**self).get_device_ids()
+    }
+
+    fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        (**self).set_primary_device(id)
+    }
+
+    fn set_volume(&self, volume: u8) -> Result<(), crate::Error> {
+        (**self).set_volume(volume)
+    }
+
+    fn set_muted(&self, muted: bool) -> Result<(), crate::Error> {
+        (**self).set_muted(muted)
+    }
+
+    fn check_and_modify(&self, desired_state: &AudioState) -> Result<bool, crate::Error> {
+        (**self).check_and_modify(desired_state)
+    }
+
+    fn modify_observed(&self, desired_state: &AudioState, system_state: &AudioState) -> Result<bool, crate::Error> {
+        (**self).modify_observed(desired_state, system_state)
+    }
+}
+
+pub (crate) fn get_objectives<'a>(
     desired_state: &'a AudioState,
     system_state: &AudioState,
 ) -> Option<AudioModifierObjectives<'a>> {
-    let primary_device_id: &str = match &desired_state.primary_device_id == &system_state.primary_device_id {
+    let primary_device_id: &str = match desired_state.primary_device_id == system_state.primary_device_id {
         true => "",
         false => &desired_state.primary_device_id,
     };
-    let volume: Option<u8> = match (&desired_state.volume == &system_state.volume, primary_device_id.is_empty()) {
+    let volume: Option<u8> = match (desired_state.volume == system_state.volume, primary_device_id.is_empty()) {
         (true, true) => None,
         _ => desired_state.volume,
     };
-    let muted: Option<bool> = match (&desired_state.muted == &system_state.muted, primary_device_id.is_empty()) {
+    let muted: Option<bool> = match (desired_state.muted == system_state.muted, primary_device_id.is_empty()) {
         (true, true) => None,
         _ => desired_state.muted,
     };
@@ -86,4 +210,20 @@ fn get_objectives<'a>(
             muted: muted,
         })
     }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_serde() {
+        let audio_state: AudioState = AudioState::from_parts("tv".to_string(), Some(60), Some(false));
+        let json: serde_json::Value = serde_json::to_value(&audio_state).unwrap();
+        assert_eq!(json, serde_json::json!({ "primary_device_id": "tv", "volume": 60, "muted": false }));
+        assert_eq!(serde_json::from_value::<AudioState>(json).unwrap(), audio_state);
+        // Missing fields are left as they are on the system
+        assert_eq!(serde_json::from_str::<AudioState>(r#"{"volume": 20}"#).unwrap(), AudioState::from_parts(String::new(), Some(20), None));
+        assert!(serde_json::from_str::<AudioState>(r#"{"volume": 300}"#).is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,264 @@
+use super::audio::{AudioState, AudioModifier};
+use super::command::{traced_command, ExitCodes};
+#[cfg(feature = "async")]
+use super::command::traced_command_async;
+use super::command_runner::{CommandRunner, CommandSpec, ProcessRunner};
+use crate::error::PactlParseError;
+
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Audio backend driving pactl. Device IDs are sink names.
+#[derive(Debug)]
+pub struct PactlModifier {
+    pub (crate) exe_path: PathBuf,
+    pub (crate) runner: Arc<dyn CommandRunner>,
+}
+
+impl AudioModifier for PactlModifier {
+    fn get_id_readout(&self) -> Result<String, crate::Error> {
+        let output: process::Output = pactl_run(&*self.runner, &self.exe_path, &["list", "short", "sinks"])?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn get_system_state(&self) -> Result<AudioState, crate::Error> {
+        Ok(AudioState {
+            primary_device_id: get_primary_device(&*self.runner, &self.exe_path)?,
+            volume: Some(get_volume(&*self.runner, &self.exe_path)?),
+            muted: Some(get_muted(&*self.runner, &self.exe_path)?),
+        })
+    }
+
+    fn get_device_ids(&self) -> Result<Vec<String>, crate::Error> {
+        get_device_ids(&*self.runner, &self.exe_path)
+    }
+
+    fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        pactl_run(&*self.runner, &self.exe_path, &["set-default-sink", id]).map(|_| ())
+    }
+
+    fn set_volume(&self, volume: u8) -> Result<(), crate::Error> {
+        pactl_run(&*self.runner, &self.exe_path, &["set-sink-volume", "@DEFAULT_SINK@", &format!("{volume}%")]).map(|_| ())
+    }
+
+    fn set_muted(&self, muted: bool) -> Result<(), crate::Error> {
+        let muted_str: &str = match muted {
+            true => "1",
+            false => "0",
+        };
+        pactl_run(&*self.runner, &self.exe_path, &["set-sink-mute", "@DEFAULT_SINK@", muted_str]).map(|_| ())
+    }
+}
+
+#[cfg(feature = "async")]
+impl super::async_modifier::AsyncAudioModifier for PactlModifier {
+    async fn get_system_state(&self) -> Result<AudioState, crate::Error> {
+        let (primary_device_id, volume, muted) = tokio::try_join!(
+            pactl_run_async(&*self.runner, &self.exe_path, &["get-default-sink"]),
+            pactl_run_async(&*self.runner, &self.exe_path, &["get-sink-volume", "@DEFAULT_SINK@"]),
+            pactl_run_async(&*self.runner, &self.exe_path, &["get-sink-mute", "@DEFAULT_SINK@"]),
+        )?;
+        Ok(AudioState {
+            primary_device_id: parse_primary_device(&primary_device_id)?,
+            volume: Some(parse_volume(&volume)?),
+            muted: Some(parse_muted(&muted)?),
+        })
+    }
+
+    async fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        pactl_run_async(&*self.runner, &self.exe_path, &["set-default-sink", id]).await.map(|_| ())
+    }
+
+    async fn set_volume(&self, volume: u8) -> Result<(), crate::Error> {
+        pactl_run_async(&*self.runner, &self.exe_path, &["set-sink-volume", "@DEFAULT_SINK@", &format!("{volume}%")]).await.map(|_| ())
+    }
+
+    async fn set_muted(&self, muted: bool) -> Result<(), crate::Error> {
+        let muted_str: &str = match muted {
+            true => "1",
+            false => "0",
+        };
+        pactl_run_async(&*self.runner, &self.exe_path, &["set-sink-mute", "@DEFAULT_SINK@", muted_str]).await.map(|_| ())
+    }
+}
+
+impl PactlModifier {
+    pub fn new(exe_path: std::path::PathBuf) -> Self {
+        PactlModifier { exe_path: exe_path, runner: Arc::new(ProcessRunner::new()) }
+    }
+
+    pub fn with_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.runner = runner
+    }
+}
+
+fn pactl_run(runner: &dyn CommandRunner, exe_path: &Path, args: &[&str]) -> Result<process::Output, crate::Error> {
+    traced_command(runner, &CommandSpec::new(exe_path.to_path_buf(), args), ExitCodes::Zero)
+}
+
+#[cfg(feature = "async")]
+async fn pactl_run_async(runner: &dyn CommandRunner, exe_path: &Path, args: &[&str]) -> Result<process::Output, crate::Error> {
+    traced_command_async(runner, &CommandSpec::new(exe_path.to_path_buf(), args), ExitCodes::Zero).await
+}
+
+fn get_primary_device(runner: &dyn CommandRunner, exe_path: &Path) -> Result<String, crate::Error> {
+    let output: process::Output = pactl_run(runner, exe_path, &["get-default-sink"])?;
+    parse_primary_device(&output)
+}
+
+fn parse_primary_device(output: &process::Output) -> Result<String, crate::Error> {
+    let stdout_str: &str = &String::from_utf8_lossy(&output.stdout);
+    match stdout_str.trim() {
+        "" => Err(crate::Error::PactlParseError(PactlParseError {
+            output: stdout_str.to_string(),
+            description: "should contain a sink name",
+        })),
+        device_id => Ok(device_id.to_string()),
+    }
+}
+
+fn get_device_ids(runner: &dyn CommandRunner, exe_path: &Path) -> Result<Vec<String>, crate::Error> {
+    let output: process::Output = pactl_run(runner, exe_path, &["list", "short", "sinks"])?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .map(|device_id| device_id.to_string())
+        .collect())
+}
+
+fn get_volume(runner: &dyn CommandRunner, exe_path: &Path) -> Result<u8, crate::Error> {
+    let output: process::Output = pactl_run(runner, exe_path, &["get-sink-volume", "@DEFAULT_SINK@"])?;
+    parse_volume(&output)
+}
+
+// The first channel's volume, e.g. "Volume: front-left: 65536 /  100% / 0.00 dB, ..."
+fn parse_volume(output: &process::Output) -> Result<u8, crate::Error> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"/\s*(\d{1,3})%").unwrap();
+    }
+    let stdout_str: &str = &String::from_utf8_lossy(&output.stdout);
+    match RE.captures(stdout_str).map(|c| c[1].parse::<u8>()) {
+        Some(Ok(volume)) => Ok(volume),
+        _ => Err(crate::Error::PactlParseError(PactlParseError {
+            output: stdout_str.to_string(),
+            description: "should contain a volume percentage",
+        })),
+    }
+}
+
+fn get_muted(runner: &dyn CommandRunner, exe_path: &Path) -> Result<bool, crate::Error> {
+    let output: process::Output = pactl_run(runner, exe_path, &["get-sink-mute", "@DEFAULT_SINK@"])?;
+    parse_muted(&output)
+}
+
+fn parse_muted(output: &process::Output) -> Result<bool, crate::Error> {
+    let stdout_str: &str = &String::from_utf8_lossy(&output.stdout);
+    match stdout_str.trim() {
+        "Mute: yes" => Ok(true),
+        "Mute: no" => Ok(false),
+        _ => Err(crate::Error::PactlParseError(PactlParseError {
+            output: stdout_str.to_string(),
+            description: "should contain 'Mute: yes' or 'Mute: no'",
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::command_runner::{ReplayRunner, CommandRecord, CommandOutcome};
+
+    fn pactl(args: &[&str], stdout: &str) -> CommandRecord {
+        CommandRecord {
+            command: CommandSpec::new(PathBuf::from("pactl"), args),
+            outcome: CommandOutcome::Exited { exit_code: 0, stdout: stdout.as_bytes().to_vec(), stderr: Vec::new() },
+        }
+    }
+
+    fn state_queries(volume: &str, mute: &str) -> Vec<CommandRecord> {
+        vec![
+            pactl(&["get-default-sink"], "alsa_output.pci-0000_00_1f.3.analog-stereo\n"),
+            pactl(&["get-sink-volume", "@DEFAULT_SINK@"], volume),
+            pactl(&["get-sink-mute", "@DEFAULT_SINK@"], mute),
+        ]
+    }
+
+    fn pactl_replay(records: Vec<CommandRecord>) -> (PactlModifier, Arc<ReplayRunner>) {
+        let runner: Arc<ReplayRunner> = Arc::new(ReplayRunner::new(records));
+        let mut pactl_modifier: PactlModifier = PactlModifier::new(PathBuf::from("pactl"));
+        pactl_modifier.with_runner(runner.clone());
+        (pactl_modifier, runner)
+    }
+
+    static VOLUME: &str = "Volume: front-left: 42598 /  65% / -11.23 dB,   front-right: 42598 /  65% / -11.23 dB\n        balance 0.00\n";
+
+    #[test]
+    fn reads_default_sink_state() {
+        let (pactl_modifier, runner) = pactl_replay(state_queries(VOLUME, "Mute: no\n"));
+        assert_eq!(
+            pactl_modifier.get_system_state().unwrap(),
+            AudioState::from_parts("alsa_output.pci-0000_00_1f.3.analog-stereo".to_string(), Some(65), Some(false)),
+        );
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn refuses_unparseable_volume_and_mute() {
+        let (pactl_modifier, _) = pactl_replay(state_queries("Volume: n/a\n", "Mute: no\n"));
+        assert!(matches!(pactl_modifier.get_system_state(), Err(crate::Error::PactlParseError(_))));
+        let (pactl_modifier, _) = pactl_replay(state_queries(VOLUME, "Mute: maybe\n"));
+        assert!(matches!(pactl_modifier.get_system_state(), Err(crate::Error::PactlParseError(_))));
+    }
+
+    #[test]
+    fn lists_sink_names() {
+        let (pactl_modifier, _) = pactl_replay(vec![pactl(
+            &["list", "short", "sinks"],
+            "0\talsa_output.hdmi-stereo\tmodule-alsa-card.c\ts16le 2ch 44100Hz\tSUSPENDED\n\
+             1\talsa_output.analog-stereo\tmodule-alsa-card.c\ts16le 2ch 44100Hz\tRUNNING\n",
+        )]);
+        assert_eq!(pactl_modifier.get_device_ids().unwrap(), vec!["alsa_output.hdmi-stereo", "alsa_output.analog-stereo"]);
+    }
+
+    #[test]
+    fn applies_desired_state() {
+        let mut records: Vec<CommandRecord> = state_queries(VOLUME, "Mute: yes\n");
+        records.push(pactl(&["set-default-sink", "alsa_output.hdmi-stereo"], ""));
+        records.push(pactl(&["set-sink-volume", "@DEFAULT_SINK@", "65%"], ""));
+        records.push(pactl(&["set-sink-mute", "@DEFAULT_SINK@", "0"], ""));
+        let (pactl_modifier, runner) = pactl_replay(records);
+        let desired_state: AudioState = AudioState::from_parts("alsa_output.hdmi-stereo".to_string(), Some(65), Some(false));
+        assert!(pactl_modifier.check_and_modify(&desired_state).unwrap());
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn sets_volume_as_percentage() {
+        let mut records: Vec<CommandRecord> = state_queries(VOLUME, "Mute: no\n");
+        records.push(pactl(&["set-sink-volume", "@DEFAULT_SINK@", "30%"], ""));
+        let (pactl_modifier, runner) = pactl_replay(records);
+        assert!(pactl_modifier.check_and_modify(&AudioState::from_parts(String::new(), Some(30), None)).unwrap());
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn applies_desired_state_async() {
+        use crate::internal::async_modifier::AsyncAudioModifier;
+        let mut records: Vec<CommandRecord> = state_queries(VOLUME, "Mute: yes\n");
+        records.push(pactl(&["set-sink-volume", "@DEFAULT_SINK@", "40%"], ""));
+        records.push(pactl(&["set-sink-mute", "@DEFAULT_SINK@", "0"], ""));
+        let (pactl_modifier, runner) = pactl_replay(records);
+        let desired_state: AudioState = AudioState::from_parts(
+            "alsa_output.pci-0000_00_1f.3.analog-stereo".to_string(),
+            Some(40),
+            Some(false),
+        );
+        assert!(AsyncAudioModifier::check_and_modify(&pactl_modifier, &desired_state).await.unwrap());
+        assert!(runner.remaining().is_empty());
+    }
+}
\ No newline at end of file
@@ -1,122 +1,564 @@
-use super::audio::{AudioState, AudioModifier};
+use super::audio::{AudioState, AudioModifier, AudioDevice, AudioDeviceType};
+use super::powershell_session::{PowershellSession, quote};
+use super::cancellation::CancellationToken;
+use super::command_runner::CommandRunner;
 use crate::error::ADCParseError;
 
-use std::path::{Path, PathBuf};
-use std::process;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time;
 
 use lazy_static::lazy_static;
-use powershell_script;
 use regex::Regex;
+use serde::Deserialize;
 
+/// Audio backend driving the AudioDeviceCmdlets PowerShell module.
 #[derive(Debug)]
 pub struct ADCModifier {
-    pub (crate) module_path: PathBuf,
+    pub (crate) session: PowershellSession,
 }
 
 impl AudioModifier for ADCModifier {
     fn get_id_readout(&self) -> Result<String, crate::Error> {
-        unimplemented!()
+        let mut readout: String = String::new();
+        for device in parse_device_list(&self.session.run("Get-AudioDevice -List")?)? {
+            readout.push_str(&format!(
+                "{:?} default: {} name: {} id: {}\n",
+                device.device_type,
+                device.default,
+                device.name,
+                device.id,
+            ));
+        }
+        Ok(readout)
     }
 
     fn get_system_state(&self) -> Result<AudioState, crate::Error> {
-        Ok(AudioState {
-            primary_device_id: get_primary_device(&self.module_path)?,
-            volume: Some(get_volume(&self.module_path)?),
-            muted: Some(get_muted(&self.module_path)?),
-        })
+        get_state(&self.session).map(|(audio_state, _)| audio_state)
+    }
+
+    fn get_device_ids(&self) -> Result<Vec<String>, crate::Error> {
+        Ok(self.get_devices()?
+            .into_iter()
+            .filter(|device| device.device_type == AudioDeviceType::Playback)
+            .map(|device| device.id)
+            .collect())
     }
 
     fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
-        set_primary_device(&self.module_path, id)
+        set_primary_device(&self.session, id)
     }
 
     fn set_volume(&self, volume: u8) -> Result<(), crate::Error> {
-        set_volume(&self.module_path, volume)
+        set_volume(&self.session, volume)
     }
     
     fn set_muted(&self, muted: bool) -> Result<(), crate::Error> {
-        set_muted(&self.module_path, muted)
+        set_muted(&self.session, muted)
+    }
+}
+
+// Each request runs as a PowerShell command of its own, rather than in the
+// long-lived session. See `PowershellSession::run_async`.
+#[cfg(feature = "async")]
+impl super::async_modifier::AsyncAudioModifier for ADCModifier {
+    async fn get_system_state(&self) -> Result<AudioState, crate::Error> {
+        parse_state_json(&self.session.run_async(STATE_SCRIPT).await?).map(|(audio_state, _)| audio_state)
+    }
+
+    async fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        self.session.run_async(&set_primary_device_script(id)?).await.map(|_| ())
+    }
+
+    async fn set_volume(&self, volume: u8) -> Result<(), crate::Error> {
+        self.session.run_async(&set_volume_script(volume)).await.map(|_| ())
+    }
+
+    async fn set_muted(&self, muted: bool) -> Result<(), crate::Error> {
+        self.session.run_async(&set_muted_script(muted)).await.map(|_| ())
     }
 }
 
 impl ADCModifier {
     pub fn new(module_path: std::path::PathBuf) -> Self {
-        ADCModifier { module_path: module_path }
+        ADCModifier { session: PowershellSession::new(PowershellSession::default_program(), module_path) }
     }
-}
 
-fn powershell_run(module_path: &Path, args: &[&str]) -> Result<process::Output, crate::Error> {
-    let mut script: String = format!("Import-Module {};", module_path.to_string_lossy());
-    for arg in args {
-        script.push(' ');
-        script.push_str(arg);
+    /// Every playback and recording device, parsed from `Get-AudioDevice -List`.
+    pub fn get_devices(&self) -> Result<Vec<AudioDevice>, crate::Error> {
+        parse_device_list(&self.session.run("Get-AudioDevice -List")?)
     }
-    match powershell_script::run(&script) {
-        Ok(output) => Ok(output.into_inner()),
-        Err(e) => Err(crate::Error::PowershellError(e)),
+
+    /// The system state and every device, from a single query.
+    pub fn get_state_and_devices(&self) -> Result<(AudioState, Vec<AudioDevice>), crate::Error> {
+        get_state(&self.session)
     }
-}
 
-fn get_primary_device(module_path: &Path) -> Result<String, crate::Error> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(
-            r"\{\d\.\d\.\d\.(\d{8})\}\.\{([a-z]|\d){8}-([a-z]|\d){4}-([a-z]|\d){4}-([a-z]|\d){4}-([a-z]|\d){12}\}",
-        ).unwrap();
+    /// Uses another PowerShell executable, e.g. `pwsh` on Windows.
+    pub fn with_powershell_path(module_path: PathBuf, powershell_path: PathBuf) -> Self {
+        ADCModifier { session: PowershellSession::new(powershell_path, module_path) }
     }
-    let output: process::Output = powershell_run(module_path, &["Get-AudioDevice", "-Playback"])?;
-    let stdout_str: &str = &String::from_utf8_lossy(&output.stdout);
-    match RE.find(stdout_str).map(|m| m.as_str()) {
-        Some(device_id) => Ok(device_id.to_string()),
-        _ => Err(crate::Error::ADCParseError(ADCParseError {
-            output: String::from_utf8_lossy(&output.stdout).to_string(),
-            description: "should contain a device ID string 55 characters long",
-        })),
+
+    /// Refuses to import AudioDeviceCmdlets unless its SHA-256, in hex, is
+    /// `sha256`. The module is checked every time PowerShell is started.
+    pub fn with_sha256(&mut self, sha256: String) {
+        self.session.with_module_sha256(sha256)
+    }
+
+    /// Runs each request with `runner` as a PowerShell command of its own,
+    /// rather than in a long-lived PowerShell process. See `PowershellSession::with_runner`.
+    pub fn with_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.session.with_runner(runner)
+    }
+
+    /// Kills PowerShell if a request has not finished after `timeout`.
+    pub fn with_command_timeout(&mut self, timeout: time::Duration) {
+        self.session.with_timeout(timeout)
+    }
+
+    /// Kills PowerShell once `token` is cancelled, or once the deadline of
+    /// the run using it passes.
+    pub fn with_cancellation(&mut self, token: CancellationToken) {
+        self.session.with_cancellation(token)
     }
 }
 
-fn get_volume(module_path: &Path) -> Result<u8, crate::Error> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"(100)|(\d{1,2})").unwrap();
+// Emits the default playback device, its volume and mute, and every device,
+// as a single line of JSON. The volume is read through a double so that
+// decimal commas in localized output parse, e.g. "48,5%".
+static STATE_SCRIPT: &str = concat!(
+    "$d = Get-AudioDevice -Playback; ",
+    "[pscustomobject]@{ ",
+    "Default = $d.ID; ",
+    "Volume = [double]((Get-AudioDevice -PlaybackVolume) -replace '[^0-9.,]', '' -replace ',', '.'); ",
+    "Muted = [bool](Get-AudioDevice -PlaybackMute); ",
+    "Devices = @(Get-AudioDevice -List | ForEach-Object { [pscustomobject]@{ ",
+    "Index = $_.Index; Default = $_.Default; Type = \"$($_.Type)\"; Name = $_.Name; ID = $_.ID ",
+    "} }) ",
+    "} | ConvertTo-Json -Compress -Depth 3",
+);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StateJson {
+    default: Option<String>,
+    volume: f64,
+    muted: bool,
+    devices: Vec<DeviceJson>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeviceJson {
+    index: u32,
+    default: bool,
+    #[serde(rename = "Type")]
+    device_type: String,
+    name: String,
+    #[serde(rename = "ID")]
+    id: String,
+}
+
+fn get_state(session: &PowershellSession) -> Result<(AudioState, Vec<AudioDevice>), crate::Error> {
+    parse_state_json(&session.run(STATE_SCRIPT)?)
+}
+
+fn parse_state_json(stdout_str: &str) -> Result<(AudioState, Vec<AudioDevice>), crate::Error> {
+    let state_json: StateJson = match serde_json::from_str(stdout_str.trim_start_matches('\u{feff}')) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::debug!(error = %e, "audio state JSON did not parse");
+            return Err(crate::Error::ADCParseError(ADCParseError {
+                output: stdout_str.to_string(),
+                line: Some(e.line()),
+                column: Some(e.column()),
+                description: "should be a JSON object with Default, Volume, Muted and Devices",
+            }))
+        },
+    };
+    if !(0.0..=100.0).contains(&state_json.volume) {
+        return Err(state_json_error(stdout_str, "Volume should be a number from 0 to 100"))
+    }
+    // Null if there is no default playback device
+    let default: String = state_json.default.unwrap_or_default();
+    if !default.is_empty() && !DEVICE_ID_RE.is_match(&default) {
+        return Err(state_json_error(stdout_str, "Default should be a device ID like {0.0.0.00000000}.{guid}"))
     }
-    let output: process::Output = powershell_run(module_path, &["Get-AudioDevice", "-PlaybackVolume"])?;
-    let stdout_str: &str = &String::from_utf8_lossy(&output.stdout);
-    match RE.find(stdout_str).map(|m| m.as_str().parse::<u8>()) {
-        Some(Ok(volume)) => Ok(volume),
-        _ => Err(crate::Error::ADCParseError(ADCParseError {
-            output: String::from_utf8_lossy(&output.stdout).to_string(),
-            description: "should contain an integer from 0 to 100",
-        })),
+    let mut devices: Vec<AudioDevice> = Vec::with_capacity(state_json.devices.len());
+    for device_json in state_json.devices {
+        let device_type: AudioDeviceType = parse_device_type(&device_json.device_type)
+            .ok_or_else(|| state_json_error(stdout_str, "device Type should be Playback or Recording"))?;
+        if !DEVICE_ID_RE.is_match(&device_json.id) {
+            return Err(state_json_error(stdout_str, "device ID should be a device ID like {0.0.0.00000000}.{guid}"))
+        }
+        devices.push(AudioDevice {
+            index: device_json.index,
+            default: device_json.default,
+            device_type: device_type,
+            name: device_json.name,
+            id: device_json.id,
+        });
     }
+    let audio_state: AudioState = AudioState {
+        primary_device_id: default,
+        volume: Some(state_json.volume.round() as u8),
+        muted: Some(state_json.muted),
+    };
+    Ok((audio_state, devices))
+}
+
+fn state_json_error(stdout_str: &str, description: &'static str) -> crate::Error {
+    crate::Error::ADCParseError(ADCParseError {
+        output: stdout_str.to_string(),
+        line: None,
+        column: None,
+        description: description,
+    })
 }
 
-fn get_muted(module_path: &Path) -> Result<bool, crate::Error> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"(True)|(False)").unwrap();
+fn parse_device_type(type_str: &str) -> Option<AudioDeviceType> {
+    match type_str {
+        "Playback" => Some(AudioDeviceType::Playback),
+        "Recording" => Some(AudioDeviceType::Recording),
+        _ => None,
     }
-    let output: process::Output = powershell_run(module_path, &["Get-AudioDevice", "-PlaybackMute"])?;
-    let stdout_str: &str = &String::from_utf8_lossy(&output.stdout);
-    match RE.find(stdout_str).map(|m| m.as_str()) {
-        Some("True") => Ok(true),
-        Some("False") => Ok(false),
-        _ => Err(crate::Error::ADCParseError(ADCParseError {
-            output: String::from_utf8_lossy(&output.stdout).to_string(),
-            description: "should contain 'True' or 'False'",
-        })),
+}
+
+lazy_static! {
+    static ref DEVICE_ID_RE: Regex = Regex::new(
+        r"^\{\d\.\d\.\d\.[0-9a-fA-F]{8}\}\.\{[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\}$",
+    ).unwrap();
+}
+
+// Parses the list format of `Get-AudioDevice -List`, one block per device:
+//
+// Index   : 1
+// Default : True
+// Type    : Playback
+// Name    : Speakers (Realtek High Definition Audio)
+// ID      : {0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}
+// Device  : CoreAudioApi.MMDevice
+//
+// Unknown properties are ignored, and indented lines continue a wrapped value.
+fn parse_device_list(stdout_str: &str) -> Result<Vec<AudioDevice>, crate::Error> {
+    let mut devices: Vec<AudioDevice> = Vec::new();
+    let mut block: Vec<(usize, String, String)> = Vec::new();
+    for (i, line) in stdout_str.lines().chain(std::iter::once("")).enumerate() {
+        let line_number: usize = i + 1;
+        if line.trim().is_empty() {
+            if !block.is_empty() {
+                devices.push(parse_device_block(stdout_str, &block)?);
+                block.clear();
+            }
+            continue
+        }
+        if line.starts_with(char::is_whitespace) {
+            match block.last_mut() {
+                Some((_, _, value)) => {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                },
+                None => return Err(device_list_error(stdout_str, line_number, "continuation line should follow a property")),
+            }
+            continue
+        }
+        match line.split_once(':') {
+            Some((key, value)) => block.push((line_number, key.trim().to_string(), value.trim().to_string())),
+            None => return Err(device_list_error(stdout_str, line_number, "should be a 'Name : Value' property")),
+        }
     }
+    Ok(devices)
+}
+
+fn parse_device_block(stdout_str: &str, block: &[(usize, String, String)]) -> Result<AudioDevice, crate::Error> {
+    let first_line: usize = block[0].0;
+    let property = |key: &'static str| -> Result<(usize, &str), crate::Error> {
+        match block.iter().find(|(_, k, _)| k == key) {
+            Some((line_number, _, value)) => Ok((*line_number, value.as_str())),
+            None => Err(device_list_error(stdout_str, first_line, "device should have Index, Default, Type, Name and ID")),
+        }
+    };
+    let (index_line_number, index_str) = property("Index")?;
+    let index: u32 = index_str.parse::<u32>()
+        .map_err(|_| device_list_error(stdout_str, index_line_number, "Index should be a non-negative integer"))?;
+    let default: bool = match property("Default")? {
+        (_, "True") => true,
+        (_, "False") => false,
+        (line_number, _) => return Err(device_list_error(stdout_str, line_number, "Default should be True or False")),
+    };
+    let (type_line_number, type_str) = property("Type")?;
+    let device_type: AudioDeviceType = parse_device_type(type_str)
+        .ok_or_else(|| device_list_error(stdout_str, type_line_number, "Type should be Playback or Recording"))?;
+    let name: String = property("Name")?.1.to_string();
+    let id: String = match property("ID")? {
+        (_, value) if DEVICE_ID_RE.is_match(value) => value.to_string(),
+        (line_number, _) => return Err(device_list_error(stdout_str, line_number, "ID should be a device ID like {0.0.0.00000000}.{guid}")),
+    };
+    Ok(AudioDevice {
+        index: index,
+        default: default,
+        device_type: device_type,
+        name: name,
+        id: id,
+    })
 }
 
-fn set_primary_device(module_path: &Path, id: &str) -> Result<(), crate::Error> {
-    powershell_run(module_path, &["Set-AudioDevice", "-ID", id]).map(|_| ())
+fn device_list_error(stdout_str: &str, line_number: usize, description: &'static str) -> crate::Error {
+    crate::Error::ADCParseError(ADCParseError {
+        output: stdout_str.to_string(),
+        line: Some(line_number),
+        column: None,
+        description: description,
+    })
 }
 
-fn set_volume(module_path: &Path, volume: u8) -> Result<(), crate::Error> {
-    powershell_run(module_path, &["Set-AudioDevice", "-ID", &volume.to_string()]).map(|_| ())
+fn set_primary_device(session: &PowershellSession, id: &str) -> Result<(), crate::Error> {
+    session.run(&set_primary_device_script(id)?).map(|_| ())
 }
 
-fn set_muted(module_path: &Path, muted: bool) -> Result<(), crate::Error> {
+fn set_volume(session: &PowershellSession, volume: u8) -> Result<(), crate::Error> {
+    session.run(&set_volume_script(volume)).map(|_| ())
+}
+
+fn set_muted(session: &PowershellSession, muted: bool) -> Result<(), crate::Error> {
+    session.run(&set_muted_script(muted)).map(|_| ())
+}
+
+fn set_primary_device_script(id: &str) -> Result<String, crate::Error> {
+    Ok(format!("Set-AudioDevice -ID {}", quote(id)?))
+}
+
+fn set_volume_script(volume: u8) -> String {
+    format!("Set-AudioDevice -PlaybackVolume {volume}")
+}
+
+fn set_muted_script(muted: bool) -> String {
     let muted_str: &str = match muted {
-        true => "True",
-        false => "False",
+        true => "$true",
+        false => "$false",
     };
-    powershell_run(module_path, &["Set-AudioDevice", "-ID", muted_str]).map(|_| ())
+    format!("Set-AudioDevice -PlaybackMute {muted_str}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::command_runner::{ReplayRunner, CommandRecord, CommandOutcome, CommandSpec};
+
+    fn device(index: u32, default: bool, device_type: AudioDeviceType, name: &str, id: &str) -> AudioDevice {
+        AudioDevice {
+            index: index,
+            default: default,
+            device_type: device_type,
+            name: name.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn state_script_is_one_line() {
+        assert!(!STATE_SCRIPT.contains(['\n', '\r']));
+        assert!(STATE_SCRIPT.ends_with("ConvertTo-Json -Compress -Depth 3"));
+    }
+
+    #[test]
+    fn parses_state_with_several_devices() {
+        let stdout_str: &str = concat!(
+            r#"{"Default":"{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}","Volume":35,"Muted":false,"Devices":["#,
+            r#"{"Index":1,"Default":true,"Type":"Playback","Name":"Speakers (Realtek High Definition Audio)","ID":"{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}"},"#,
+            r#"{"Index":2,"Default":false,"Type":"Playback","Name":"LG TV (NVIDIA High Definition Audio)","ID":"{0.0.0.00000000}.{8c2d9e4f-1a3b-4c5d-8e7f-6a5b4c3d2e1f}"},"#,
+            r#"{"Index":3,"Default":true,"Type":"Recording","Name":"Microphone (USB Audio)","ID":"{0.0.1.00000000}.{0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d}"}"#,
+            "]}\r\n",
+        );
+        let (audio_state, devices) = parse_state_json(stdout_str).unwrap();
+        assert_eq!(
+            audio_state,
+            AudioState::from_parts("{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}".to_string(), Some(35), Some(false)),
+        );
+        assert_eq!(devices, vec![
+            device(1, true, AudioDeviceType::Playback, "Speakers (Realtek High Definition Audio)", "{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}"),
+            device(2, false, AudioDeviceType::Playback, "LG TV (NVIDIA High Definition Audio)", "{0.0.0.00000000}.{8c2d9e4f-1a3b-4c5d-8e7f-6a5b4c3d2e1f}"),
+            device(3, true, AudioDeviceType::Recording, "Microphone (USB Audio)", "{0.0.1.00000000}.{0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d}"),
+        ]);
+    }
+
+    // A German system, whose "48,5%" volume the script has already converted
+    #[test]
+    fn parses_localized_state() {
+        let stdout_str: &str = concat!(
+            "\u{feff}",
+            r#"{"Default":"{0.0.0.00000000}.{5e6f7a8b-9c0d-4e1f-a2b3-c4d5e6f7a8b9}","Volume":48.5,"Muted":true,"Devices":["#,
+            r#"{"Index":1,"Default":true,"Type":"Playback","Name":"Lautsprecher (Realtek(R) Audio)","ID":"{0.0.0.00000000}.{5e6f7a8b-9c0d-4e1f-a2b3-c4d5e6f7a8b9}"},"#,
+            r#"{"Index":2,"Default":false,"Type":"Playback","Name":"Kopfhörer (Arctis 7 Game)","ID":"{0.0.0.00000000}.{6f7a8b9c-0d1e-4f2a-b3c4-d5e6f7a8b9c0}"}"#,
+            "]}",
+        );
+        let (audio_state, devices) = parse_state_json(stdout_str).unwrap();
+        assert_eq!(audio_state.volume(), Some(49));
+        assert_eq!(audio_state.muted(), Some(true));
+        assert_eq!(devices[1].name, "Kopfhörer (Arctis 7 Game)");
+    }
+
+    // Get-AudioDevice -Playback returns nothing without a default playback device
+    #[test]
+    fn parses_state_without_a_default_device() {
+        let stdout_str: &str = r#"{"Default":null,"Volume":0,"Muted":false,"Devices":[]}"#;
+        let (audio_state, devices) = parse_state_json(stdout_str).unwrap();
+        assert_eq!(audio_state.primary_device_id(), "");
+        assert_eq!(audio_state.volume(), Some(0));
+        assert!(devices.is_empty());
+    }
+
+    #[test]
+    fn reports_position_of_invalid_json() {
+        match parse_state_json("{\"Default\":\"\",\n\"Volume\":oops}") {
+            Err(crate::Error::ADCParseError(e)) => {
+                assert_eq!(e.line, Some(2));
+                assert_eq!(e.column, Some(10));
+            },
+            other => panic!("expected ADCParseError, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn refuses_out_of_range_values() {
+        let volume: &str = r#"{"Default":"","Volume":101,"Muted":false,"Devices":[]}"#;
+        let device_type: &str = r#"{"Default":"","Volume":1,"Muted":false,"Devices":[{"Index":1,"Default":true,"Type":"Both","Name":"x","ID":"y"}]}"#;
+        let id: &str = r#"{"Default":"","Volume":1,"Muted":false,"Devices":[{"Index":1,"Default":true,"Type":"Playback","Name":"x","ID":"Speakers"}]}"#;
+        let default: &str = r#"{"Default":"Speakers","Volume":1,"Muted":false,"Devices":[]}"#;
+        for stdout_str in [volume, device_type, id, default] {
+            match parse_state_json(stdout_str) {
+                Err(crate::Error::ADCParseError(e)) => assert_eq!((e.line, e.column), (None, None)),
+                other => panic!("expected ADCParseError, got {other:?}"),
+            }
+        }
+    }
+
+    static DEVICE_LIST: &str = "
+Index   : 1
+Default : True
+Type    : Playback
+Name    : Speakers (Realtek High Definition Audio)
+ID      : {0.0.0.00000000}.{1F7C1B2A-3E4D-4C5B-9A8F-0E1D2C3B4A59}
+Device  : CoreAudioApi.MMDevice
+
+Index   : 2
+Default : False
+Type    : Playback
+Name    : LG TV with a name long enough that PowerShell wraps it onto
+          a second line (NVIDIA High Definition Audio)
+ID      : {0.0.0.00000000}.{8c2d9e4f-1a3b-4c5d-8e7f-6a5b4c3d2e1f}
+Device  : CoreAudioApi.MMDevice
+
+Index   : 3
+Default : True
+Type    : Recording
+Name    : Microphone (USB Audio)
+ID      : {0.0.1.00000000}.{0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d}
+Device  : CoreAudioApi.MMDevice
+
+
+";
+
+    #[test]
+    fn parses_device_list() {
+        assert_eq!(parse_device_list(DEVICE_LIST).unwrap(), vec![
+            device(1, true, AudioDeviceType::Playback, "Speakers (Realtek High Definition Audio)", "{0.0.0.00000000}.{1F7C1B2A-3E4D-4C5B-9A8F-0E1D2C3B4A59}"),
+            device(
+                2,
+                false,
+                AudioDeviceType::Playback,
+                "LG TV with a name long enough that PowerShell wraps it onto a second line (NVIDIA High Definition Audio)",
+                "{0.0.0.00000000}.{8c2d9e4f-1a3b-4c5d-8e7f-6a5b4c3d2e1f}",
+            ),
+            device(3, true, AudioDeviceType::Recording, "Microphone (USB Audio)", "{0.0.1.00000000}.{0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d}"),
+        ]);
+        assert_eq!(parse_device_list("\r\n\r\n").unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn parses_crlf_device_list() {
+        let devices: Vec<AudioDevice> = parse_device_list(&DEVICE_LIST.replace('\n', "\r\n")).unwrap();
+        assert_eq!(devices.len(), 3);
+        assert_eq!(devices[0].id, "{0.0.0.00000000}.{1F7C1B2A-3E4D-4C5B-9A8F-0E1D2C3B4A59}");
+    }
+
+    fn device_list_error_line(stdout_str: &str) -> Option<usize> {
+        match parse_device_list(stdout_str) {
+            Err(crate::Error::ADCParseError(e)) => e.line,
+            other => panic!("expected ADCParseError, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reports_line_of_device_list_errors() {
+        // Line 1 is the empty line DEVICE_LIST starts with
+        assert_eq!(device_list_error_line(&DEVICE_LIST.replace("Index   : 2", "Index   : two")), Some(9));
+        assert_eq!(device_list_error_line(&DEVICE_LIST.replace("Default : False", "Default : Falsch")), Some(10));
+        assert_eq!(device_list_error_line(&DEVICE_LIST.replace("Type    : Recording", "Type    : Both")), Some(19));
+        assert_eq!(device_list_error_line(&DEVICE_LIST.replace("{8c2d9e4f", "{8c2d9e4")), Some(14));
+        assert_eq!(device_list_error_line(&DEVICE_LIST.replace("Device  : CoreAudioApi.MMDevice\n\nIndex   : 3", "no colon\n\nIndex   : 3")), Some(15));
+        // A block missing a property is reported at its first line
+        assert_eq!(device_list_error_line(&DEVICE_LIST.replace("Name    : Microphone (USB Audio)\n", "")), Some(17));
+        assert_eq!(device_list_error_line("   wrapped\nIndex   : 1\n"), Some(1));
+    }
+
+    fn adc_record(script: &str, exit_code: i32, stdout: &str) -> CommandRecord {
+        let command_script: String = format!(
+            "$ErrorActionPreference = 'Stop'; Import-Module 'C:\\Program Files\\steamwig\\AudioDeviceCmdlets.dll'; {script} | Out-String -Stream -Width 4096",
+        );
+        CommandRecord::new(
+            CommandSpec::new(PathBuf::from("powershell"), &["-NoLogo", "-NoProfile", "-NonInteractive", "-Command", &command_script]),
+            CommandOutcome::Exited { exit_code: exit_code, stdout: stdout.as_bytes().to_vec(), stderr: Vec::new() },
+        )
+    }
+
+    #[test]
+    fn replays_requests() {
+        let state_json: &str = r#"{"Default":"{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}","Volume":20,"Muted":true,"Devices":[]}"#;
+        let runner: Arc<ReplayRunner> = Arc::new(ReplayRunner::new(vec![
+            adc_record(STATE_SCRIPT, 0, state_json),
+            adc_record("Set-AudioDevice -ID '{0.0.0.00000000}.{8c2d9e4f-1a3b-4c5d-8e7f-6a5b4c3d2e1f}'", 0, ""),
+            adc_record("Set-AudioDevice -PlaybackVolume 20", 0, ""),
+            adc_record("Set-AudioDevice -PlaybackMute $false", 1, ""),
+        ]));
+        let mut adc_modifier: ADCModifier = ADCModifier::with_powershell_path(
+            PathBuf::from("C:\\Program Files\\steamwig\\AudioDeviceCmdlets.dll"),
+            PathBuf::from("powershell"),
+        );
+        adc_modifier.with_runner(runner.clone());
+        let desired_state: AudioState = AudioState::from_parts(
+            "{0.0.0.00000000}.{8c2d9e4f-1a3b-4c5d-8e7f-6a5b4c3d2e1f}".to_string(),
+            Some(20),
+            Some(false),
+        );
+        assert!(matches!(adc_modifier.check_and_modify(&desired_state), Err(crate::Error::PowershellSessionError(_))));
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn replays_requests_async() {
+        use crate::internal::async_modifier::AsyncAudioModifier;
+        let state_json: &str = r#"{"Default":"{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}","Volume":20,"Muted":true,"Devices":[]}"#;
+        let runner: Arc<ReplayRunner> = Arc::new(ReplayRunner::new(vec![
+            adc_record(STATE_SCRIPT, 0, state_json),
+            adc_record("Set-AudioDevice -PlaybackVolume 35", 0, ""),
+            adc_record("Set-AudioDevice -PlaybackMute $false", 1, ""),
+        ]));
+        let mut adc_modifier: ADCModifier = ADCModifier::with_powershell_path(
+            PathBuf::from("C:\\Program Files\\steamwig\\AudioDeviceCmdlets.dll"),
+            PathBuf::from("powershell"),
+        );
+        adc_modifier.with_runner(runner.clone());
+        let desired_state: AudioState = AudioState::from_parts(
+            "{0.0.0.00000000}.{1f7c1b2a-3e4d-4c5b-9a8f-0e1d2c3b4a59}".to_string(),
+            Some(35),
+            Some(false),
+        );
+        assert!(matches!(
+            AsyncAudioModifier::check_and_modify(&adc_modifier, &desired_state).await,
+            Err(crate::Error::PowershellSessionError(_)),
+        ));
+        assert!(runner.remaining().is_empty());
+    }
 }
\ No newline at end of file
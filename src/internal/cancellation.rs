@@ -0,0 +1,128 @@
+use crate::error::TimeoutError;
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+pub (crate) static POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
+/// Stops a run, and kills the commands of backends sharing the token, once
+/// cancelled. Clones share the same state.
+///
+/// `cancel` only stores to an atomic, so it may be called from a signal handler.
+/// A cancelled token stays cancelled, and every later run with it fails with
+/// `Error::Cancelled`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+}
+
+// A deadline and the timeout it was set from
+pub (crate) type Deadline = Option<(time::Instant, time::Duration)>;
+
+thread_local! {
+    // The deadline of the run on this thread. Kept per thread rather than on
+    // the token, so that runs sharing a token each keep their own.
+    static RUN_DEADLINE: Cell<Deadline> = const { Cell::new(None) };
+}
+
+/// Puts back the deadline of the thread from before `enter_deadline` once dropped.
+#[derive(Debug)]
+pub (crate) struct DeadlineGuard {
+    previous: Deadline,
+}
+
+impl Drop for DeadlineGuard {
+    fn drop(&mut self) {
+        RUN_DEADLINE.with(|d| d.set(self.previous))
+    }
+}
+
+/// The deadline of a run starting now with `timeout`.
+pub (crate) fn deadline_after(timeout: Option<time::Duration>) -> Deadline {
+    timeout.map(|t| (time::Instant::now() + t, t))
+}
+
+/// The deadline of the run on this thread, for passing on to threads it starts.
+pub (crate) fn current_deadline() -> Deadline {
+    RUN_DEADLINE.with(|d| d.get())
+}
+
+/// Sets the deadline token checks on this thread fail after, until the guard is dropped.
+pub (crate) fn enter_deadline(deadline: Deadline) -> DeadlineGuard {
+    DeadlineGuard { previous: RUN_DEADLINE.with(|d| d.replace(deadline)) }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns `Error::Cancelled` once cancelled, and `Error::Timeout` once
+    /// the deadline of the run on this thread has passed.
+    pub (crate) fn check(&self) -> Result<(), crate::Error> {
+        if self.is_cancelled() {
+            return Err(crate::Error::Cancelled)
+        }
+        match current_deadline() {
+            Some((deadline, timeout)) if time::Instant::now() >= deadline => {
+                Err(crate::Error::Timeout(TimeoutError {
+                    operation: "run".to_string(),
+                    timeout: timeout,
+                }))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Sleeps for `duration`, waking early with an error if the token is
+    /// cancelled or the run's deadline passes.
+    pub (crate) fn sleep(&self, duration: time::Duration) -> Result<(), crate::Error> {
+        let until: time::Instant = time::Instant::now() + duration;
+        loop {
+            self.check()?;
+            let now: time::Instant = time::Instant::now();
+            if now >= until {
+                return Ok(())
+            }
+            thread::sleep(POLL_INTERVAL.min(until - now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadlines_are_per_thread() {
+        let token: CancellationToken = CancellationToken::new();
+        let outer: DeadlineGuard = enter_deadline(deadline_after(Some(time::Duration::from_secs(60))));
+        {
+            let _inner: DeadlineGuard = enter_deadline(deadline_after(Some(time::Duration::ZERO)));
+            assert!(matches!(token.check(), Err(crate::Error::Timeout(_))));
+            // Another thread sharing the token has no deadline
+            thread::scope(|scope| assert!(scope.spawn(|| token.check()).join().unwrap().is_ok()));
+        }
+        assert!(token.check().is_ok());
+        drop(outer);
+        assert_eq!(current_deadline(), None);
+        token.cancel();
+        assert!(matches!(token.check(), Err(crate::Error::Cancelled)));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,24 @@
+use crate::error::ChecksumMismatchError;
+
+use std::path::Path;
+use std::fs;
+
+use sha2::{Digest, Sha256};
+
+pub (crate) fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Errors with `Error::ChecksumMismatch` unless the file's SHA-256 is `expected`.
+pub (crate) fn verify_sha256(path: &Path, expected: &str) -> Result<(), crate::Error> {
+    let bytes: Vec<u8> = fs::read(path).map_err(|e| crate::Error::AssetIoError(e))?;
+    let actual: String = sha256_hex(&bytes);
+    match actual.eq_ignore_ascii_case(expected) {
+        true => Ok(()),
+        false => Err(crate::Error::ChecksumMismatch(ChecksumMismatchError {
+            path: path.to_path_buf(),
+            expected: expected.to_ascii_lowercase(),
+            actual: actual,
+        })),
+    }
+}
\ No newline at end of file
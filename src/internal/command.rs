@@ -0,0 +1,156 @@
+use super::command_runner::{CommandRunner, CommandSpec};
+use crate::error::CommandFailedError;
+
+use std::process;
+use std::time;
+
+/// The exit codes a call accepts as success.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub (crate) enum ExitCodes {
+    Zero,
+    OneOf(&'static [i32]),
+}
+
+impl ExitCodes {
+    fn accepts(&self, status: &process::ExitStatus) -> bool {
+        match (self, status.code()) {
+            (ExitCodes::Zero, _) => status.success(),
+            (ExitCodes::OneOf(codes), Some(code)) => codes.contains(&code),
+            (ExitCodes::OneOf(_), None) => false,
+        }
+    }
+}
+
+/// Runs `command` with `runner` inside a span recording the command line,
+/// then logs its duration and exit code, and at trace level its captured output.
+///
+/// An exit code not accepted by `exit_codes` is returned as `Error::CommandFailed`.
+pub (crate) fn traced_command(
+    runner: &dyn CommandRunner,
+    command: &CommandSpec,
+    exit_codes: ExitCodes,
+) -> Result<process::Output, crate::Error> {
+    let _span = command_span(command).entered();
+    let start: time::Instant = time::Instant::now();
+    let result: Result<process::Output, crate::Error> = runner.run(command);
+    finish_command(command, exit_codes, start, result)
+}
+
+/// `traced_command` for async backends, running `command` with `runner.run_async`.
+#[cfg(feature = "async")]
+pub (crate) async fn traced_command_async(
+    runner: &dyn CommandRunner,
+    command: &CommandSpec,
+    exit_codes: ExitCodes,
+) -> Result<process::Output, crate::Error> {
+    use tracing::Instrument;
+    async {
+        let start: time::Instant = time::Instant::now();
+        let result: Result<process::Output, crate::Error> = runner.run_async(command).await;
+        finish_command(command, exit_codes, start, result)
+    }.instrument(command_span(command)).await
+}
+
+fn command_span(command: &CommandSpec) -> tracing::Span {
+    tracing::debug_span!(
+        "command",
+        program = %command.program.display(),
+        args = ?command.args,
+    )
+}
+
+// Logs the command's duration and output, then checks its exit code
+fn finish_command(
+    command: &CommandSpec,
+    exit_codes: ExitCodes,
+    start: time::Instant,
+    result: Result<process::Output, crate::Error>,
+) -> Result<process::Output, crate::Error> {
+    let duration_ms: u64 = start.elapsed().as_millis() as u64;
+    let output: process::Output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            tracing::warn!(duration_ms, error = ?e, "command failed");
+            return Err(e)
+        },
+    };
+    tracing::debug!(duration_ms, exit_code = ?output.status.code(), "command finished");
+    tracing::trace!(
+        stdout = %String::from_utf8_lossy(&output.stdout),
+        stderr = %String::from_utf8_lossy(&output.stderr),
+        "command output",
+    );
+    check_status(command, exit_codes, output)
+}
+
+/// Spawns `command` with `runner` and leaves it running.
+pub (crate) fn traced_spawn(runner: &dyn CommandRunner, command: &CommandSpec) -> Result<(), crate::Error> {
+    tracing::debug!(program = %command.program.display(), args = ?command.args, "spawning command");
+    runner.spawn(command).inspect_err(|e| tracing::warn!(error = ?e, "spawn failed"))
+}
+
+fn check_status(
+    command: &CommandSpec,
+    exit_codes: ExitCodes,
+    output: process::Output,
+) -> Result<process::Output, crate::Error> {
+    if exit_codes.accepts(&output.status) {
+        return Ok(output)
+    }
+    let error: CommandFailedError = CommandFailedError {
+        program: command.program.to_string_lossy().to_string(),
+        args: command.args.clone(),
+        status: output.status,
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    };
+    tracing::warn!(?error, "command exited unsuccessfully");
+    Err(crate::Error::CommandFailed(error))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::internal::command_runner::ProcessRunner;
+
+    use std::path::PathBuf;
+
+    fn sh(script: &str) -> CommandSpec {
+        CommandSpec::new(PathBuf::from("sh"), &["-c", script])
+    }
+
+    #[test]
+    fn accepts_listed_exit_code() {
+        let output: process::Output = traced_command(&ProcessRunner::new(), &sh("exit 1"), ExitCodes::OneOf(&[0, 1])).unwrap();
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    #[test]
+    fn refuses_unlisted_exit_code() {
+        match traced_command(&ProcessRunner::new(), &sh("echo denied >&2; exit 2"), ExitCodes::OneOf(&[0, 1])) {
+            Err(crate::Error::CommandFailed(e)) => {
+                assert_eq!(e.program(), "sh");
+                assert_eq!(e.status().code(), Some(2));
+                assert_eq!(e.stderr().trim(), "denied");
+            },
+            other => panic!("expected CommandFailed, got {other:?}"),
+        }
+        assert!(matches!(
+            traced_command(&ProcessRunner::new(), &sh("exit 3"), ExitCodes::Zero),
+            Err(crate::Error::CommandFailed(_)),
+        ));
+    }
+
+    #[test]
+    fn refuses_termination_by_signal() {
+        use std::os::unix::process::ExitStatusExt;
+        for exit_codes in [ExitCodes::Zero, ExitCodes::OneOf(&[0, 1])] {
+            match traced_command(&ProcessRunner::new(), &sh("kill -TERM $$"), exit_codes) {
+                Err(crate::Error::CommandFailed(e)) => {
+                    assert_eq!(e.status().code(), None);
+                    assert_eq!(e.status().signal(), Some(15));
+                },
+                other => panic!("expected CommandFailed, got {other:?}"),
+            }
+        }
+    }
+}
\ No newline at end of file
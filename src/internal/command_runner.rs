@@ -0,0 +1,576 @@
+use super::cancellation::{CancellationToken, POLL_INTERVAL};
+use crate::error::{TimeoutError, UnexpectedCommandError};
+
+use std::collections::VecDeque;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+/// An external command for a `CommandRunner` to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSpec {
+    pub (crate) program: PathBuf,
+    pub (crate) args: Vec<String>,
+    pub (crate) env: Vec<(String, String)>,
+    pub (crate) current_dir: Option<PathBuf>,
+    pub (crate) timeout: Option<time::Duration>,
+}
+
+impl CommandSpec {
+    pub fn new(program: PathBuf, args: &[&str]) -> Self {
+        CommandSpec {
+            program: program,
+            args: args.iter().map(|s| s.to_string()).collect(),
+            env: Vec::new(),
+            current_dir: None,
+            timeout: None,
+        }
+    }
+
+    /// Sets an environment variable on top of the inherited environment.
+    pub fn with_env(&mut self, key: String, value: String) {
+        self.env.push((key, value))
+    }
+
+    pub fn with_current_dir(&mut self, current_dir: PathBuf) {
+        self.current_dir = Some(current_dir)
+    }
+
+    /// Kills the command if it has not exited after `timeout`, overriding the
+    /// runner's default timeout.
+    pub fn with_timeout(&mut self, timeout: time::Duration) {
+        self.timeout = Some(timeout)
+    }
+
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn env(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    pub fn current_dir(&self) -> Option<&Path> {
+        self.current_dir.as_deref()
+    }
+
+    pub fn timeout(&self) -> Option<time::Duration> {
+        self.timeout
+    }
+
+    pub (crate) fn command_line(&self) -> String {
+        let mut command_line: String = self.program.to_string_lossy().to_string();
+        for arg in &self.args {
+            command_line.push(' ');
+            command_line.push_str(arg);
+        }
+        command_line
+    }
+
+    pub (crate) fn command(&self) -> process::Command {
+        let mut command: process::Command = process::Command::new(&self.program);
+        command.args(&self.args).envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(current_dir) = &self.current_dir {
+            command.current_dir(current_dir);
+        }
+        command
+    }
+}
+
+/// Runs the external commands of the MultiMonitorTool, xrandr, pactl and
+/// Steam backends. Give a backend a `RecordingRunner` or `ReplayRunner` with
+/// `with_runner` to capture or replay its invocations.
+///
+/// `PowershellSession` keeps one process open across requests, so the
+/// AudioDeviceCmdlets backend only runs through a `CommandRunner` once given
+/// one, and in its async implementation.
+pub trait CommandRunner: std::fmt::Debug + Send + Sync {
+    /// Runs the command to completion with stdin closed, capturing its output.
+    fn run(&self, command: &CommandSpec) -> Result<process::Output, crate::Error>;
+
+    /// Starts a command that keeps running, such as Steam, without waiting on it.
+    fn spawn(&self, command: &CommandSpec) -> Result<(), crate::Error>;
+
+    /// `run` for the async backends. The default calls `run` on the calling
+    /// task, so runners which block on the command should override it.
+    #[cfg(feature = "async")]
+    fn run_async<'a>(
+        &'a self,
+        command: &'a CommandSpec,
+    ) -> Pin<Box<dyn Future<Output = Result<process::Output, crate::Error>> + Send + 'a>> {
+        Box::pin(async move { self.run(command) })
+    }
+}
+
+/// Runs commands as child processes.
+#[derive(Debug, Default)]
+pub struct ProcessRunner {
+    timeout: Option<time::Duration>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl ProcessRunner {
+    pub fn new() -> Self {
+        ProcessRunner { timeout: None, cancellation: None }
+    }
+
+    /// Kills commands without a timeout of their own if they have not exited after `timeout`.
+    pub fn with_timeout(&mut self, timeout: time::Duration) {
+        self.timeout = Some(timeout)
+    }
+
+    /// Kills running commands once `token` is cancelled, or once the deadline
+    /// of the run using it passes.
+    pub fn with_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token)
+    }
+}
+
+impl CommandRunner for ProcessRunner {
+    fn run(&self, command: &CommandSpec) -> Result<process::Output, crate::Error> {
+        if let Some(token) = &self.cancellation {
+            token.check()?;
+        }
+        let child: process::Child = command.command()
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .map_err(|e| crate::Error::CommandError(e))?;
+        match (command.timeout.or(self.timeout), &self.cancellation) {
+            (None, None) => child.wait_with_output().map_err(|e| crate::Error::CommandError(e)),
+            (timeout, cancellation) => wait_with_deadline(child, command, timeout, cancellation.as_ref()),
+        }
+    }
+
+    fn spawn(&self, command: &CommandSpec) -> Result<(), crate::Error> {
+        if let Some(token) = &self.cancellation {
+            token.check()?;
+        }
+        command.command()
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn()
+            .map(|_| ())
+            .map_err(|e| crate::Error::CommandError(e))
+    }
+
+    // Runs the command with tokio. The child is killed if the future is
+    // dropped, such as by the run timeout of `AsyncInnerSystemModifier`.
+    #[cfg(feature = "async")]
+    fn run_async<'a>(
+        &'a self,
+        command: &'a CommandSpec,
+    ) -> Pin<Box<dyn Future<Output = Result<process::Output, crate::Error>> + Send + 'a>> {
+        Box::pin(async move {
+            if let Some(token) = &self.cancellation {
+                token.check()?;
+            }
+            let mut child_command: tokio::process::Command = command.command().into();
+            child_command
+                .stdin(process::Stdio::null())
+                .stdout(process::Stdio::piped())
+                .stderr(process::Stdio::piped())
+                .kill_on_drop(true);
+            let output = async {
+                match command.timeout.or(self.timeout) {
+                    Some(timeout) => match tokio::time::timeout(timeout, child_command.output()).await {
+                        Ok(output) => output.map_err(|e| crate::Error::CommandError(e)),
+                        Err(_) => Err(crate::Error::Timeout(TimeoutError {
+                            operation: command.command_line(),
+                            timeout: timeout,
+                        })),
+                    },
+                    None => child_command.output().await.map_err(|e| crate::Error::CommandError(e)),
+                }
+            };
+            let result: Result<process::Output, crate::Error> = match &self.cancellation {
+                Some(token) => tokio::select! {
+                    result = output => result,
+                    e = cancelled(token) => Err(e),
+                },
+                None => output.await,
+            };
+            if let Err(e) = &result {
+                tracing::warn!(error = ?e, "killing command");
+            }
+            result
+        })
+    }
+}
+
+// Resolves to the token's error once it is cancelled
+#[cfg(feature = "async")]
+async fn cancelled(token: &CancellationToken) -> crate::Error {
+    loop {
+        if let Err(e) = token.check() {
+            return e
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+// The pipes are drained on their own threads so that a chatty child cannot
+// block on a full pipe while it is being polled.
+fn wait_with_deadline(
+    mut child: process::Child,
+    command: &CommandSpec,
+    timeout: Option<time::Duration>,
+    cancellation: Option<&CancellationToken>,
+) -> Result<process::Output, crate::Error> {
+    let stdout_reader: thread::JoinHandle<Vec<u8>> = read_pipe(child.stdout.take());
+    let stderr_reader: thread::JoinHandle<Vec<u8>> = read_pipe(child.stderr.take());
+    let deadline: Option<time::Instant> = timeout.map(|t| time::Instant::now() + t);
+    let error: crate::Error = loop {
+        if let Some(status) = child.try_wait().map_err(|e| crate::Error::CommandError(e))? {
+            return Ok(process::Output {
+                status: status,
+                stdout: stdout_reader.join().unwrap_or_default(),
+                stderr: stderr_reader.join().unwrap_or_default(),
+            })
+        }
+        if let Some(Err(e)) = cancellation.map(|t| t.check()) {
+            break e
+        }
+        match (deadline, timeout) {
+            (Some(deadline), Some(timeout)) if time::Instant::now() >= deadline => {
+                break crate::Error::Timeout(TimeoutError {
+                    operation: command.command_line(),
+                    timeout: timeout,
+                })
+            },
+            _ => thread::sleep(POLL_INTERVAL),
+        }
+    };
+    // The readers are left to finish on their own, in case the child passed
+    // its pipes on to a process that outlives it.
+    tracing::warn!(error = ?error, "killing command");
+    let _ = child.kill();
+    let _ = child.wait();
+    Err(error)
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf: Vec<u8> = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// What came of a recorded command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandOutcome {
+    /// The command ran to completion. A command killed by a signal has exit code -1.
+    Exited { exit_code: i32, stdout: Vec<u8>, stderr: Vec<u8> },
+    /// The command was spawned and left running.
+    Spawned,
+    /// The command could not be run, or timed out.
+    Failed(String),
+}
+
+impl CommandOutcome {
+    fn from_output(output: &process::Output) -> Self {
+        CommandOutcome::Exited {
+            exit_code: output.status.code().unwrap_or(-1),
+            stdout: output.stdout.clone(),
+            stderr: output.stderr.clone(),
+        }
+    }
+}
+
+/// A command and what came of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandRecord {
+    pub (crate) command: CommandSpec,
+    pub (crate) outcome: CommandOutcome,
+}
+
+impl CommandRecord {
+    pub fn new(command: CommandSpec, outcome: CommandOutcome) -> Self {
+        CommandRecord { command: command, outcome: outcome }
+    }
+
+    pub fn command(&self) -> &CommandSpec {
+        &self.command
+    }
+
+    pub fn outcome(&self) -> &CommandOutcome {
+        &self.outcome
+    }
+}
+
+/// Runs commands with another runner, recording every invocation and its outcome.
+#[derive(Debug)]
+pub struct RecordingRunner<R: CommandRunner> {
+    inner: R,
+    records: Mutex<Vec<CommandRecord>>,
+}
+
+impl<R: CommandRunner> RecordingRunner<R> {
+    pub fn new(inner: R) -> Self {
+        RecordingRunner { inner: inner, records: Mutex::new(Vec::new()) }
+    }
+
+    /// The commands run so far, in order.
+    pub fn records(&self) -> Vec<CommandRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    fn record(&self, command: &CommandSpec, outcome: CommandOutcome) {
+        self.records.lock().unwrap().push(CommandRecord { command: command.clone(), outcome: outcome });
+    }
+}
+
+impl<R: CommandRunner> CommandRunner for RecordingRunner<R> {
+    fn run(&self, command: &CommandSpec) -> Result<process::Output, crate::Error> {
+        let result: Result<process::Output, crate::Error> = self.inner.run(command);
+        self.record(command, match &result {
+            Ok(output) => CommandOutcome::from_output(output),
+            Err(e) => CommandOutcome::Failed(format!("{e:?}")),
+        });
+        result
+    }
+
+    fn spawn(&self, command: &CommandSpec) -> Result<(), crate::Error> {
+        let result: Result<(), crate::Error> = self.inner.spawn(command);
+        self.record(command, match &result {
+            Ok(()) => CommandOutcome::Spawned,
+            Err(e) => CommandOutcome::Failed(format!("{e:?}")),
+        });
+        result
+    }
+
+    #[cfg(feature = "async")]
+    fn run_async<'a>(
+        &'a self,
+        command: &'a CommandSpec,
+    ) -> Pin<Box<dyn Future<Output = Result<process::Output, crate::Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let result: Result<process::Output, crate::Error> = self.inner.run_async(command).await;
+            self.record(command, match &result {
+                Ok(output) => CommandOutcome::from_output(output),
+                Err(e) => CommandOutcome::Failed(format!("{e:?}")),
+            });
+            result
+        })
+    }
+}
+
+/// Replays recorded outcomes without running anything. Each command must
+/// equal the next recorded one, or `Error::UnexpectedCommand` is returned.
+/// Recorded failures are replayed as `Error::CommandError`.
+#[derive(Debug)]
+pub struct ReplayRunner {
+    records: Mutex<VecDeque<CommandRecord>>,
+}
+
+impl ReplayRunner {
+    pub fn new(records: Vec<CommandRecord>) -> Self {
+        ReplayRunner { records: Mutex::new(records.into()) }
+    }
+
+    /// The recordings not yet replayed.
+    pub fn remaining(&self) -> Vec<CommandRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn next(&self, command: &CommandSpec) -> Result<CommandOutcome, crate::Error> {
+        let mut records = self.records.lock().unwrap();
+        match records.front() {
+            Some(record) if record.command == *command => Ok(records.pop_front().unwrap().outcome),
+            expected => Err(crate::Error::UnexpectedCommand(UnexpectedCommandError {
+                expected: expected.map(|r| Box::new(r.command.clone())),
+                actual: Box::new(command.clone()),
+            })),
+        }
+    }
+}
+
+impl CommandRunner for ReplayRunner {
+    fn run(&self, command: &CommandSpec) -> Result<process::Output, crate::Error> {
+        match self.next(command)? {
+            CommandOutcome::Exited { exit_code, stdout, stderr } => Ok(process::Output {
+                status: exit_status(exit_code),
+                stdout: stdout,
+                stderr: stderr,
+            }),
+            CommandOutcome::Spawned => Err(replay_failure("the command was recorded as spawned, not run")),
+            CommandOutcome::Failed(e) => Err(replay_failure(&e)),
+        }
+    }
+
+    fn spawn(&self, command: &CommandSpec) -> Result<(), crate::Error> {
+        match self.next(command)? {
+            CommandOutcome::Spawned => Ok(()),
+            CommandOutcome::Exited { .. } => Err(replay_failure("the command was recorded as run, not spawned")),
+            CommandOutcome::Failed(e) => Err(replay_failure(&e)),
+        }
+    }
+}
+
+fn replay_failure(description: &str) -> crate::Error {
+    crate::Error::CommandError(std::io::Error::other(description.to_string()))
+}
+
+#[cfg(unix)]
+fn exit_status(exit_code: i32) -> process::ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    process::ExitStatus::from_raw((exit_code & 0xff) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(exit_code: i32) -> process::ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    process::ExitStatus::from_raw(exit_code as u32)
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> CommandSpec {
+        CommandSpec::new(PathBuf::from("sh"), &["-c", script])
+    }
+
+    #[test]
+    fn replays_recorded_commands() {
+        let recording_runner: RecordingRunner<ProcessRunner> = RecordingRunner::new(ProcessRunner::new());
+        let mut env_command: CommandSpec = sh("printf %s \"$GREETING\" >&2; exit 3");
+        env_command.with_env("GREETING".to_string(), "hello".to_string());
+        let output: process::Output = recording_runner.run(&env_command).unwrap();
+        recording_runner.spawn(&sh("true")).unwrap();
+        assert!(recording_runner.run(&CommandSpec::new(PathBuf::from("/nonexistent/tool"), &[])).is_err());
+
+        let records: Vec<CommandRecord> = recording_runner.records();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0].outcome(),
+            &CommandOutcome::Exited { exit_code: 3, stdout: Vec::new(), stderr: b"hello".to_vec() },
+        );
+        assert_eq!(records[1], CommandRecord::new(sh("true"), CommandOutcome::Spawned));
+        assert!(matches!(records[2].outcome(), CommandOutcome::Failed(_)));
+
+        let replay_runner: ReplayRunner = ReplayRunner::new(records);
+        let replayed: process::Output = replay_runner.run(&env_command).unwrap();
+        assert_eq!((replayed.status.code(), replayed.stderr), (output.status.code(), output.stderr));
+        replay_runner.spawn(&sh("true")).unwrap();
+        assert!(matches!(
+            replay_runner.run(&CommandSpec::new(PathBuf::from("/nonexistent/tool"), &[])),
+            Err(crate::Error::CommandError(_)),
+        ));
+        assert!(replay_runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn refuses_unexpected_commands() {
+        let replay_runner: ReplayRunner = ReplayRunner::new(vec![CommandRecord::new(sh("true"), CommandOutcome::Spawned)]);
+        match replay_runner.run(&sh("false")) {
+            Err(crate::Error::UnexpectedCommand(e)) => {
+                assert_eq!(e.expected.as_deref(), Some(&sh("true")));
+                assert_eq!(*e.actual, sh("false"));
+            },
+            r => panic!("expected UnexpectedCommand, got {r:?}"),
+        }
+        // A spawned recording is not replayed as a run
+        assert!(matches!(replay_runner.run(&sh("true")), Err(crate::Error::CommandError(_))));
+        assert!(matches!(replay_runner.spawn(&sh("true")), Err(crate::Error::UnexpectedCommand(_))));
+    }
+
+    #[test]
+    fn command_timeout_kills_command() {
+        let mut process_runner: ProcessRunner = ProcessRunner::new();
+        process_runner.with_timeout(time::Duration::from_millis(100));
+        let start: time::Instant = time::Instant::now();
+        match process_runner.run(&sh("sleep 5")) {
+            Err(crate::Error::Timeout(e)) => assert_eq!(e.timeout(), time::Duration::from_millis(100)),
+            r => panic!("expected Timeout, got {r:?}"),
+        }
+        assert!(start.elapsed() < time::Duration::from_secs(2));
+        // A command's own timeout takes precedence
+        let mut patient_command: CommandSpec = sh("sleep 0.3");
+        patient_command.with_timeout(time::Duration::from_secs(5));
+        assert!(process_runner.run(&patient_command).unwrap().status.success());
+    }
+
+    #[test]
+    fn cancellation_kills_command() {
+        let token: CancellationToken = CancellationToken::new();
+        let mut process_runner: ProcessRunner = ProcessRunner::new();
+        process_runner.with_cancellation(token.clone());
+        let start: time::Instant = time::Instant::now();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(time::Duration::from_millis(100));
+                token.cancel();
+            });
+            assert!(matches!(process_runner.run(&sh("sleep 5")), Err(crate::Error::Cancelled)));
+        });
+        assert!(start.elapsed() < time::Duration::from_secs(2));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn command_timeout_kills_command_async() {
+        let mut process_runner: ProcessRunner = ProcessRunner::new();
+        process_runner.with_timeout(time::Duration::from_millis(100));
+        let start: time::Instant = time::Instant::now();
+        match process_runner.run_async(&sh("sleep 5")).await {
+            Err(crate::Error::Timeout(e)) => assert_eq!(e.timeout(), time::Duration::from_millis(100)),
+            r => panic!("expected Timeout, got {r:?}"),
+        }
+        assert!(start.elapsed() < time::Duration::from_secs(2));
+        let output: process::Output = process_runner.run_async(&sh("printf hello")).await.unwrap();
+        assert_eq!(output.stdout, b"hello");
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn cancellation_kills_command_async() {
+        let token: CancellationToken = CancellationToken::new();
+        let mut process_runner: ProcessRunner = ProcessRunner::new();
+        process_runner.with_cancellation(token.clone());
+        let start: time::Instant = time::Instant::now();
+        let cancel = async {
+            tokio::time::sleep(time::Duration::from_millis(100)).await;
+            token.cancel();
+        };
+        let sleep_command: CommandSpec = sh("sleep 5");
+        let (result, ()) = tokio::join!(process_runner.run_async(&sleep_command), cancel);
+        assert!(matches!(result, Err(crate::Error::Cancelled)));
+        assert!(start.elapsed() < time::Duration::from_secs(2));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn records_async_commands() {
+        let recording_runner: RecordingRunner<ProcessRunner> = RecordingRunner::new(ProcessRunner::new());
+        recording_runner.run_async(&sh("printf hello; exit 4")).await.unwrap();
+        let records: Vec<CommandRecord> = recording_runner.records();
+        assert_eq!(
+            records,
+            vec![CommandRecord::new(
+                sh("printf hello; exit 4"),
+                CommandOutcome::Exited { exit_code: 4, stdout: b"hello".to_vec(), stderr: Vec::new() },
+            )],
+        );
+        let replay_runner: ReplayRunner = ReplayRunner::new(records);
+        let output: process::Output = replay_runner.run_async(&sh("printf hello; exit 4")).await.unwrap();
+        assert_eq!((output.status.code(), output.stdout), (Some(4), b"hello".to_vec()));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,264 @@
+use super::system_modifier_builder::{DisplayBackend, AudioBackend};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::time;
+
+use serde::Deserialize;
+
+static CONFIG_FILE_NAME: &str = "config.toml";
+static LAYOUTS_DIR_NAME: &str = "layouts";
+static LAYOUT_FILE_EXTENSION: &str = "cfg";
+
+/// Settings read from steamwig's config file.
+///
+/// ```toml
+/// [backends]
+/// display = "mmt"
+/// audio = "adc"
+///
+/// [checksums]
+/// verify = true
+///
+/// [timeouts]
+/// command = 30
+/// run = 120
+///
+/// [aliases]
+/// tv = '\\.\DISPLAY3\Monitor0'
+/// headset = '{0.0.0.00000000}.{2d3c4b5a-6978-4a1b-8c2d-3e4f5a6b7c8d}'
+/// ```
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub (crate) backends: BackendsConfig,
+    pub (crate) checksums: ChecksumsConfig,
+    pub (crate) timeouts: TimeoutsConfig,
+    pub (crate) aliases: Aliases,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct BackendsConfig {
+    pub (crate) display: DisplayBackend,
+    pub (crate) audio: AudioBackend,
+}
+
+/// SHA-256 checksums, in hex, which MultiMonitorTool and AudioDeviceCmdlets
+/// must match. With `verify`, tools without a configured checksum must match
+/// the checksums pinned for the bundled copies.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct ChecksumsConfig {
+    pub (crate) verify: bool,
+    pub (crate) mmt: Option<String>,
+    pub (crate) adc: Option<String>,
+}
+
+/// Seconds after which a backend command is killed, and a run gives up.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct TimeoutsConfig {
+    pub (crate) command: Option<u64>,
+    pub (crate) run: Option<u64>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, crate::Error> {
+        let config_str: String = fs::read_to_string(path).map_err(|e| crate::Error::ConfigReadError(e))?;
+        toml::from_str(&config_str).map_err(|e| crate::Error::ConfigParseError(e))
+    }
+
+    /// Loads the config file from the config directory, or the defaults if there is none.
+    pub fn load_default() -> Result<Config, crate::Error> {
+        match config_dir().map(|p| p.join(CONFIG_FILE_NAME)) {
+            Some(path) if path.is_file() => Config::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn display_backend(&self) -> DisplayBackend {
+        self.backends.display
+    }
+
+    pub fn audio_backend(&self) -> AudioBackend {
+        self.backends.audio
+    }
+
+    pub fn verify_checksums(&self) -> bool {
+        self.checksums.verify
+    }
+
+    pub fn command_timeout(&self) -> Option<time::Duration> {
+        self.timeouts.command.map(time::Duration::from_secs)
+    }
+
+    pub fn run_timeout(&self) -> Option<time::Duration> {
+        self.timeouts.run.map(time::Duration::from_secs)
+    }
+
+    pub fn mmt_sha256(&self) -> Option<&str> {
+        self.checksums.mmt.as_deref()
+    }
+
+    pub fn adc_sha256(&self) -> Option<&str> {
+        self.checksums.adc.as_deref()
+    }
+
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
+}
+
+/// Names for display and audio device IDs.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Aliases {
+    pub (crate) device_ids: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    pub fn new() -> Aliases {
+        Aliases { device_ids: BTreeMap::new() }
+    }
+
+    pub fn insert(&mut self, name: String, device_id: String) {
+        self.device_ids.insert(name, device_id);
+    }
+
+    /// The device ID an alias names, or `name_or_id` itself if it is not an alias.
+    pub fn resolve(&self, name_or_id: &str) -> String {
+        match self.device_ids.get(name_or_id) {
+            Some(device_id) => device_id.clone(),
+            None => name_or_id.to_string(),
+        }
+    }
+
+    /// Aliases and the device IDs they name, ordered by alias.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.device_ids.iter().map(|(name, device_id)| (name.as_str(), device_id.as_str()))
+    }
+
+    /// Aliases naming a device ID which is not in `device_ids`.
+    pub fn check<'a>(&'a self, device_ids: &[String]) -> Vec<(&'a str, &'a str)> {
+        self.iter().filter(|(_, device_id)| !device_ids.iter().any(|id| id == device_id)).collect()
+    }
+}
+
+/// `%APPDATA%\steamwig` on Windows, `$XDG_CONFIG_HOME/steamwig` or
+/// `~/.config/steamwig` elsewhere.
+pub fn config_dir() -> Option<PathBuf> {
+    let base_dir: PathBuf = match cfg!(windows) {
+        true => std::env::var_os("APPDATA").map(PathBuf::from)?,
+        false => match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(p) => PathBuf::from(p),
+            None => std::env::var_os("HOME").map(PathBuf::from)?.join(".config"),
+        },
+    };
+    Some(base_dir.join("steamwig"))
+}
+
+/// `%LOCALAPPDATA%\steamwig` on Windows, `$XDG_CACHE_HOME/steamwig` or
+/// `~/.cache/steamwig` elsewhere.
+pub fn cache_dir() -> Option<PathBuf> {
+    let base_dir: PathBuf = match cfg!(windows) {
+        true => std::env::var_os("LOCALAPPDATA").map(PathBuf::from)?,
+        false => match std::env::var_os("XDG_CACHE_HOME") {
+            Some(p) => PathBuf::from(p),
+            None => std::env::var_os("HOME").map(PathBuf::from)?.join(".cache"),
+        },
+    };
+    Some(base_dir.join("steamwig"))
+}
+
+/// The `layouts` directory in `config_dir()`, where saved display layouts are kept.
+pub fn layouts_dir() -> Option<PathBuf> {
+    config_dir().map(|p| p.join(LAYOUTS_DIR_NAME))
+}
+
+/// The file a display layout named `name` is saved to. `None` if there is no
+/// config directory, or if `name` is empty, `.`, `..` or contains a path separator.
+pub fn layout_path(name: &str) -> Option<PathBuf> {
+    layout_path_in(&layouts_dir()?, name)
+}
+
+/// Whether `name` can name a layout, i.e. names a file directly in `layouts_dir()`.
+pub (crate) fn is_layout_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".."
+        // Either separator, so that layouts are portable between platforms
+        && !name.contains(['/', '\\'])
+}
+
+// `name` may contain dots, which `Path::with_extension` would replace
+fn layout_path_in(layouts_dir: &Path, name: &str) -> Option<PathBuf> {
+    match is_layout_name(name) {
+        true => Some(layouts_dir.join(format!("{name}.{LAYOUT_FILE_EXTENSION}"))),
+        false => None,
+    }
+}
+
+/// Names of the saved display layouts, sorted.
+pub fn layout_names() -> Result<Vec<String>, crate::Error> {
+    let layouts_dir: PathBuf = match layouts_dir() {
+        Some(p) if p.is_dir() => p,
+        _ => return Ok(Vec::new()),
+    };
+    let mut names: Vec<String> = Vec::new();
+    for entry in fs::read_dir(layouts_dir).map_err(|e| crate::Error::LayoutIoError(e))? {
+        let path: PathBuf = entry.map_err(|e| crate::Error::LayoutIoError(e))?.path();
+        if path.extension().is_some_and(|e| e == LAYOUT_FILE_EXTENSION) {
+            if let Some(name) = path.file_stem() {
+                names.push(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases() -> Aliases {
+        let mut aliases: Aliases = Aliases::new();
+        aliases.insert("tv".to_string(), "\\\\.\\DISPLAY2".to_string());
+        aliases.insert("desk".to_string(), "\\\\.\\DISPLAY1".to_string());
+        aliases.insert("headset".to_string(), "{0.0.0.00000000}.{6f7a8b9c-0d1e-4f2a-b3c4-d5e6f7a8b9c0}".to_string());
+        aliases
+    }
+
+    #[test]
+    fn resolves_aliases_and_raw_ids() {
+        let aliases: Aliases = aliases();
+        assert_eq!(aliases.resolve("tv"), "\\\\.\\DISPLAY2");
+        assert_eq!(aliases.resolve("\\\\.\\DISPLAY3"), "\\\\.\\DISPLAY3");
+        // Aliases are case-sensitive
+        assert_eq!(aliases.resolve("TV"), "TV");
+    }
+
+    #[test]
+    fn reports_aliases_of_missing_devices() {
+        let aliases: Aliases = aliases();
+        let device_ids: Vec<String> = vec!["\\\\.\\DISPLAY1".to_string(), "\\\\.\\DISPLAY2".to_string()];
+        assert_eq!(aliases.check(&device_ids), vec![("headset", "{0.0.0.00000000}.{6f7a8b9c-0d1e-4f2a-b3c4-d5e6f7a8b9c0}")]);
+        assert!(Aliases::new().check(&device_ids).is_empty());
+    }
+
+    #[test]
+    fn keeps_dots_in_layout_names() {
+        let layouts_dir: &Path = Path::new("layouts");
+        assert_eq!(layout_path_in(layouts_dir, "tv"), Some(layouts_dir.join("tv.cfg")));
+        assert_eq!(layout_path_in(layouts_dir, "tv.evening"), Some(layouts_dir.join("tv.evening.cfg")));
+        assert_eq!(layout_path_in(layouts_dir, "desk.cfg"), Some(layouts_dir.join("desk.cfg.cfg")));
+    }
+
+    #[test]
+    fn refuses_layout_names_outside_layouts_dir() {
+        for name in ["", ".", "..", "../config", "a/b", "..\\config", "C:\\layouts\\tv", "/tmp/tv"] {
+            assert_eq!(layout_path_in(Path::new("layouts"), name), None, "{name:?}");
+        }
+        assert!(is_layout_name("..tv"));
+    }
+}
\ No newline at end of file
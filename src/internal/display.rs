@@ -1,8 +1,48 @@
+use std::path::Path;
+
+/// Which displays are enabled, disabled and primary, and optionally where
+/// enabled displays sit on the desktop.
+///
+/// As a desired state, empty fields are left as they are on the system.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default = "DisplayState::new"))]
 pub struct DisplayState {
     pub (crate) primary_device_id: String,
     pub (crate) enabled_device_ids: Vec<String>,
     pub (crate) disabled_device_ids: Vec<String>,
+    pub (crate) positions: Vec<DisplayPosition>,
+}
+
+/// The desktop position of a display's top left corner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisplayPosition {
+    pub (crate) device_id: String,
+    pub (crate) x: i32,
+    pub (crate) y: i32,
+}
+
+impl DisplayPosition {
+    pub fn new(device_id: String, x: i32, y: i32) -> DisplayPosition {
+        DisplayPosition {
+            device_id: device_id,
+            x: x,
+            y: y,
+        }
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
 }
 
 impl DisplayState {
@@ -11,13 +51,55 @@ impl DisplayState {
             primary_device_id: String::new(),
             enabled_device_ids: Vec::new(),
             disabled_device_ids: Vec::new(),
+            positions: Vec::new(),
         }
     }
 
+    /// Used by backends to report the system state.
+    pub fn from_device_ids(
+        primary_device_id: String,
+        enabled_device_ids: Vec<String>,
+        disabled_device_ids: Vec<String>,
+    ) -> DisplayState {
+        DisplayState {
+            primary_device_id: primary_device_id,
+            enabled_device_ids: enabled_device_ids,
+            disabled_device_ids: disabled_device_ids,
+            positions: Vec::new(),
+        }
+    }
+
+    /// Used by backends which report where enabled displays are.
+    pub fn with_positions(&mut self, positions: Vec<DisplayPosition>) {
+        self.positions = positions
+    }
+
+    /// Empty if the primary display is unknown or unspecified.
+    pub fn primary_device_id(&self) -> &str {
+        &self.primary_device_id
+    }
+
+    pub fn enabled_device_ids(&self) -> &[String] {
+        &self.enabled_device_ids
+    }
+
+    pub fn disabled_device_ids(&self) -> &[String] {
+        &self.disabled_device_ids
+    }
+
+    pub fn positions(&self) -> &[DisplayPosition] {
+        &self.positions
+    }
+
+    pub fn position(&self, device_id: &str) -> Option<&DisplayPosition> {
+        self.positions.iter().find(|p| p.device_id == device_id)
+    }
+
     pub fn is_empty(&self) -> bool {
         if self.primary_device_id.is_empty()
             && self.enabled_device_ids.is_empty()
             && self.disabled_device_ids.is_empty()
+            && self.positions.is_empty()
         {
             true
         } else {
@@ -27,48 +109,220 @@ impl DisplayState {
 }
 
 #[derive(Debug)]
-struct DisplayModifierObjectives<'a> {
-    primary_device_id: &'a str,
-    enabled_device_ids: Vec<&'a str>,
-    disabled_device_ids: Vec<&'a str>,
+pub (crate) struct DisplayModifierObjectives<'a> {
+    pub (crate) primary_device_id: &'a str,
+    pub (crate) enabled_device_ids: Vec<&'a str>,
+    pub (crate) disabled_device_ids: Vec<&'a str>,
+    pub (crate) positions: Vec<DisplayPosition>,
+}
+
+/// A call `modify_observed` makes to meet its objectives.
+#[derive(Debug, PartialEq, Eq)]
+pub (crate) enum DisplayAction<'a> {
+    EnableMonitors(&'a [&'a str]),
+    DisableMonitors(&'a [&'a str]),
+    SetPrimaryDevice(&'a str),
+    SetPositions(&'a [DisplayPosition]),
 }
 
-pub trait DisplayModifier {
+impl DisplayModifierObjectives<'_> {
+    /// The calls which meet the objectives, in the order they are made.
+    /// Monitors are enabled before others are disabled, so that one stays enabled.
+    pub (crate) fn actions(&self) -> Vec<DisplayAction<'_>> {
+        let mut actions: Vec<DisplayAction> = Vec::with_capacity(4);
+        if !self.enabled_device_ids.is_empty() {
+            actions.push(DisplayAction::EnableMonitors(&self.enabled_device_ids));
+        }
+        if !self.disabled_device_ids.is_empty() {
+            actions.push(DisplayAction::DisableMonitors(&self.disabled_device_ids));
+        }
+        if !self.primary_device_id.is_empty() {
+            actions.push(DisplayAction::SetPrimaryDevice(self.primary_device_id));
+        }
+        if !self.positions.is_empty() {
+            actions.push(DisplayAction::SetPositions(&self.positions));
+        }
+        actions
+    }
+}
+
+/// A display backend.
+///
+/// Implementors provide the queries and actions; `check_and_modify` compares
+/// the desired state with the system state and issues only the actions needed.
+/// Backends are shared across threads, so that subsystems can be queried concurrently.
+pub trait DisplayModifier: Send + Sync {
     fn get_id_readout(&self) -> Result<String, crate::Error>;
 
     fn get_system_state(&self) -> Result<DisplayState, crate::Error>;
 
+    fn get_device_ids(&self) -> Result<Vec<String>, crate::Error> {
+        let system_state: DisplayState = self.get_system_state()?;
+        let mut device_ids: Vec<String> = system_state.enabled_device_ids;
+        device_ids.extend(system_state.disabled_device_ids);
+        Ok(device_ids)
+    }
+
     fn enable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error>;
     fn disable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error>;
     fn set_primary_device(&self, id: &str) -> Result<(), crate::Error>;
 
+    /// Moves enabled displays. Backends which cannot position displays keep this default.
+    fn set_positions(&self, _positions: &[DisplayPosition]) -> Result<(), crate::Error> {
+        Err(crate::Error::BackendUnavailable("display backend cannot set positions"))
+    }
+
+    /// Saves the whole display configuration to `path`.
+    fn save_layout(&self, _path: &Path) -> Result<(), crate::Error> {
+        Err(crate::Error::BackendUnavailable("display backend cannot save layouts"))
+    }
+
+    /// Applies a display configuration saved by `save_layout`.
+    fn load_layout(&self, _path: &Path) -> Result<(), crate::Error> {
+        Err(crate::Error::BackendUnavailable("display backend cannot load layouts"))
+    }
+
+    /// The display state a layout saved by `save_layout` describes.
+    fn read_layout(&self, _path: &Path) -> Result<DisplayState, crate::Error> {
+        Err(crate::Error::BackendUnavailable("display backend cannot read layouts"))
+    }
+
+    /// Loads the layout unless the system state already matches it.
+    /// Returns true if it was loaded, so that the next pass can verify it.
+    fn check_and_load_layout(&self, path: &Path) -> Result<bool, crate::Error> {
+        let layout_state: DisplayState = self.read_layout(path)?;
+        let system_state: DisplayState = self.get_system_state()?;
+        self.load_layout_observed(path, &layout_state, &system_state)
+    }
+
+    /// Loads the layout at `path`, which describes `layout_state`, unless
+    /// `system_state` already matches it. Returns true if it was loaded.
+    fn load_layout_observed(
+        &self,
+        path: &Path,
+        layout_state: &DisplayState,
+        system_state: &DisplayState,
+    ) -> Result<bool, crate::Error> {
+        tracing::debug!(observed = ?system_state, layout = ?layout_state, path = %path.display(), "display layout");
+        if get_objectives(layout_state, system_state).is_none() {
+            return Ok(false)
+        }
+        self.load_layout(path)?;
+        Ok(true)
+    }
+
+    /// Returns true if any action was issued, false if the system state already matches.
     fn check_and_modify(&self, desired_state: &DisplayState) -> Result<bool, crate::Error> {
         if desired_state.is_empty() {
             return Ok(false)
         }
         let system_state: DisplayState = self.get_system_state()?;
-        let objectives: DisplayModifierObjectives = match get_objectives(&desired_state, &system_state) {
+        self.modify_observed(desired_state, &system_state)
+    }
+
+    /// Issues the actions needed to bring `system_state`, as queried by
+    /// `get_system_state`, to `desired_state`. Returns true if any action was issued.
+    fn modify_observed(&self, desired_state: &DisplayState, system_state: &DisplayState) -> Result<bool, crate::Error> {
+        let objectives: DisplayModifierObjectives = match plan(desired_state, system_state)? {
             Some(o) => o,
             None => return Ok(false),
         };
-        if !objectives.enabled_device_ids.is_empty() {
-            self.enable_monitors(&objectives.enabled_device_ids)?;
-        }
-        if !objectives.disabled_device_ids.is_empty() {
-            self.disable_monitors(&objectives.disabled_device_ids)?;
-        }
-        if !objectives.primary_device_id.is_empty() {
-            self.set_primary_device(&objectives.primary_device_id)?;
+        for action in objectives.actions() {
+            match action {
+                DisplayAction::EnableMonitors(device_ids) => self.enable_monitors(device_ids)?,
+                DisplayAction::DisableMonitors(device_ids) => self.disable_monitors(device_ids)?,
+                DisplayAction::SetPrimaryDevice(id) => self.set_primary_device(id)?,
+                DisplayAction::SetPositions(positions) => self.set_positions(positions)?,
+            }
         }
         Ok(true)
     }
 }
 
-fn get_objectives<'a>(
+impl<T: DisplayModifier + ?Sized> DisplayModifier for Box<T> {
+    fn get_id_readout(&self) -> Result<String, crate::Error> {
+        (**self).get_id_readout()
+    }
+
+    fn get_system_state(&self) -> Result<DisplayState, crate::Error> {
+        (**self).get_system_state()
+    }
+
+    fn get_device_ids(&self) -> Result<Vec<String>, crate::Error> {
+        (**self).get_device_ids()
+    }
+
+    fn enable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        (**self).enable_monitors(device_ids)
+    }
+
+    fn disable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        (**self).disable_monitors(device_ids)
+    }
+
+    fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        (**self).set_primary_device(id)
+    }
+
+    fn set_positions(&self, positions: &[DisplayPosition]) -> Result<(), crate::Error> {
+        (**self).set_positions(positions)
+    }
+
+    fn save_layout(&self, path: &Path) -> Result<(), crate::Error> {
+        (**self).save_layout(path)
+    }
+
+    fn load_layout(&self, path: &Path) -> Result<(), crate::Error> {
+        (**self).load_layout(path)
+    }
+
+    fn read_layout(&self, path: &Path) -> Result<DisplayState, crate::Error> {
+        (**self).read_layout(path)
+    }
+
+    fn check_and_load_layout(&self, path: &Path) -> Result<bool, crate::Error> {
+        (**self).check_and_load_layout(path)
+    }
+
+    fn load_layout_observed(
+        &self,
+        path: &Path,
+        layout_state: &DisplayState,
+        system_state: &DisplayState,
+    ) -> Result<bool, crate::Error> {
+        (**self).load_layout_observed(path, layout_state, system_state)
+    }
+
+    fn check_and_modify(&self, desired_state: &DisplayState) -> Result<bool, crate::Error> {
+        (**self).check_and_modify(desired_state)
+    }
+
+    fn modify_observed(&self, desired_state: &DisplayState, system_state: &DisplayState) -> Result<bool, crate::Error> {
+        (**self).modify_observed(desired_state, system_state)
+    }
+}
+
+/// The objectives which bring `system_state` to `desired_state`, or None if
+/// it matches already. Shared by the sync and async `modify_observed`.
+pub (crate) fn plan<'a>(
+    desired_state: &'a DisplayState,
+    system_state: &DisplayState,
+) -> Result<Option<DisplayModifierObjectives<'a>>, crate::Error> {
+    tracing::debug!(observed = ?system_state, desired = ?desired_state, "display state");
+    match get_objectives(desired_state, system_state) {
+        Some(objectives) => {
+            check_objectives(&objectives, system_state)?;
+            Ok(Some(objectives))
+        },
+        None => Ok(None),
+    }
+}
+
+pub (crate) fn get_objectives<'a>(
     desired_state: &'a DisplayState,
     system_state: &DisplayState,
 ) -> Option<DisplayModifierObjectives<'a>> {
-    let primary_device_id: &str = match &desired_state.primary_device_id == &system_state.primary_device_id {
+    let primary_device_id: &str = match desired_state.primary_device_id == system_state.primary_device_id {
         true => "",
         false => &desired_state.primary_device_id,
     };
@@ -85,12 +339,168 @@ fn get_objectives<'a>(
         }
     }
 
-    match (primary_device_id.is_empty(), enabled_device_ids.is_empty(), disabled_device_ids.is_empty()) {
-        (true, true, true) => None,
+    // Only displays which are enabled and staying enabled are moved. Displays
+    // being enabled now are moved on the next pass, once they have a position.
+    let mut positions: Vec<DisplayPosition> = Vec::with_capacity(desired_state.positions.len());
+    for position in &desired_state.positions {
+        if system_state.enabled_device_ids.contains(&position.device_id)
+            && !disabled_device_ids.contains(&position.device_id.as_str())
+            && system_state.position(&position.device_id) != Some(position)
+        {
+            positions.push(position.clone());
+        }
+    }
+
+    match (
+        primary_device_id.is_empty(),
+        enabled_device_ids.is_empty(),
+        disabled_device_ids.is_empty(),
+        positions.is_empty(),
+    ) {
+        (true, true, true, true) => None,
         _ => Some(DisplayModifierObjectives {
             primary_device_id: primary_device_id,
             enabled_device_ids: enabled_device_ids,
             disabled_device_ids: disabled_device_ids,
+            positions: positions,
         }),
     }
+}
+
+// Refuses objectives which would leave the system without an enabled display,
+// or with a primary display which is disabled.
+pub (crate) fn check_objectives(
+    objectives: &DisplayModifierObjectives,
+    system_state: &DisplayState,
+) -> Result<(), crate::Error> {
+    let mut enabled_device_ids: Vec<String> = Vec::with_capacity(system_state.enabled_device_ids.len());
+    for device_id in &system_state.enabled_device_ids {
+        if !objectives.disabled_device_ids.contains(&device_id.as_str()) {
+            enabled_device_ids.push(device_id.clone());
+        }
+    }
+    for device_id in &objectives.enabled_device_ids {
+        if !enabled_device_ids.iter().any(|id| id == device_id) {
+            enabled_device_ids.push(device_id.to_string());
+        }
+    }
+    let primary_device_id: String = match objectives.primary_device_id.is_empty() {
+        true => system_state.primary_device_id.clone(),
+        false => objectives.primary_device_id.to_string(),
+    };
+    let primary_touched: bool = !objectives.primary_device_id.is_empty()
+        || objectives.disabled_device_ids.contains(&primary_device_id.as_str());
+
+    let description: &'static str = if enabled_device_ids.is_empty() {
+        "would leave no display enabled"
+    } else if primary_touched && !enabled_device_ids.contains(&primary_device_id) {
+        "would leave the primary display disabled"
+    } else {
+        return Ok(())
+    };
+    Err(crate::Error::DisplaySafetyError(crate::error::DisplaySafetyError {
+        primary_device_id: primary_device_id,
+        enabled_device_ids: enabled_device_ids,
+        description: description,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(primary: &str, enabled: &[&str], disabled: &[&str]) -> DisplayState {
+        DisplayState::from_device_ids(
+            primary.to_string(),
+            enabled.iter().map(|s| s.to_string()).collect(),
+            disabled.iter().map(|s| s.to_string()).collect(),
+        )
+    }
+
+    fn check(desired_state: &DisplayState, system_state: &DisplayState) -> Result<(), crate::Error> {
+        let objectives: DisplayModifierObjectives = get_objectives(desired_state, system_state).unwrap();
+        check_objectives(&objectives, system_state)
+    }
+
+    fn safety_description(result: Result<(), crate::Error>) -> &'static str {
+        match result {
+            Err(crate::Error::DisplaySafetyError(e)) => e.description,
+            r => panic!("expected a DisplaySafetyError, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn refuses_disabling_every_display() {
+        let system_state: DisplayState = state("A", &["A", "B"], &[]);
+        let desired_state: DisplayState = state("", &[], &["A", "B"]);
+        assert_eq!(safety_description(check(&desired_state, &system_state)), "would leave no display enabled");
+    }
+
+    #[test]
+    fn refuses_disabling_the_primary_display() {
+        let system_state: DisplayState = state("A", &["A", "B"], &[]);
+        let desired_state: DisplayState = state("", &[], &["A"]);
+        assert_eq!(safety_description(check(&desired_state, &system_state)), "would leave the primary display disabled");
+    }
+
+    #[test]
+    fn refuses_a_disabled_primary_display() {
+        let system_state: DisplayState = state("A", &["A"], &["B"]);
+        let desired_state: DisplayState = state("B", &[], &[]);
+        assert_eq!(safety_description(check(&desired_state, &system_state)), "would leave the primary display disabled");
+    }
+
+    #[test]
+    fn allows_moving_the_primary_to_an_enabled_display() {
+        let system_state: DisplayState = state("A", &["A"], &["B"]);
+        let desired_state: DisplayState = state("B", &["B"], &["A"]);
+        assert!(check(&desired_state, &system_state).is_ok());
+    }
+
+    #[test]
+    fn allows_disabling_a_secondary_display() {
+        let system_state: DisplayState = state("A", &["A", "B"], &[]);
+        let desired_state: DisplayState = state("", &[], &["B"]);
+        assert!(check(&desired_state, &system_state).is_ok());
+    }
+
+    #[test]
+    fn matching_state_has_no_objectives() {
+        let system_state: DisplayState = state("A", &["A"], &["B"]);
+        let desired_state: DisplayState = state("A", &["A"], &["B"]);
+        assert!(get_objectives(&desired_state, &system_state).is_none());
+    }
+
+    #[test]
+    fn plans_enabling_before_disabling() {
+        let system_state: DisplayState = state("A", &["A"], &["B"]);
+        let mut desired_state: DisplayState = state("B", &["B"], &["A"]);
+        desired_state.with_positions(vec![DisplayPosition::new("A".to_string(), 0, 0)]);
+        let objectives: DisplayModifierObjectives = plan(&desired_state, &system_state).unwrap().unwrap();
+        assert_eq!(objectives.actions(), vec![
+            DisplayAction::EnableMonitors(&["B"]),
+            DisplayAction::DisableMonitors(&["A"]),
+            DisplayAction::SetPrimaryDevice("B"),
+        ]);
+        assert!(plan(&system_state, &system_state).unwrap().is_none());
+        assert!(matches!(plan(&state("", &[], &["A"]), &system_state), Err(crate::Error::DisplaySafetyError(_))));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_through_serde() {
+        let mut display_state: DisplayState = state("A", &["A", "B"], &["C"]);
+        display_state.with_positions(vec![DisplayPosition::new("B".to_string(), -1920, 0)]);
+        let json: serde_json::Value = serde_json::to_value(&display_state).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "primary_device_id": "A",
+            "enabled_device_ids": ["A", "B"],
+            "disabled_device_ids": ["C"],
+            "positions": [{ "device_id": "B", "x": -1920, "y": 0 }],
+        }));
+        assert_eq!(serde_json::from_value::<DisplayState>(json).unwrap(), display_state);
+        // Missing fields are left empty
+        let partial: DisplayState = serde_json::from_str(r#"{"primary_device_id": "A"}"#).unwrap();
+        assert_eq!(partial, state("A", &[], &[]));
+    }
 }
\ No newline at end of file
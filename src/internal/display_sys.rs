@@ -1,20 +1,30 @@
-use super::display::{DisplayState, DisplayModifier};
-use crate::error::MMTParseError;
+use super::display::{DisplayState, DisplayPosition, DisplayModifier};
+use super::command::{traced_command, ExitCodes};
+#[cfg(feature = "async")]
+use super::command::traced_command_async;
+use super::command_runner::{CommandRunner, CommandSpec, ProcessRunner};
+use super::checksum::verify_sha256;
+use crate::error::{MMTParseError, MMTLayoutParseError};
 
 use std::path::{Path, PathBuf};
-use std::fs::File;
+use std::fs::{self, File};
 use std::process;
+use std::sync::Arc;
 
 use tempfile::TempDir;
 use csv;
 
+/// Display backend driving MultiMonitorTool.
 #[derive(Debug)]
 pub struct MMTModifier {
     pub (crate) exe_path: PathBuf,
+    pub (crate) runner: Arc<dyn CommandRunner>,
+    pub (crate) sha256: Option<String>,
 }
 
 #[derive(Debug)]
 struct ParsedRecord {
+    position: (i32, i32),
     enabled: bool,
     primary: bool,
     id: String,
@@ -26,33 +36,120 @@ impl DisplayModifier for MMTModifier {
     }
 
     fn get_system_state(&self) -> Result<DisplayState, crate::Error> {
-        get_system_state(&self.exe_path)
+        get_system_state(&*self.runner, self.checked_exe_path()?)
     }
 
     fn enable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
-        enable_monitors(&self.exe_path, device_ids)
+        enable_monitors(&*self.runner, self.checked_exe_path()?, device_ids)
     }
 
     fn disable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
-        disable_monitors(&self.exe_path, device_ids)
+        disable_monitors(&*self.runner, self.checked_exe_path()?, device_ids)
     }
 
     fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
-        set_primary_device(&self.exe_path, id)
+        set_primary_device(&*self.runner, self.checked_exe_path()?, id)
+    }
+
+    fn set_positions(&self, positions: &[DisplayPosition]) -> Result<(), crate::Error> {
+        set_positions(&*self.runner, self.checked_exe_path()?, positions)
+    }
+
+    fn save_layout(&self, path: &Path) -> Result<(), crate::Error> {
+        mmt_run(&*self.runner, self.checked_exe_path()?, "/SaveConfig", &[&path.to_string_lossy()]).map(|_| ())
+    }
+
+    fn load_layout(&self, path: &Path) -> Result<(), crate::Error> {
+        mmt_run(&*self.runner, self.checked_exe_path()?, "/LoadConfig", &[&path.to_string_lossy()]).map(|_| ())
+    }
+
+    fn read_layout(&self, path: &Path) -> Result<DisplayState, crate::Error> {
+        let layout_str: String = fs::read_to_string(path).map_err(|e| crate::Error::LayoutIoError(e))?;
+        parse_layout(&layout_str)
+    }
+}
+
+#[cfg(feature = "async")]
+impl super::async_modifier::AsyncDisplayModifier for MMTModifier {
+    async fn get_system_state(&self) -> Result<DisplayState, crate::Error> {
+        let temp_dir: TempDir = TempDir::new().map_err(|e| crate::Error::TempDirError(e))?;
+        let csv_outfile_path: PathBuf = temp_dir.path().join("d.csv");
+        mmt_run_async(&*self.runner, self.checked_exe_path()?, "/scomma", &[&csv_outfile_path.to_string_lossy()]).await?;
+        read_csv(&csv_outfile_path)
+    }
+
+    async fn enable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        mmt_run_async(&*self.runner, self.checked_exe_path()?, "/enable", device_ids).await.map(|_| ())
+    }
+
+    async fn disable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        mmt_run_async(&*self.runner, self.checked_exe_path()?, "/disable", device_ids).await.map(|_| ())
+    }
+
+    async fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        mmt_run_async(&*self.runner, self.checked_exe_path()?, "/SetPrimary", &[id]).await.map(|_| ())
+    }
+
+    async fn set_positions(&self, positions: &[DisplayPosition]) -> Result<(), crate::Error> {
+        let monitor_args: Vec<String> = monitor_args(positions);
+        let monitor_args: Vec<&str> = monitor_args.iter().map(|s| s.as_str()).collect();
+        mmt_run_async(&*self.runner, self.checked_exe_path()?, "/SetMonitors", &monitor_args).await.map(|_| ())
+    }
+
+    async fn load_layout(&self, path: &Path) -> Result<(), crate::Error> {
+        mmt_run_async(&*self.runner, self.checked_exe_path()?, "/LoadConfig", &[&path.to_string_lossy()]).await.map(|_| ())
+    }
+
+    async fn read_layout(&self, path: &Path) -> Result<DisplayState, crate::Error> {
+        DisplayModifier::read_layout(self, path)
     }
 }
 
 impl MMTModifier {
     pub fn new(exe_path: std::path::PathBuf) -> Self {
-        MMTModifier { exe_path: exe_path }
+        MMTModifier { exe_path: exe_path, runner: Arc::new(ProcessRunner::new()), sha256: None }
+    }
+
+    pub fn with_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.runner = runner
+    }
+
+    /// Refuses to run MultiMonitorTool unless its SHA-256, in hex, is `sha256`.
+    /// The file is checked before every run, not only once.
+    pub fn with_sha256(&mut self, sha256: String) {
+        self.sha256 = Some(sha256)
+    }
+
+    // The executable, once its checksum is verified
+    fn checked_exe_path(&self) -> Result<&Path, crate::Error> {
+        if let Some(s) = &self.sha256 {
+            verify_sha256(&self.exe_path, s)?;
+        }
+        Ok(&self.exe_path)
     }
 }
 
-fn scomma(exe_path: &Path, csv_outfile_path: &Path) -> Result<(), crate::Error> {
-    mmt_run(exe_path, "/scomma", &[&csv_outfile_path.to_string_lossy()]).map(|_| ())
+fn scomma(runner: &dyn CommandRunner, exe_path: &Path, csv_outfile_path: &Path) -> Result<(), crate::Error> {
+    mmt_run(runner, exe_path, "/scomma", &[&csv_outfile_path.to_string_lossy()]).map(|_| ())
 }
 
 fn parse_csv_record(record: csv::StringRecord) -> Result<ParsedRecord, crate::Error> {
+    // "Left - Top", e.g. "-1920, 0"
+    let position: (i32, i32) = match record.get(1).and_then(|s| s.split_once(',')) {
+        Some((x, y)) => match (x.trim().parse::<i32>(), y.trim().parse::<i32>()) {
+            (Ok(x), Ok(y)) => (x, y),
+            _ => return Err(crate::Error::MMTParseError(MMTParseError {
+                output: record,
+                index: 1,
+                description: "should contain integer left and top coordinates",
+            })),
+        },
+        None => return Err(crate::Error::MMTParseError(MMTParseError {
+            output: record,
+            index: 1,
+            description: "should contain left and top coordinates separated by a comma",
+        })),
+    };
     let enabled: bool = match record.get(3) {
         Some("Yes") => true,
         Some("No") => false,
@@ -80,6 +177,7 @@ fn parse_csv_record(record: csv::StringRecord) -> Result<ParsedRecord, crate::Er
         })),
     };
     Ok(ParsedRecord {
+        position: position,
         enabled: enabled,
         primary: primary,
         id: id,
@@ -87,14 +185,18 @@ fn parse_csv_record(record: csv::StringRecord) -> Result<ParsedRecord, crate::Er
 }
 
 fn parse_csv_reader(reader: csv::Reader<File>) -> Result<DisplayState, crate::Error> {
-    let mut display_state: DisplayState = DisplayState {
-        primary_device_id: String::new(),
-        enabled_device_ids: Vec::new(),
-        disabled_device_ids: Vec::new(),
-    };
+    let mut display_state: DisplayState = DisplayState::new();
     for record in reader.into_records() {
         let record: csv::StringRecord = record.map_err(|e| crate::Error::CsvError(e))?;
         let parsed_record: ParsedRecord = parse_csv_record(record)?;
+        // Disabled monitors report a stale position
+        if parsed_record.enabled {
+            display_state.positions.push(DisplayPosition {
+                device_id: parsed_record.id.clone(),
+                x: parsed_record.position.0,
+                y: parsed_record.position.1,
+            });
+        }
         match (parsed_record.primary, parsed_record.enabled) {
             (false, false) => display_state.disabled_device_ids.push(parsed_record.id),
             (false, true) => display_state.enabled_device_ids.push(parsed_record.id),
@@ -111,32 +213,216 @@ fn parse_csv_reader(reader: csv::Reader<File>) -> Result<DisplayState, crate::Er
     Ok(display_state)
 }
 
-fn mmt_run(exe_path: &Path, arg: &str, extra_args: &[&str]) -> Result<process::Output, crate::Error> {
-    process::Command::new(exe_path).arg(arg)
-        .args(extra_args)
-        .stdin(process::Stdio::piped())
-        .stdout(process::Stdio::piped())
-        .stderr(process::Stdio::piped())
-        .output()
-        .map_err(|e| crate::Error::CommandError(e))
+fn mmt_command(exe_path: &Path, arg: &str, extra_args: &[&str]) -> CommandSpec {
+    let mut args: Vec<&str> = vec![arg];
+    args.extend(extra_args);
+    CommandSpec::new(exe_path.to_path_buf(), &args)
+}
+
+fn mmt_run(runner: &dyn CommandRunner, exe_path: &Path, arg: &str, extra_args: &[&str]) -> Result<process::Output, crate::Error> {
+    traced_command(runner, &mmt_command(exe_path, arg, extra_args), ExitCodes::Zero)
 }
 
-fn get_system_state(exe_path: &Path) -> Result<DisplayState, crate::Error> {
+#[cfg(feature = "async")]
+async fn mmt_run_async(runner: &dyn CommandRunner, exe_path: &Path, arg: &str, extra_args: &[&str]) -> Result<process::Output, crate::Error> {
+    traced_command_async(runner, &mmt_command(exe_path, arg, extra_args), ExitCodes::Zero).await
+}
+
+fn get_system_state(runner: &dyn CommandRunner, exe_path: &Path) -> Result<DisplayState, crate::Error> {
     let temp_dir: TempDir = TempDir::new().map_err(|e| crate::Error::TempDirError(e))?;
     let csv_outfile_path: PathBuf = temp_dir.path().join("d.csv");
-    scomma(exe_path, &csv_outfile_path)?;
-    let reader: csv::Reader<File> = csv::Reader::from_path(csv_outfile_path).map_err(|e| crate::Error::CsvError(e))?;
+    scomma(runner, exe_path, &csv_outfile_path)?;
+    read_csv(&csv_outfile_path)
+}
+
+fn read_csv(csv_path: &Path) -> Result<DisplayState, crate::Error> {
+    let reader: csv::Reader<File> = csv::Reader::from_path(csv_path).map_err(|e| crate::Error::CsvError(e))?;
     parse_csv_reader(reader)
 }
 
-fn enable_monitors(exe_path: &Path, device_ids: &[&str]) -> Result<(), crate::Error> {
-    mmt_run(exe_path, "/enable", device_ids).map(|_| ())
+fn enable_monitors(runner: &dyn CommandRunner, exe_path: &Path, device_ids: &[&str]) -> Result<(), crate::Error> {
+    mmt_run(runner, exe_path, "/enable", device_ids).map(|_| ())
+}
+
+fn disable_monitors(runner: &dyn CommandRunner, exe_path: &Path, device_ids: &[&str]) -> Result<(), crate::Error> {
+    mmt_run(runner, exe_path, "/disable", device_ids).map(|_| ())
+}
+
+fn set_primary_device(runner: &dyn CommandRunner, exe_path: &Path, id: &str) -> Result<(), crate::Error> {
+    mmt_run(runner, exe_path, "/SetPrimary", &[id]).map(|_| ())
+}
+
+// Every monitor is moved in one /SetMonitors call so that Windows lays them
+// out together, rather than shifting the others after each move.
+fn set_positions(runner: &dyn CommandRunner, exe_path: &Path, positions: &[DisplayPosition]) -> Result<(), crate::Error> {
+    let monitor_args: Vec<String> = monitor_args(positions);
+    let monitor_args: Vec<&str> = monitor_args.iter().map(|s| s.as_str()).collect();
+    mmt_run(runner, exe_path, "/SetMonitors", &monitor_args).map(|_| ())
+}
+
+fn monitor_args(positions: &[DisplayPosition]) -> Vec<String> {
+    positions.iter()
+        .map(|p| format!("Name={} PositionX={} PositionY={}", p.device_id, p.x, p.y))
+        .collect()
+}
+
+// Parses a /SaveConfig file, one section per monitor:
+//
+// [Monitor0]
+// Name=\\.\DISPLAY1
+// MonitorID=MONITOR\GSM5B08\{4d36e96e-e325-11ce-bfc1-08002be10318}\0001
+// BitsPerPixel=32
+// Width=2560
+// Height=1440
+// DisplayFrequency=144
+// PositionX=0
+// PositionY=0
+//
+// MonitorID is the same ID /scomma reports. Disabled monitors are saved with
+// no size, and the primary monitor is the enabled one at 0, 0.
+fn parse_layout(layout_str: &str) -> Result<DisplayState, crate::Error> {
+    let mut sections: Vec<(usize, Vec<(&str, &str)>)> = Vec::new();
+    for (i, line) in layout_str.lines().enumerate() {
+        let line: &str = line.trim().trim_start_matches('\u{feff}');
+        if line.is_empty() || line.starts_with(';') {
+            continue
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            sections.push((i + 1, Vec::new()));
+            continue
+        }
+        match (sections.last_mut(), line.split_once('=')) {
+            (Some((_, properties)), Some((key, value))) => properties.push((key.trim(), value.trim())),
+            _ => return Err(layout_error(layout_str, i + 1, "should be a [Monitor] section or a Key=Value property in one")),
+        }
+    }
+    let mut display_state: DisplayState = DisplayState::new();
+    for (line_number, properties) in sections {
+        let property = |key: &str| properties.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+        let integer = |key: &str| property(key).and_then(|v| v.parse::<i32>().ok());
+        let id: String = match property("MonitorID") {
+            Some(id) if !id.is_empty() => id.to_string(),
+            _ => return Err(layout_error(layout_str, line_number, "monitor should have a MonitorID")),
+        };
+        match (integer("Width"), integer("Height"), integer("PositionX"), integer("PositionY")) {
+            (Some(0), _, _, _) | (_, Some(0), _, _) => display_state.disabled_device_ids.push(id),
+            (Some(_), Some(_), Some(x), Some(y)) => {
+                if x == 0 && y == 0 {
+                    display_state.primary_device_id = id.clone();
+                }
+                display_state.positions.push(DisplayPosition::new(id.clone(), x, y));
+                display_state.enabled_device_ids.push(id);
+            },
+            _ => return Err(layout_error(layout_str, line_number, "monitor should have integer Width, Height, PositionX and PositionY")),
+        }
+    }
+    Ok(display_state)
 }
 
-fn disable_monitors(exe_path: &Path, device_ids: &[&str]) -> Result<(), crate::Error> {
-    mmt_run(exe_path, "/disable", device_ids).map(|_| ())
+fn layout_error(layout_str: &str, line_number: usize, description: &'static str) -> crate::Error {
+    crate::Error::MMTLayoutParseError(MMTLayoutParseError {
+        output: layout_str.to_string(),
+        line: Some(line_number),
+        description: description,
+    })
 }
 
-fn set_primary_device(exe_path: &Path, id: &str) -> Result<(), crate::Error> {
-    mmt_run(exe_path, "/SetPrimary", &[id]).map(|_| ())
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::command_runner::{ReplayRunner, CommandRecord, CommandOutcome};
+
+    fn mmt(args: &[&str]) -> CommandRecord {
+        CommandRecord {
+            command: CommandSpec::new(PathBuf::from("MultiMonitorTool.exe"), args),
+            outcome: CommandOutcome::Exited { exit_code: 0, stdout: Vec::new(), stderr: Vec::new() },
+        }
+    }
+
+    fn mmt_modifier(records: Vec<CommandRecord>) -> (MMTModifier, Arc<ReplayRunner>) {
+        let runner: Arc<ReplayRunner> = Arc::new(ReplayRunner::new(records));
+        let mut mmt_modifier: MMTModifier = MMTModifier::new(PathBuf::from("MultiMonitorTool.exe"));
+        mmt_modifier.with_runner(runner.clone());
+        (mmt_modifier, runner)
+    }
+
+    #[test]
+    fn saves_and_loads_layouts() {
+        let path: PathBuf = PathBuf::from("layouts").join("tv.evening.cfg");
+        let path_str: String = path.to_string_lossy().into_owned();
+        let (mmt_modifier, runner) = mmt_modifier(vec![
+            mmt(&["/SaveConfig", &path_str]),
+            mmt(&["/LoadConfig", &path_str]),
+        ]);
+        mmt_modifier.save_layout(&path).unwrap();
+        mmt_modifier.load_layout(&path).unwrap();
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn loads_layout_only_if_needed() {
+        let path: PathBuf = PathBuf::from("tv.cfg");
+        let desk: DisplayState = DisplayState::from_device_ids(
+            "\\\\.\\DISPLAY1".to_string(),
+            vec!["\\\\.\\DISPLAY1".to_string()],
+            vec!["\\\\.\\DISPLAY2".to_string()],
+        );
+        let tv: DisplayState = DisplayState::from_device_ids(
+            "\\\\.\\DISPLAY2".to_string(),
+            vec!["\\\\.\\DISPLAY2".to_string()],
+            vec!["\\\\.\\DISPLAY1".to_string()],
+        );
+        let (mmt_modifier, runner) = mmt_modifier(vec![mmt(&["/LoadConfig", "tv.cfg"])]);
+        assert!(!mmt_modifier.load_layout_observed(&path, &tv, &tv).unwrap());
+        assert!(mmt_modifier.load_layout_observed(&path, &tv, &desk).unwrap());
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn verifies_checksum_before_every_run() {
+        let dir: TempDir = TempDir::new().unwrap();
+        let exe_path: PathBuf = dir.path().join("MultiMonitorTool.exe");
+        fs::write(&exe_path, b"MZ original").unwrap();
+        let runner: Arc<ReplayRunner> = Arc::new(ReplayRunner::new(vec![CommandRecord {
+            command: CommandSpec::new(exe_path.clone(), &["/SetPrimary", "\\\\.\\DISPLAY1"]),
+            outcome: CommandOutcome::Exited { exit_code: 0, stdout: Vec::new(), stderr: Vec::new() },
+        }]));
+        let mut mmt_modifier: MMTModifier = MMTModifier::new(exe_path.clone());
+        mmt_modifier.with_runner(runner.clone());
+        mmt_modifier.with_sha256(crate::internal::checksum::sha256_hex(b"MZ original").to_ascii_uppercase());
+        mmt_modifier.set_primary_device("\\\\.\\DISPLAY1").unwrap();
+        fs::write(&exe_path, b"MZ replaced").unwrap();
+        match mmt_modifier.load_layout(Path::new("tv.cfg")) {
+            Err(crate::Error::ChecksumMismatch(e)) => assert_eq!(e.path(), exe_path),
+            r => panic!("expected ChecksumMismatch, got {r:?}"),
+        }
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn modifies_observed_state_async() {
+        use crate::internal::async_modifier::AsyncDisplayModifier;
+        let desk: DisplayState = DisplayState::from_device_ids(
+            "\\\\.\\DISPLAY1".to_string(),
+            vec!["\\\\.\\DISPLAY1".to_string()],
+            vec!["\\\\.\\DISPLAY2".to_string()],
+        );
+        let tv: DisplayState = DisplayState::from_device_ids(
+            "\\\\.\\DISPLAY2".to_string(),
+            vec!["\\\\.\\DISPLAY2".to_string()],
+            vec!["\\\\.\\DISPLAY1".to_string()],
+        );
+        let (mmt_modifier, runner) = mmt_modifier(vec![
+            mmt(&["/enable", "\\\\.\\DISPLAY2"]),
+            mmt(&["/disable", "\\\\.\\DISPLAY1"]),
+            mmt(&["/SetPrimary", "\\\\.\\DISPLAY2"]),
+            mmt(&["/LoadConfig", "tv.cfg"]),
+        ]);
+        assert!(!AsyncDisplayModifier::modify_observed(&mmt_modifier, &tv, &tv).await.unwrap());
+        assert!(AsyncDisplayModifier::modify_observed(&mmt_modifier, &tv, &desk).await.unwrap());
+        assert!(AsyncDisplayModifier::load_layout_observed(&mmt_modifier, Path::new("tv.cfg"), &tv, &desk).await.unwrap());
+        assert!(runner.remaining().is_empty());
+    }
 }
\ No newline at end of file
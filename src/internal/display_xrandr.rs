@@ -0,0 +1,277 @@
+use super::display::{DisplayState, DisplayPosition, DisplayModifier};
+use super::command::{traced_command, ExitCodes};
+#[cfg(feature = "async")]
+use super::command::traced_command_async;
+use super::command_runner::{CommandRunner, CommandSpec, ProcessRunner};
+use crate::error::XrandrParseError;
+
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Display backend driving xrandr. Device IDs are xrandr output names.
+#[derive(Debug)]
+pub struct XrandrModifier {
+    pub (crate) exe_path: PathBuf,
+    pub (crate) runner: Arc<dyn CommandRunner>,
+}
+
+#[derive(Debug)]
+struct ParsedOutput {
+    position: Option<(i32, i32)>,
+    enabled: bool,
+    primary: bool,
+    id: String,
+}
+
+impl DisplayModifier for XrandrModifier {
+    fn get_id_readout(&self) -> Result<String, crate::Error> {
+        let mut readout: String = String::new();
+        for parsed_output in get_outputs(&*self.runner, &self.exe_path)? {
+            readout.push_str(&format!(
+                "{} enabled: {} primary: {}\n",
+                parsed_output.id,
+                parsed_output.enabled,
+                parsed_output.primary,
+            ));
+        }
+        Ok(readout)
+    }
+
+    fn get_system_state(&self) -> Result<DisplayState, crate::Error> {
+        Ok(display_state(get_outputs(&*self.runner, &self.exe_path)?))
+    }
+
+    fn enable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        xrandr_run(&*self.runner, &self.exe_path, &output_args(device_ids, &["--auto"])).map(|_| ())
+    }
+
+    fn disable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        xrandr_run(&*self.runner, &self.exe_path, &output_args(device_ids, &["--off"])).map(|_| ())
+    }
+
+    fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        xrandr_run(&*self.runner, &self.exe_path, &["--output", id, "--primary"]).map(|_| ())
+    }
+
+    fn set_positions(&self, positions: &[DisplayPosition]) -> Result<(), crate::Error> {
+        let pos_strs: Vec<String> = positions.iter().map(|p| format!("{}x{}", p.x, p.y)).collect();
+        let mut args: Vec<&str> = Vec::with_capacity(positions.len() * 4);
+        for (position, pos_str) in positions.iter().zip(&pos_strs) {
+            args.extend(["--output", &position.device_id, "--pos", pos_str]);
+        }
+        xrandr_run(&*self.runner, &self.exe_path, &args).map(|_| ())
+    }
+}
+
+#[cfg(feature = "async")]
+impl super::async_modifier::AsyncDisplayModifier for XrandrModifier {
+    async fn get_system_state(&self) -> Result<DisplayState, crate::Error> {
+        let output: process::Output = xrandr_run_async(&*self.runner, &self.exe_path, &["--query"]).await?;
+        Ok(display_state(parse_query(&String::from_utf8_lossy(&output.stdout))?))
+    }
+
+    async fn enable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        xrandr_run_async(&*self.runner, &self.exe_path, &output_args(device_ids, &["--auto"])).await.map(|_| ())
+    }
+
+    async fn disable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        xrandr_run_async(&*self.runner, &self.exe_path, &output_args(device_ids, &["--off"])).await.map(|_| ())
+    }
+
+    async fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        xrandr_run_async(&*self.runner, &self.exe_path, &["--output", id, "--primary"]).await.map(|_| ())
+    }
+
+    async fn set_positions(&self, positions: &[DisplayPosition]) -> Result<(), crate::Error> {
+        let pos_strs: Vec<String> = positions.iter().map(|p| format!("{}x{}", p.x, p.y)).collect();
+        let mut args: Vec<&str> = Vec::with_capacity(positions.len() * 4);
+        for (position, pos_str) in positions.iter().zip(&pos_strs) {
+            args.extend(["--output", &position.device_id, "--pos", pos_str]);
+        }
+        xrandr_run_async(&*self.runner, &self.exe_path, &args).await.map(|_| ())
+    }
+}
+
+impl XrandrModifier {
+    pub fn new(exe_path: std::path::PathBuf) -> Self {
+        XrandrModifier { exe_path: exe_path, runner: Arc::new(ProcessRunner::new()) }
+    }
+
+    pub fn with_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.runner = runner
+    }
+}
+
+fn output_args<'a>(device_ids: &[&'a str], extra_args: &[&'a str]) -> Vec<&'a str> {
+    let mut args: Vec<&str> = Vec::with_capacity(device_ids.len() * (extra_args.len() + 2));
+    for device_id in device_ids {
+        args.push("--output");
+        args.push(device_id);
+        args.extend(extra_args);
+    }
+    args
+}
+
+fn xrandr_run(runner: &dyn CommandRunner, exe_path: &std::path::Path, args: &[&str]) -> Result<process::Output, crate::Error> {
+    traced_command(runner, &CommandSpec::new(exe_path.to_path_buf(), args), ExitCodes::Zero)
+}
+
+#[cfg(feature = "async")]
+async fn xrandr_run_async(runner: &dyn CommandRunner, exe_path: &std::path::Path, args: &[&str]) -> Result<process::Output, crate::Error> {
+    traced_command_async(runner, &CommandSpec::new(exe_path.to_path_buf(), args), ExitCodes::Zero).await
+}
+
+fn get_outputs(runner: &dyn CommandRunner, exe_path: &std::path::Path) -> Result<Vec<ParsedOutput>, crate::Error> {
+    let output: process::Output = xrandr_run(runner, exe_path, &["--query"])?;
+    parse_query(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn display_state(parsed_outputs: Vec<ParsedOutput>) -> DisplayState {
+    let mut display_state: DisplayState = DisplayState::new();
+    for parsed_output in parsed_outputs {
+        if parsed_output.primary {
+            display_state.primary_device_id = parsed_output.id.clone();
+        }
+        if let Some((x, y)) = parsed_output.position {
+            display_state.positions.push(DisplayPosition::new(parsed_output.id.clone(), x, y));
+        }
+        match parsed_output.enabled {
+            true => display_state.enabled_device_ids.push(parsed_output.id),
+            false => display_state.disabled_device_ids.push(parsed_output.id),
+        }
+    }
+    display_state
+}
+
+// Only connected outputs are reported. An output is enabled if it has a
+// geometry, e.g. "HDMI-1 connected primary 1920x1080+0+0 ...".
+fn parse_query(stdout_str: &str) -> Result<Vec<ParsedOutput>, crate::Error> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"^(\S+) connected( primary)? ?(\d+x\d+\+(-?\d+)\+(-?\d+))?",
+        ).unwrap();
+    }
+    if !stdout_str.starts_with("Screen ") {
+        return Err(crate::Error::XrandrParseError(XrandrParseError {
+            output: stdout_str.to_string(),
+            description: "should start with a Screen line",
+        }))
+    }
+    Ok(stdout_str.lines()
+        .filter_map(|line| RE.captures(line))
+        .map(|captures| ParsedOutput {
+            position: match (captures.get(4), captures.get(5)) {
+                (Some(x), Some(y)) => Some((x.as_str().parse().unwrap(), y.as_str().parse().unwrap())),
+                _ => None,
+            },
+            enabled: captures.get(3).is_some(),
+            primary: captures.get(2).is_some(),
+            id: captures[1].to_string(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::command_runner::{ReplayRunner, CommandRecord, CommandOutcome};
+
+    static QUERY: &str = "\
+Screen 0: minimum 8 x 8, current 3840 x 1080, maximum 32767 x 32767
+eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 194mm
+   1920x1080     60.01*+
+HDMI-1 connected (normal left inverted right x axis y axis)
+   3840x2160     60.00 +
+DP-1 disconnected (normal left inverted right x axis y axis)
+DP-2 connected 1920x1080+1920+0 (normal left inverted right x axis y axis) 527mm x 296mm
+   1920x1080     60.00*+
+";
+
+    fn xrandr(args: &[&str], stdout: &str) -> CommandRecord {
+        CommandRecord {
+            command: CommandSpec::new(PathBuf::from("xrandr"), args),
+            outcome: CommandOutcome::Exited { exit_code: 0, stdout: stdout.as_bytes().to_vec(), stderr: Vec::new() },
+        }
+    }
+
+    fn xrandr_modifier(records: Vec<CommandRecord>) -> (XrandrModifier, Arc<ReplayRunner>) {
+        let runner: Arc<ReplayRunner> = Arc::new(ReplayRunner::new(records));
+        let mut xrandr_modifier: XrandrModifier = XrandrModifier::new(PathBuf::from("xrandr"));
+        xrandr_modifier.with_runner(runner.clone());
+        (xrandr_modifier, runner)
+    }
+
+    #[test]
+    fn reads_connected_outputs() {
+        let (xrandr_modifier, runner) = xrandr_modifier(vec![xrandr(&["--query"], QUERY)]);
+        let mut expected: DisplayState = DisplayState::from_device_ids(
+            "eDP-1".to_string(),
+            vec!["eDP-1".to_string(), "DP-2".to_string()],
+            vec!["HDMI-1".to_string()],
+        );
+        expected.with_positions(vec![
+            DisplayPosition::new("eDP-1".to_string(), 0, 0),
+            DisplayPosition::new("DP-2".to_string(), 1920, 0),
+        ]);
+        assert_eq!(xrandr_modifier.get_system_state().unwrap(), expected);
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn refuses_output_without_screen_line() {
+        let (xrandr_modifier, _) = xrandr_modifier(vec![xrandr(&["--query"], "Can't open display\n")]);
+        assert!(matches!(xrandr_modifier.get_system_state(), Err(crate::Error::XrandrParseError(_))));
+    }
+
+    #[test]
+    fn issues_only_needed_commands() {
+        let (xrandr_modifier, runner) = xrandr_modifier(vec![
+            xrandr(&["--query"], QUERY),
+            xrandr(&["--output", "HDMI-1", "--auto"], ""),
+            xrandr(&["--output", "eDP-1", "--off"], ""),
+            xrandr(&["--output", "HDMI-1", "--primary"], ""),
+            xrandr(&["--output", "DP-2", "--pos", "0x0"], ""),
+        ]);
+        let mut desired_state: DisplayState = DisplayState::from_device_ids(
+            "HDMI-1".to_string(),
+            vec!["HDMI-1".to_string(), "DP-2".to_string()],
+            vec!["eDP-1".to_string()],
+        );
+        desired_state.with_positions(vec![DisplayPosition::new("DP-2".to_string(), 0, 0)]);
+        assert!(xrandr_modifier.check_and_modify(&desired_state).unwrap());
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn matching_state_issues_no_commands() {
+        let (xrandr_modifier, runner) = xrandr_modifier(vec![xrandr(&["--query"], QUERY)]);
+        let desired_state: DisplayState = DisplayState::from_device_ids("eDP-1".to_string(), vec!["DP-2".to_string()], Vec::new());
+        assert!(!xrandr_modifier.check_and_modify(&desired_state).unwrap());
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn issues_only_needed_commands_async() {
+        use crate::internal::async_modifier::AsyncDisplayModifier;
+        let (xrandr_modifier, runner) = xrandr_modifier(vec![
+            xrandr(&["--query"], QUERY),
+            xrandr(&["--output", "HDMI-1", "--auto"], ""),
+            xrandr(&["--output", "eDP-1", "--off"], ""),
+            xrandr(&["--output", "HDMI-1", "--primary"], ""),
+            xrandr(&["--output", "DP-2", "--pos", "0x0"], ""),
+        ]);
+        let mut desired_state: DisplayState = DisplayState::from_device_ids(
+            "HDMI-1".to_string(),
+            vec!["HDMI-1".to_string(), "DP-2".to_string()],
+            vec!["eDP-1".to_string()],
+        );
+        desired_state.with_positions(vec![DisplayPosition::new("DP-2".to_string(), 0, 0)]);
+        assert!(AsyncDisplayModifier::check_and_modify(&xrandr_modifier, &desired_state).await.unwrap());
+        assert!(runner.remaining().is_empty());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,176 @@
+use super::display::{DisplayState, DisplayPosition, DisplayModifier};
+use super::audio::{AudioState, AudioModifier};
+use super::steam::{SteamState, SteamModifier};
+
+use std::sync::Mutex;
+
+/// In-memory display backend. Actions change its state directly.
+#[derive(Debug)]
+pub struct MockDisplayModifier {
+    pub (crate) state: Mutex<DisplayState>,
+}
+
+/// In-memory audio backend. Actions change its state directly.
+#[derive(Debug)]
+pub struct MockAudioModifier {
+    pub (crate) state: Mutex<AudioState>,
+    pub (crate) device_ids: Vec<String>,
+}
+
+/// In-memory Steam backend. Actions change its state directly.
+#[derive(Debug)]
+pub struct MockSteamModifier {
+    pub (crate) state: Mutex<(SteamState, Option<u32>)>,
+}
+
+impl MockDisplayModifier {
+    pub fn new(state: DisplayState) -> Self {
+        MockDisplayModifier { state: Mutex::new(state) }
+    }
+
+    pub fn state(&self) -> DisplayState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+impl MockAudioModifier {
+    pub fn new(state: AudioState, device_ids: Vec<String>) -> Self {
+        MockAudioModifier { state: Mutex::new(state), device_ids: device_ids }
+    }
+
+    pub fn state(&self) -> AudioState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+impl MockSteamModifier {
+    pub fn new(state: SteamState) -> Self {
+        MockSteamModifier { state: Mutex::new((state, None)) }
+    }
+
+    pub fn state(&self) -> SteamState {
+        self.state.lock().unwrap().0
+    }
+
+    pub fn running_app(&self) -> Option<u32> {
+        self.state.lock().unwrap().1
+    }
+}
+
+impl DisplayModifier for MockDisplayModifier {
+    fn get_id_readout(&self) -> Result<String, crate::Error> {
+        Ok(format!("{:?}", self.state()))
+    }
+
+    fn get_system_state(&self) -> Result<DisplayState, crate::Error> {
+        Ok(self.state())
+    }
+
+    fn enable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        let mut state = self.state.lock().unwrap();
+        for device_id in device_ids {
+            state.disabled_device_ids.retain(|id| id != device_id);
+            state.enabled_device_ids.push(device_id.to_string());
+        }
+        Ok(())
+    }
+
+    fn disable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+        let mut state = self.state.lock().unwrap();
+        for device_id in device_ids {
+            state.enabled_device_ids.retain(|id| id != device_id);
+            state.disabled_device_ids.push(device_id.to_string());
+            state.positions.retain(|p| p.device_id != *device_id);
+        }
+        Ok(())
+    }
+
+    fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        self.state.lock().unwrap().primary_device_id = id.to_string();
+        Ok(())
+    }
+
+    fn set_positions(&self, positions: &[DisplayPosition]) -> Result<(), crate::Error> {
+        let mut state = self.state.lock().unwrap();
+        for position in positions {
+            state.positions.retain(|p| p.device_id != position.device_id);
+            state.positions.push(position.clone());
+        }
+        Ok(())
+    }
+}
+
+impl AudioModifier for MockAudioModifier {
+    fn get_id_readout(&self) -> Result<String, crate::Error> {
+        Ok(self.device_ids.join("\n"))
+    }
+
+    fn get_system_state(&self) -> Result<AudioState, crate::Error> {
+        Ok(self.state())
+    }
+
+    fn get_device_ids(&self) -> Result<Vec<String>, crate::Error> {
+        Ok(self.device_ids.clone())
+    }
+
+    fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+        self.state.lock().unwrap().primary_device_id = id.to_string();
+        Ok(())
+    }
+
+    fn set_volume(&self, volume: u8) -> Result<(), crate::Error> {
+        self.state.lock().unwrap().volume = Some(volume);
+        Ok(())
+    }
+
+    fn set_muted(&self, muted: bool) -> Result<(), crate::Error> {
+        self.state.lock().unwrap().muted = Some(muted);
+        Ok(())
+    }
+}
+
+impl SteamModifier for MockSteamModifier {
+    fn get_system_state(&self) -> Result<SteamState, crate::Error> {
+        Ok(self.state())
+    }
+
+    fn get_running_app(&self) -> Result<Option<u32>, crate::Error> {
+        Ok(self.running_app())
+    }
+
+    fn request_shutdown(&self) -> Result<(), crate::Error> {
+        self.kill_steam()
+    }
+
+    fn kill_steam(&self) -> Result<(), crate::Error> {
+        *self.state.lock().unwrap() = (SteamState::NotRunning, None);
+        Ok(())
+    }
+
+    fn launch_steam(&self, _args: &[String]) -> Result<(), crate::Error> {
+        self.state.lock().unwrap().0 = SteamState::RunningNormal;
+        Ok(())
+    }
+
+    fn launch_big_picture(&self, _args: &[String]) -> Result<(), crate::Error> {
+        self.state.lock().unwrap().0 = SteamState::RunningBigPicture;
+        Ok(())
+    }
+
+    fn launch_app(&self, app_id: u32, _args: &[String]) -> Result<(), crate::Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.0 == SteamState::NotRunning {
+            state.0 = SteamState::RunningNormal;
+        }
+        state.1 = Some(app_id);
+        Ok(())
+    }
+
+    fn enable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        self.launch_big_picture(args)
+    }
+
+    fn disable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        self.launch_steam(args)
+    }
+}
\ No newline at end of file
@@ -1,17 +1,54 @@
-mod display;
+pub mod display;
 
-mod display_sys;
+pub mod display_sys;
 
-mod audio;
+pub mod display_xrandr;
 
-mod audio_sys;
+pub mod audio;
 
-mod steam;
+pub mod audio_sys;
 
-mod steam_sys;
+pub mod powershell_session;
+
+pub mod audio_pactl;
+
+pub mod steam;
+
+#[cfg(windows)]
+pub mod steam_sys;
+
+pub mod steam_proc;
+
+pub mod steam_discovery;
+
+mod command;
+
+pub mod command_runner;
+
+pub mod cancellation;
+
+pub mod observer;
+
+mod checksum;
+
+#[cfg(windows)]
+mod assets;
 
 pub mod task;
 
-mod system_modifier_inner;
+pub mod system_modifier_inner;
+
+#[cfg(windows)]
+pub mod system_modifier;
+
+pub mod system_modifier_builder;
+
+#[cfg(feature = "async")]
+pub mod async_modifier;
+
+#[cfg(feature = "async")]
+pub mod async_system_modifier;
+
+pub mod mock;
 
-pub mod system_modifier;
\ No newline at end of file
+pub mod config;
\ No newline at end of file
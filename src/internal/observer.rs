@@ -0,0 +1,87 @@
+use super::display::DisplayState;
+use super::audio::AudioState;
+use super::steam::SteamStatus;
+
+use std::sync::mpsc;
+
+/// One of the subsystems a task changes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Subsystem {
+    Display,
+    Audio,
+    Steam,
+}
+
+impl std::fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Subsystem::Display => "display".fmt(f),
+            Subsystem::Audio => "audio".fmt(f),
+            Subsystem::Steam => "steam".fmt(f),
+        }
+    }
+}
+
+/// The progress of a run, as reported to a `RunObserver`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunEvent {
+    /// A pass is starting. `attempt` counts from 1.
+    AttemptStarted { attempt: usize, max_attempts: usize },
+    /// The state of each subsystem the task changes was queried. Subsystems
+    /// the task leaves alone are `None`.
+    StateObserved {
+        display: Option<DisplayState>,
+        audio: Option<AudioState>,
+        steam: Option<SteamStatus>,
+    },
+    /// Actions were issued to bring a subsystem to the desired state.
+    ActionIssued { subsystem: Subsystem },
+    /// Bringing a subsystem to the desired state failed, ending the run.
+    ActionFailed { subsystem: Subsystem, error: String },
+    /// A subsystem matches the desired state. Reported again only if it
+    /// needed another action since.
+    SubsystemConverged { subsystem: Subsystem },
+    /// The run ended. `Ok(false)` if the system state never matched the task.
+    /// Not reported for an async run cancelled by dropping its future.
+    RunFinished { result: Result<bool, String> },
+}
+
+/// Receives the events of runs, e.g. to show progress. Events are reported
+/// from the thread calling `run`, and `on_event` should return quickly.
+///
+/// `mpsc::Sender<RunEvent>` is an observer, for receiving events on another thread.
+pub trait RunObserver: std::fmt::Debug + Send + Sync {
+    fn on_event(&self, event: &RunEvent);
+}
+
+// Events sent after the receiver hung up are dropped.
+impl RunObserver for mpsc::Sender<RunEvent> {
+    fn on_event(&self, event: &RunEvent) {
+        let _ = self.send(event.clone());
+    }
+}
+
+/// Tracks which subsystems have converged within a run, so that each is
+/// reported once per convergence.
+#[derive(Debug, Default)]
+pub (crate) struct Convergence {
+    converged: Vec<Subsystem>,
+}
+
+impl Convergence {
+    // The event for a subsystem's modification result, if any is due
+    pub (crate) fn event(&mut self, subsystem: Subsystem, result: &Result<bool, crate::Error>) -> Option<RunEvent> {
+        match result {
+            Ok(true) => {
+                self.converged.retain(|s| *s != subsystem);
+                Some(RunEvent::ActionIssued { subsystem: subsystem })
+            },
+            Ok(false) if self.converged.contains(&subsystem) => None,
+            Ok(false) => {
+                self.converged.push(subsystem);
+                Some(RunEvent::SubsystemConverged { subsystem: subsystem })
+            },
+            Err(e) => Some(RunEvent::ActionFailed { subsystem: subsystem, error: format!("{e:?}") }),
+        }
+    }
+}
\ No newline at end of file
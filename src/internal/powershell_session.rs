@@ -0,0 +1,394 @@
+use super::cancellation::{CancellationToken, POLL_INTERVAL};
+use super::checksum::verify_sha256;
+use super::command::{traced_command, ExitCodes};
+#[cfg(feature = "async")]
+use super::command::traced_command_async;
+use super::command_runner::{CommandRunner, CommandSpec};
+#[cfg(feature = "async")]
+use super::command_runner::ProcessRunner;
+use crate::error::{PowershellSessionError, TimeoutError};
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time;
+
+// The session protocol is line-delimited. Each request is a single line of
+// PowerShell, and its response is the lines it writes to stdout followed by
+// either RESPONSE_OK or RESPONSE_ERROR on a line of their own.
+static RESPONSE_OK: &str = "<<steamwig:ok>>";
+static RESPONSE_ERROR: &str = "<<steamwig:error>>";
+// PowerShell ends a single-quoted string on any of these, not just on '
+static SINGLE_QUOTES: [char; 5] = ['\'', '\u{2018}', '\u{2019}', '\u{201A}', '\u{201B}'];
+
+/// A long-lived PowerShell process with a module imported once.
+///
+/// The process is started on the first request, and restarted if it dies.
+/// A request which times out or is cancelled kills the process.
+///
+/// With `with_runner`, each request is instead run as a PowerShell command of
+/// its own, so that requests can be recorded and replayed like other commands.
+#[derive(Debug)]
+pub struct PowershellSession {
+    pub (crate) program: PathBuf,
+    pub (crate) module_path: PathBuf,
+    pub (crate) module_sha256: Option<String>,
+    pub (crate) timeout: Option<time::Duration>,
+    pub (crate) cancellation: Option<CancellationToken>,
+    pub (crate) runner: Option<Arc<dyn CommandRunner>>,
+    child: Mutex<Option<SessionChild>>,
+}
+
+// Lines of stdout are read on their own thread, so that a request can stop
+// waiting on a hung process.
+#[derive(Debug)]
+struct SessionChild {
+    child: process::Child,
+    stdin: process::ChildStdin,
+    lines: mpsc::Receiver<io::Result<String>>,
+}
+
+enum SessionFailure {
+    // The process died or its pipes broke, so the request can be retried.
+    Io(io::Error),
+    Script(String),
+    // The request timed out or was cancelled.
+    Stopped(crate::Error),
+}
+
+impl PowershellSession {
+    pub fn new(program: PathBuf, module_path: PathBuf) -> Self {
+        PowershellSession {
+            program: program,
+            module_path: module_path,
+            module_sha256: None,
+            timeout: None,
+            cancellation: None,
+            runner: None,
+            child: Mutex::new(None),
+        }
+    }
+
+    /// Refuses to import the module unless its SHA-256, in hex, is `sha256`.
+    /// The file is checked every time the process is started.
+    pub fn with_module_sha256(&mut self, sha256: String) {
+        self.module_sha256 = Some(sha256)
+    }
+
+    /// Kills the process if a request has not finished after `timeout`.
+    pub fn with_timeout(&mut self, timeout: time::Duration) {
+        self.timeout = Some(timeout)
+    }
+
+    /// Kills the process once `token` is cancelled, or once the deadline of
+    /// the run using it passes.
+    pub fn with_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token)
+    }
+
+    /// Runs each request with `runner` as a PowerShell command of its own,
+    /// which imports the module every time, rather than in a long-lived process.
+    /// A script which fails is reported as `Error::PowershellSessionError` all the same.
+    pub fn with_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.runner = Some(runner)
+    }
+
+    /// `powershell` on Windows, `pwsh` elsewhere.
+    pub fn default_program() -> PathBuf {
+        match cfg!(windows) {
+            true => PathBuf::from("powershell"),
+            false => PathBuf::from("pwsh"),
+        }
+    }
+
+    /// Runs a single line of PowerShell, returning what it wrote to stdout.
+    pub fn run(&self, script: &str) -> Result<String, crate::Error> {
+        let _span = tracing::debug_span!("powershell", script = script).entered();
+        let start: time::Instant = time::Instant::now();
+        if script.contains(['\n', '\r']) {
+            return Err(session_error(script, String::new(), "script should be a single line"))
+        }
+        if let Some(token) = &self.cancellation {
+            token.check()?;
+        }
+        if let Some(runner) = &self.runner {
+            return self.run_command(&**runner, script)
+        }
+        let mut child = self.child.lock().unwrap();
+        let mut restarted: bool = false;
+        let result: Result<String, crate::Error> = loop {
+            if child.is_none() {
+                *child = Some(self.start()?);
+                restarted = true;
+            }
+            match self.request(child.as_mut().unwrap(), script) {
+                Ok(output) => break Ok(output),
+                Err(SessionFailure::Script(output)) => break Err(session_error(script, output, "script failed")),
+                Err(SessionFailure::Stopped(e)) => {
+                    tracing::warn!(error = ?e, "killing powershell session");
+                    if let Some(stopped) = child.take() {
+                        kill(stopped);
+                    }
+                    break Err(e)
+                },
+                Err(SessionFailure::Io(e)) => {
+                    tracing::warn!(error = ?e, "powershell session died");
+                    if let Some(dead) = child.take() {
+                        kill(dead);
+                    }
+                    if restarted {
+                        break Err(crate::Error::CommandError(e))
+                    }
+                },
+            }
+        };
+        let duration_ms: u64 = start.elapsed().as_millis() as u64;
+        match &result {
+            Ok(output) => {
+                tracing::debug!(duration_ms, "powershell request finished");
+                tracing::trace!(stdout = %output, "powershell output");
+            },
+            Err(e) => tracing::warn!(duration_ms, error = ?e, "powershell request failed"),
+        }
+        result
+    }
+
+    /// `run` for async backends. Each request is a PowerShell command of its
+    /// own, run with the runner given to `with_runner`, or else as a child
+    /// process which is killed if the future is dropped.
+    #[cfg(feature = "async")]
+    pub async fn run_async(&self, script: &str) -> Result<String, crate::Error> {
+        use tracing::Instrument;
+        if script.contains(['\n', '\r']) {
+            return Err(session_error(script, String::new(), "script should be a single line"))
+        }
+        if let Some(token) = &self.cancellation {
+            token.check()?;
+        }
+        let command: CommandSpec = self.command(script)?;
+        let mut process_runner: ProcessRunner = ProcessRunner::new();
+        if let Some(token) = &self.cancellation {
+            process_runner.with_cancellation(token.clone());
+        }
+        let runner: &dyn CommandRunner = match &self.runner {
+            Some(runner) => &**runner,
+            None => &process_runner,
+        };
+        let result: Result<process::Output, crate::Error> = traced_command_async(runner, &command, ExitCodes::Zero)
+            .instrument(tracing::debug_span!("powershell", script = script))
+            .await;
+        command_output(script, result)
+    }
+
+    // The request as a command of its own, with the runner given to `with_runner`
+    fn run_command(&self, runner: &dyn CommandRunner, script: &str) -> Result<String, crate::Error> {
+        let command: CommandSpec = self.command(script)?;
+        command_output(script, traced_command(runner, &command, ExitCodes::Zero))
+    }
+
+    fn command(&self, script: &str) -> Result<CommandSpec, crate::Error> {
+        if let Some(s) = &self.module_sha256 {
+            verify_sha256(&self.module_path, s)?;
+        }
+        let command_script: String = format!("{}; {script} | Out-String -Stream -Width 4096", import_script(&self.module_path)?);
+        let mut command: CommandSpec = CommandSpec::new(
+            self.program.clone(),
+            &["-NoLogo", "-NoProfile", "-NonInteractive", "-Command", &command_script],
+        );
+        if let Some(d) = self.timeout { command.with_timeout(d) }
+        Ok(command)
+    }
+
+    fn start(&self) -> Result<SessionChild, crate::Error> {
+        tracing::debug!(program = %self.program.display(), "starting powershell session");
+        if let Some(s) = &self.module_sha256 {
+            verify_sha256(&self.module_path, s)?;
+        }
+        let mut child: process::Child = process::Command::new(&self.program)
+            .args(["-NoLogo", "-NoProfile", "-NonInteractive", "-Command", "-"])
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::null())
+            .spawn()
+            .map_err(|e| crate::Error::CommandError(e))?;
+        let mut session_child: SessionChild = SessionChild {
+            stdin: child.stdin.take().unwrap(),
+            lines: read_lines(child.stdout.take().unwrap()),
+            child: child,
+        };
+        let import_script: String = import_script(&self.module_path)?;
+        let result: Result<String, SessionFailure> = self.request(&mut session_child, &import_script);
+        let error: crate::Error = match result {
+            Ok(_) => return Ok(session_child),
+            Err(SessionFailure::Io(e)) => crate::Error::CommandError(e),
+            Err(SessionFailure::Stopped(e)) => e,
+            Err(SessionFailure::Script(output)) => {
+                session_error(&import_script, output, "module could not be imported")
+            },
+        };
+        kill(session_child);
+        Err(error)
+    }
+
+    fn request(&self, session_child: &mut SessionChild, script: &str) -> Result<String, SessionFailure> {
+        let request_line: String = format!(
+            "try {{ {script} | Out-String -Stream -Width 4096; '{RESPONSE_OK}' }} catch {{ $_ | Out-String -Stream -Width 4096; '{RESPONSE_ERROR}' }}\n",
+        );
+        session_child.stdin.write_all(request_line.as_bytes()).map_err(|e| SessionFailure::Io(e))?;
+        session_child.stdin.flush().map_err(|e| SessionFailure::Io(e))?;
+        let deadline: Option<time::Instant> = self.timeout.map(|t| time::Instant::now() + t);
+        let mut output: String = String::new();
+        loop {
+            let line: String = match session_child.lines.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => line.map_err(|e| SessionFailure::Io(e))?,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(SessionFailure::Io(io::ErrorKind::UnexpectedEof.into()))
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    self.check_deadline(script, deadline).map_err(|e| SessionFailure::Stopped(e))?;
+                    continue
+                },
+            };
+            match line.trim_end() {
+                l if l == RESPONSE_OK => return Ok(output),
+                l if l == RESPONSE_ERROR => return Err(SessionFailure::Script(output)),
+                _ => output.push_str(&line),
+            }
+        }
+    }
+
+    fn check_deadline(&self, script: &str, deadline: Option<time::Instant>) -> Result<(), crate::Error> {
+        if let Some(token) = &self.cancellation {
+            token.check()?;
+        }
+        match (deadline, self.timeout) {
+            (Some(deadline), Some(timeout)) if time::Instant::now() >= deadline => {
+                Err(crate::Error::Timeout(TimeoutError {
+                    operation: script.to_string(),
+                    timeout: timeout,
+                }))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for PowershellSession {
+    fn drop(&mut self) {
+        if let Some(session_child) = self.child.lock().unwrap().take() {
+            kill(session_child);
+        }
+    }
+}
+
+fn command_output(script: &str, result: Result<process::Output, crate::Error>) -> Result<String, crate::Error> {
+    match result {
+        Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Err(crate::Error::CommandFailed(e)) => Err(session_error(script, e.stderr, "script failed")),
+        Err(e) => Err(e),
+    }
+}
+
+fn import_script(module_path: &Path) -> Result<String, crate::Error> {
+    Ok(format!(
+        "$ErrorActionPreference = 'Stop'; Import-Module {}",
+        quote(&module_path.to_string_lossy())?,
+    ))
+}
+
+/// Quotes `s` as a single-quoted PowerShell string literal, doubling any
+/// single quotes, typographic ones included. Line breaks are refused, as they
+/// would end the request line.
+pub (crate) fn quote(s: &str) -> Result<String, crate::Error> {
+    if s.contains(['\n', '\r']) {
+        return Err(session_error(s, String::new(), "argument should not contain a line break"))
+    }
+    let mut quoted: String = String::from("'");
+    for c in s.chars() {
+        if SINGLE_QUOTES.contains(&c) {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    Ok(quoted)
+}
+
+fn kill(mut session_child: SessionChild) {
+    let _ = session_child.child.kill();
+    let _ = session_child.child.wait();
+}
+
+// Ends with an UnexpectedEof error once stdout closes
+fn read_lines(stdout: process::ChildStdout) -> mpsc::Receiver<io::Result<String>> {
+    let (tx, rx) = mpsc::channel::<io::Result<String>>();
+    thread::spawn(move || {
+        let mut stdout: BufReader<process::ChildStdout> = BufReader::new(stdout);
+        loop {
+            let mut line: String = String::new();
+            let result: io::Result<String> = match stdout.read_line(&mut line) {
+                Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => Ok(line),
+                Err(e) => Err(e),
+            };
+            let stop: bool = result.is_err();
+            if tx.send(result).is_err() || stop {
+                break
+            }
+        }
+    });
+    rx
+}
+
+fn session_error(script: &str, output: String, description: &'static str) -> crate::Error {
+    crate::Error::PowershellSessionError(PowershellSessionError {
+        script: script.to_string(),
+        output: output,
+        description: description,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn quotes_single_quotes() {
+        assert_eq!(quote("Speakers").unwrap(), "'Speakers'");
+        assert_eq!(quote("it's; Remove-Item x").unwrap(), "'it''s; Remove-Item x'");
+        assert_eq!(quote("''").unwrap(), "''''''");
+    }
+
+    #[test]
+    fn quotes_typographic_single_quotes() {
+        assert_eq!(quote("John\u{2018}s AirPods").unwrap(), "'John\u{2018}\u{2018}s AirPods'");
+        assert_eq!(quote("John\u{2019}s AirPods").unwrap(), "'John\u{2019}\u{2019}s AirPods'");
+        assert_eq!(quote("John\u{201A}s AirPods").unwrap(), "'John\u{201A}\u{201A}s AirPods'");
+        assert_eq!(quote("John\u{201B}s AirPods").unwrap(), "'John\u{201B}\u{201B}s AirPods'");
+        assert_eq!(quote("\u{2019}; Remove-Item x").unwrap(), "'\u{2019}\u{2019}; Remove-Item x'");
+    }
+
+    #[test]
+    fn refuses_line_breaks() {
+        assert!(matches!(quote("a\nb"), Err(crate::Error::PowershellSessionError(_))));
+        assert!(matches!(quote("a\rb"), Err(crate::Error::PowershellSessionError(_))));
+        // Refused before a process is started
+        let session: PowershellSession = PowershellSession::new(PathBuf::from("/nonexistent/pwsh"), PathBuf::new());
+        assert!(matches!(session.run("Get-Date\nGet-Date"), Err(crate::Error::PowershellSessionError(_))));
+    }
+
+    #[test]
+    fn verifies_module_checksum_before_starting() {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let module_path: PathBuf = dir.path().join("AudioDeviceCmdlets.dll");
+        fs::write(&module_path, b"MZ replaced").unwrap();
+        // Refused before the nonexistent program is started
+        let mut session: PowershellSession = PowershellSession::new(PathBuf::from("/nonexistent/pwsh"), module_path);
+        session.with_module_sha256(crate::internal::checksum::sha256_hex(b"MZ original"));
+        assert!(matches!(session.run("Get-AudioDevice -List"), Err(crate::Error::ChecksumMismatch(_))));
+    }
+}
\ No newline at end of file
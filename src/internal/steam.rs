@@ -1,4 +1,14 @@
+use super::cancellation::CancellationToken;
+
+use std::time;
+use std::thread;
+
+/// Whether Steam is running, and in which mode.
+///
+/// As a desired state, `None` leaves Steam as it is.
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum SteamState {
     None,
     NotRunning,
@@ -20,28 +30,555 @@ impl SteamState {
     }
 }
 
-pub trait SteamModifier {
+/// The observed state of Steam, and which app it is running.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SteamStatus {
+    pub (crate) state: SteamState,
+    pub (crate) running_app: Option<u32>,
+}
+
+impl SteamStatus {
+    pub fn new(state: SteamState, running_app: Option<u32>) -> SteamStatus {
+        SteamStatus {
+            state: state,
+            running_app: running_app,
+        }
+    }
+
+    pub fn state(&self) -> SteamState {
+        self.state
+    }
+
+    /// The ID of the app Steam is running, if any.
+    pub fn running_app(&self) -> Option<u32> {
+        self.running_app
+    }
+}
+
+/// How Steam is launched and shut down, and which app should be running.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default = "SteamOptions::new"))]
+pub struct SteamOptions {
+    pub (crate) app_id: Option<u32>,
+    pub (crate) args: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(rename = "shutdown_grace_period_secs", with = "optional_secs"))]
+    pub (crate) shutdown_grace_period: Option<time::Duration>,
+}
+
+// A duration as a number of seconds
+#[cfg(feature = "serde")]
+mod optional_secs {
+    use serde::{Deserialize, Serialize};
+    use serde::de::Error;
+    use std::time;
+
+    pub fn serialize<S: serde::Serializer>(duration: &Option<time::Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        duration.map(|d| d.as_secs_f64()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<time::Duration>, D::Error> {
+        Option::<f64>::deserialize(deserializer)?
+            .map(|secs| time::Duration::try_from_secs_f64(secs).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+impl SteamOptions {
+    pub fn new() -> SteamOptions {
+        SteamOptions {
+            app_id: None,
+            args: Vec::new(),
+            shutdown_grace_period: None,
+        }
+    }
+
+    pub fn app_id(&self) -> Option<u32> {
+        self.app_id
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn shutdown_grace_period(&self) -> Option<time::Duration> {
+        self.shutdown_grace_period
+    }
+}
+
+pub (crate) const SHUTDOWN_POLL_INTERVAL_MILLIS: u64 = 500;
+pub (crate) const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS: u64 = 15;
+
+/// A Steam backend.
+///
+/// Implementors provide the queries and actions; `check_and_modify` compares
+/// the desired state with the system state and issues only the actions needed.
+/// Backends are shared across threads, so that subsystems can be queried concurrently.
+pub trait SteamModifier: Send + Sync {
     fn get_system_state(&self) -> Result<SteamState, crate::Error>;
+    fn get_running_app(&self) -> Result<Option<u32>, crate::Error>;
 
+    fn request_shutdown(&self) -> Result<(), crate::Error>;
     fn kill_steam(&self) -> Result<(), crate::Error>;
-    fn launch_steam(&self) -> Result<(), crate::Error>;
-    fn launch_big_picture(&self) -> Result<(), crate::Error>;
-    fn enable_big_picture(&self) -> Result<(), crate::Error>;
-    fn disable_big_picture(&self) -> Result<(), crate::Error>;
+    fn launch_steam(&self, args: &[String]) -> Result<(), crate::Error>;
+    fn launch_big_picture(&self, args: &[String]) -> Result<(), crate::Error>;
+    fn launch_app(&self, app_id: u32, args: &[String]) -> Result<(), crate::Error>;
+    fn enable_big_picture(&self, args: &[String]) -> Result<(), crate::Error>;
+    fn disable_big_picture(&self, args: &[String]) -> Result<(), crate::Error>;
 
-    fn check_and_modify(&self, desired_state: &SteamState) -> Result<bool, crate::Error> {
-        if desired_state.is_empty() {
+    /// The Steam state and running app. The running app is only queried while Steam runs.
+    fn get_status(&self) -> Result<SteamStatus, crate::Error> {
+        let state: SteamState = self.get_system_state()?;
+        let running_app: Option<u32> = match state {
+            SteamState::NotRunning => None,
+            _ => self.get_running_app()?,
+        };
+        Ok(SteamStatus::new(state, running_app))
+    }
+
+    /// The token `shutdown_steam` checks while waiting for Steam to exit.
+    /// Backends given one with `with_cancellation` return it.
+    fn cancellation_token(&self) -> Option<&CancellationToken> {
+        None
+    }
+
+    /// Asks Steam to exit, waiting up to `grace_period` before killing it.
+    /// Stops waiting with an error once the cancellation token is cancelled.
+    fn shutdown_steam(&self, grace_period: Option<time::Duration>) -> Result<(), crate::Error> {
+        let grace_period: time::Duration = grace_period
+            .unwrap_or(time::Duration::from_secs(DEFAULT_SHUTDOWN_GRACE_PERIOD_SECS));
+        let poll_interval: time::Duration = time::Duration::from_millis(SHUTDOWN_POLL_INTERVAL_MILLIS);
+        self.request_shutdown()?;
+        let start: time::Instant = time::Instant::now();
+        while start.elapsed() < grace_period {
+            if self.get_system_state()? == SteamState::NotRunning {
+                return Ok(())
+            }
+            match self.cancellation_token() {
+                Some(token) => token.sleep(poll_interval)?,
+                None => thread::sleep(poll_interval),
+            }
+        }
+        self.kill_steam()
+    }
+
+    /// Returns true if any action was issued, false if the system state already matches.
+    fn check_and_modify(&self, desired_state: &SteamState, options: &SteamOptions) -> Result<bool, crate::Error> {
+        if desired_state.is_empty() && options.app_id.is_none() {
             return Ok(false)
         }
-        let system_state: SteamState = self.get_system_state()?;
-        match (&system_state, desired_state) {
-            (_, _) if &system_state == desired_state => Ok(false),
-            (_, SteamState::NotRunning) => self.kill_steam().and(Ok(true)),
-            (SteamState::NotRunning, SteamState::RunningNormal) => self.launch_steam().and(Ok(true)),
-            (SteamState::NotRunning, SteamState::RunningBigPicture) => self.launch_big_picture().and(Ok(true)),
-            (SteamState::RunningNormal, SteamState::RunningBigPicture) => self.enable_big_picture().and(Ok(true)),
-            (SteamState::RunningBigPicture, SteamState::RunningNormal) => self.disable_big_picture().and(Ok(true)),
-            (_, _) => unreachable!(),
+        let system_status: SteamStatus = self.get_status()?;
+        self.modify_observed(desired_state, options, &system_status)
+    }
+
+    /// Issues the actions needed to bring `system_status`, as queried by
+    /// `get_status`, to `desired_state` running `options.app_id`.
+    /// Returns true if any action was issued.
+    fn modify_observed(
+        &self,
+        desired_state: &SteamState,
+        options: &SteamOptions,
+        system_status: &SteamStatus,
+    ) -> Result<bool, crate::Error> {
+        let action: SteamAction = match plan(desired_state, options, system_status) {
+            Some(a) => a,
+            None => return Ok(false),
+        };
+        match action {
+            SteamAction::Shutdown => self.shutdown_steam(options.shutdown_grace_period)?,
+            SteamAction::LaunchSteam => self.launch_steam(&options.args)?,
+            SteamAction::LaunchBigPicture => self.launch_big_picture(&options.args)?,
+            SteamAction::EnableBigPicture => self.enable_big_picture(&options.args)?,
+            SteamAction::DisableBigPicture => self.disable_big_picture(&options.args)?,
+            SteamAction::LaunchApp(app_id) => self.launch_app(app_id, &options.args)?,
+        }
+        Ok(true)
+    }
+}
+
+/// The call `modify_observed` makes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub (crate) enum SteamAction {
+    Shutdown,
+    LaunchSteam,
+    LaunchBigPicture,
+    EnableBigPicture,
+    DisableBigPicture,
+    LaunchApp(u32),
+}
+
+/// The call which brings `system_status` to `desired_state` running
+/// `options.app_id`, or None if it matches already. The app is only launched
+/// once Steam itself is in the desired state. A backend reporting
+/// `SteamState::None` is treated as if Steam were not running. Shared by the
+/// sync and async `modify_observed`.
+pub (crate) fn plan(
+    desired_state: &SteamState,
+    options: &SteamOptions,
+    system_status: &SteamStatus,
+) -> Option<SteamAction> {
+    tracing::debug!(observed = ?system_status, desired = ?desired_state, ?options, "steam state");
+    let system_state: &SteamState = &system_status.state;
+    match (system_state, desired_state) {
+        (_, SteamState::None) => (),
+        (SteamState::NotRunning, SteamState::NotRunning) => (),
+        (_, SteamState::NotRunning) => return Some(SteamAction::Shutdown),
+        (SteamState::NotRunning | SteamState::None, SteamState::RunningNormal) => return Some(SteamAction::LaunchSteam),
+        (SteamState::NotRunning | SteamState::None, SteamState::RunningBigPicture) => return Some(SteamAction::LaunchBigPicture),
+        (SteamState::RunningNormal, SteamState::RunningNormal) => (),
+        (SteamState::RunningBigPicture, SteamState::RunningBigPicture) => (),
+        (SteamState::RunningNormal, SteamState::RunningBigPicture) => return Some(SteamAction::EnableBigPicture),
+        (SteamState::RunningBigPicture, SteamState::RunningNormal) => return Some(SteamAction::DisableBigPicture),
+    }
+    match options.app_id {
+        Some(app_id) if system_status.running_app != Some(app_id) => Some(SteamAction::LaunchApp(app_id)),
+        _ => None,
+    }
+}
+
+impl<T: SteamModifier + ?Sized> SteamModifier for Box<T> {
+    fn get_system_state(&self) -> Result<SteamState, crate::Error> {
+        (**self).get_system_state()
+    }
+
+    fn get_running_app(&self) -> Result<Option<u32>, crate::Error> {
+        (**self).get_running_app()
+    }
+
+    fn request_shutdown(&self) -> Result<(), crate::Error> {
+        (**self).request_shutdown()
+    }
+
+    fn kill_steam(&self) -> Result<(), crate::Error> {
+        (**self).kill_steam()
+    }
+
+    fn launch_steam(&self, args: &[String]) -> Result<(), crate::Error> {
+        (**self).launch_steam(args)
+    }
+
+    fn launch_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        (**self).launch_big_picture(args)
+    }
+
+    fn launch_app(&self, app_id: u32, args: &[String]) -> Result<(), crate::Error> {
+        (**self).launch_app(app_id, args)
+    }
+
+    fn enable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        (**self).enable_big_picture(args)
+    }
+
+    fn disable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        (**self).disable_big_picture(args)
+    }
+
+    fn get_status(&self) -> Result<SteamStatus, crate::Error> {
+        (**self).get_status()
+    }
+
+    fn cancellation_token(&self) -> Option<&CancellationToken> {
+        (**self).cancellation_token()
+    }
+
+    fn shutdown_steam(&self, grace_period: Option<time::Duration>) -> Result<(), crate::Error> {
+        (**self).shutdown_steam(grace_period)
+    }
+
+    fn check_and_modify(&self, desired_state: &SteamState, options: &SteamOptions) -> Result<bool, crate::Error> {
+        (**self).check_and_modify(desired_state, options)
+    }
+
+    fn modify_observed(
+        &self,
+        desired_state: &SteamState,
+        options: &SteamOptions,
+        system_status: &SteamStatus,
+    ) -> Result<bool, crate::Error> {
+        (**self).modify_observed(desired_state, options, system_status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::mock::MockSteamModifier;
+
+    fn options(app_id: Option<u32>) -> SteamOptions {
+        SteamOptions {
+            app_id: app_id,
+            args: vec!["-silent".to_string()],
+            shutdown_grace_period: Some(time::Duration::ZERO),
+        }
+    }
+
+    // Modifies until no action is issued, returning the number of passes which issued actions
+    fn converge(steam_modifier: &MockSteamModifier, desired_state: SteamState, options: &SteamOptions) -> usize {
+        for passes in 0..5 {
+            if !steam_modifier.check_and_modify(&desired_state, options).unwrap() {
+                return passes
+            }
+        }
+        panic!("steam state did not converge")
+    }
+
+    #[test]
+    fn transitions_between_states() {
+        let transitions: [(SteamState, SteamState); 6] = [
+            (SteamState::NotRunning, SteamState::RunningNormal),
+            (SteamState::NotRunning, SteamState::RunningBigPicture),
+            (SteamState::RunningNormal, SteamState::RunningBigPicture),
+            (SteamState::RunningBigPicture, SteamState::RunningNormal),
+            (SteamState::RunningNormal, SteamState::NotRunning),
+            (SteamState::RunningBigPicture, SteamState::NotRunning),
+        ];
+        for (from, to) in transitions {
+            let steam_modifier: MockSteamModifier = MockSteamModifier::new(from);
+            assert_eq!(converge(&steam_modifier, to, &options(None)), 1, "{from:?} to {to:?}");
+            assert_eq!(steam_modifier.state(), to);
+        }
+    }
+
+    #[test]
+    fn matching_state_issues_no_action() {
+        let steam_modifier: MockSteamModifier = MockSteamModifier::new(SteamState::RunningBigPicture);
+        assert!(!steam_modifier.check_and_modify(&SteamState::RunningBigPicture, &options(None)).unwrap());
+        assert!(!steam_modifier.check_and_modify(&SteamState::None, &options(None)).unwrap());
+        assert_eq!(steam_modifier.state(), SteamState::RunningBigPicture);
+    }
+
+    #[test]
+    fn launches_app_once_steam_is_in_state() {
+        let steam_modifier: MockSteamModifier = MockSteamModifier::new(SteamState::NotRunning);
+        assert_eq!(converge(&steam_modifier, SteamState::RunningBigPicture, &options(Some(570))), 2);
+        assert_eq!(steam_modifier.get_status().unwrap(), SteamStatus::new(SteamState::RunningBigPicture, Some(570)));
+    }
+
+    #[test]
+    fn launches_app_without_a_desired_state() {
+        let steam_modifier: MockSteamModifier = MockSteamModifier::new(SteamState::NotRunning);
+        assert_eq!(converge(&steam_modifier, SteamState::None, &options(Some(570))), 1);
+        assert_eq!(steam_modifier.get_status().unwrap(), SteamStatus::new(SteamState::RunningNormal, Some(570)));
+    }
+
+    #[test]
+    fn replaces_a_different_running_app() {
+        let steam_modifier: MockSteamModifier = MockSteamModifier::new(SteamState::RunningNormal);
+        steam_modifier.launch_app(440, &[]).unwrap();
+        assert_eq!(converge(&steam_modifier, SteamState::RunningNormal, &options(Some(570))), 1);
+        assert_eq!(steam_modifier.running_app(), Some(570));
+    }
+
+    #[test]
+    fn running_app_is_left_alone_without_an_app_id() {
+        let steam_modifier: MockSteamModifier = MockSteamModifier::new(SteamState::RunningNormal);
+        steam_modifier.launch_app(440, &[]).unwrap();
+        assert_eq!(converge(&steam_modifier, SteamState::RunningNormal, &options(None)), 0);
+        assert_eq!(steam_modifier.running_app(), Some(440));
+    }
+
+    #[test]
+    fn status_has_no_app_while_steam_is_off() {
+        let steam_modifier: MockSteamModifier = MockSteamModifier::new(SteamState::RunningNormal);
+        steam_modifier.launch_app(440, &[]).unwrap();
+        steam_modifier.kill_steam().unwrap();
+        assert_eq!(steam_modifier.get_status().unwrap(), SteamStatus::new(SteamState::NotRunning, None));
+    }
+
+    // A Steam which ignores requests to shut down
+    #[derive(Debug)]
+    struct StubbornSteam {
+        mock: MockSteamModifier,
+        cancellation: CancellationToken,
+    }
+
+    impl SteamModifier for StubbornSteam {
+        fn get_system_state(&self) -> Result<SteamState, crate::Error> {
+            self.mock.get_system_state()
+        }
+
+        fn get_running_app(&self) -> Result<Option<u32>, crate::Error> {
+            self.mock.get_running_app()
+        }
+
+        fn cancellation_token(&self) -> Option<&CancellationToken> {
+            Some(&self.cancellation)
+        }
+
+        fn request_shutdown(&self) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn kill_steam(&self) -> Result<(), crate::Error> {
+            self.mock.kill_steam()
+        }
+
+        fn launch_steam(&self, args: &[String]) -> Result<(), crate::Error> {
+            self.mock.launch_steam(args)
+        }
+
+        fn launch_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+            self.mock.launch_big_picture(args)
+        }
+
+        fn launch_app(&self, app_id: u32, args: &[String]) -> Result<(), crate::Error> {
+            self.mock.launch_app(app_id, args)
+        }
+
+        fn enable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+            self.mock.enable_big_picture(args)
+        }
+
+        fn disable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+            self.mock.disable_big_picture(args)
+        }
+    }
+
+    fn stubborn_steam() -> StubbornSteam {
+        StubbornSteam {
+            mock: MockSteamModifier::new(SteamState::RunningNormal),
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    #[test]
+    fn shutdown_kills_steam_after_grace_period() {
+        let steam_modifier: StubbornSteam = stubborn_steam();
+        steam_modifier.shutdown_steam(Some(time::Duration::from_millis(50))).unwrap();
+        assert_eq!(steam_modifier.mock.state(), SteamState::NotRunning);
+    }
+
+    #[test]
+    fn cancelled_shutdown_stops_waiting() {
+        let steam_modifier: StubbornSteam = stubborn_steam();
+        let cancellation: CancellationToken = steam_modifier.cancellation.clone();
+        let start: time::Instant = time::Instant::now();
+        let result: Result<(), crate::Error> = thread::scope(|scope| {
+            let handle = scope.spawn(|| steam_modifier.shutdown_steam(Some(time::Duration::from_secs(30))));
+            thread::sleep(time::Duration::from_millis(50));
+            cancellation.cancel();
+            handle.join().unwrap()
+        });
+        assert!(matches!(result, Err(crate::Error::Cancelled)));
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+        assert_eq!(steam_modifier.mock.state(), SteamState::RunningNormal);
+    }
+
+    #[test]
+    fn plans_state_before_app() {
+        let running_normal: SteamStatus = SteamStatus::new(SteamState::RunningNormal, None);
+        assert_eq!(plan(&SteamState::RunningBigPicture, &options(Some(570)), &running_normal), Some(SteamAction::EnableBigPicture));
+        assert_eq!(plan(&SteamState::RunningNormal, &options(Some(570)), &running_normal), Some(SteamAction::LaunchApp(570)));
+        assert_eq!(plan(&SteamState::NotRunning, &options(Some(570)), &running_normal), Some(SteamAction::Shutdown));
+        let running_app: SteamStatus = SteamStatus::new(SteamState::RunningNormal, Some(570));
+        assert_eq!(plan(&SteamState::None, &options(Some(570)), &running_app), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn steam_state_round_trips_through_serde() {
+        for (steam_state, name) in [
+            (SteamState::None, "none"),
+            (SteamState::NotRunning, "not_running"),
+            (SteamState::RunningNormal, "running_normal"),
+            (SteamState::RunningBigPicture, "running_big_picture"),
+        ] {
+            assert_eq!(serde_json::to_value(steam_state).unwrap(), serde_json::json!(name));
+            assert_eq!(serde_json::from_value::<SteamState>(serde_json::json!(name)).unwrap(), steam_state);
+        }
+        assert!(serde_json::from_str::<SteamState>(r#""RunningNormal""#).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn steam_options_round_trip_through_serde() {
+        let steam_options: SteamOptions = SteamOptions {
+            app_id: Some(570),
+            args: vec!["-silent".to_string()],
+            shutdown_grace_period: Some(time::Duration::from_millis(1500)),
+        };
+        let json: serde_json::Value = serde_json::to_value(&steam_options).unwrap();
+        assert_eq!(json, serde_json::json!({ "app_id": 570, "args": ["-silent"], "shutdown_grace_period_secs": 1.5 }));
+        assert_eq!(serde_json::from_value::<SteamOptions>(json).unwrap(), steam_options);
+        assert_eq!(serde_json::from_str::<SteamOptions>("{}").unwrap(), SteamOptions::new());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn grace_period_is_read_as_seconds() {
+        let grace_period = |json: &str| {
+            serde_json::from_str::<SteamOptions>(json).map(|o| o.shutdown_grace_period)
+        };
+        assert_eq!(grace_period(r#"{"shutdown_grace_period_secs": 15}"#).unwrap(), Some(time::Duration::from_secs(15)));
+        assert_eq!(grace_period(r#"{"shutdown_grace_period_secs": 0.25}"#).unwrap(), Some(time::Duration::from_millis(250)));
+        assert_eq!(grace_period(r#"{"shutdown_grace_period_secs": null}"#).unwrap(), None);
+        assert_eq!(serde_json::to_value(SteamOptions::new()).unwrap()["shutdown_grace_period_secs"], serde_json::Value::Null);
+        assert!(grace_period(r#"{"shutdown_grace_period_secs": -1}"#).is_err());
+        assert!(grace_period(r#"{"shutdown_grace_period_secs": "15"}"#).is_err());
+    }
+
+    // Backend which always reports an unknown state, recording the calls made on it
+    struct UnknownStateSteamModifier {
+        calls: std::sync::Mutex<Vec<&'static str>>,
+    }
+
+    impl UnknownStateSteamModifier {
+        fn record(&self, call: &'static str) -> Result<(), crate::Error> {
+            self.calls.lock().unwrap().push(call);
+            Ok(())
+        }
+    }
+
+    impl SteamModifier for UnknownStateSteamModifier {
+        fn get_system_state(&self) -> Result<SteamState, crate::Error> {
+            Ok(SteamState::None)
+        }
+
+        fn get_running_app(&self) -> Result<Option<u32>, crate::Error> {
+            Ok(None)
+        }
+
+        fn request_shutdown(&self) -> Result<(), crate::Error> {
+            self.record("request_shutdown")
+        }
+
+        fn kill_steam(&self) -> Result<(), crate::Error> {
+            self.record("kill_steam")
+        }
+
+        fn launch_steam(&self, _args: &[String]) -> Result<(), crate::Error> {
+            self.record("launch_steam")
+        }
+
+        fn launch_big_picture(&self, _args: &[String]) -> Result<(), crate::Error> {
+            self.record("launch_big_picture")
+        }
+
+        fn launch_app(&self, _app_id: u32, _args: &[String]) -> Result<(), crate::Error> {
+            self.record("launch_app")
+        }
+
+        fn enable_big_picture(&self, _args: &[String]) -> Result<(), crate::Error> {
+            self.record("enable_big_picture")
+        }
+
+        fn disable_big_picture(&self, _args: &[String]) -> Result<(), crate::Error> {
+            self.record("disable_big_picture")
+        }
+    }
+
+    #[test]
+    fn unknown_system_state_is_treated_as_not_running() {
+        let expectations: [(SteamState, Vec<&'static str>); 3] = [
+            (SteamState::RunningNormal, vec!["launch_steam"]),
+            (SteamState::RunningBigPicture, vec!["launch_big_picture"]),
+            (SteamState::None, vec![]),
+        ];
+        for (desired_state, calls) in expectations {
+            let steam_modifier: UnknownStateSteamModifier = UnknownStateSteamModifier { calls: std::sync::Mutex::new(Vec::new()) };
+            assert_eq!(steam_modifier.check_and_modify(&desired_state, &options(None)).unwrap(), !calls.is_empty());
+            assert_eq!(*steam_modifier.calls.lock().unwrap(), calls, "{desired_state:?}");
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,155 @@
+use std::path::PathBuf;
+
+/// Locates the Steam executable of the current user's Steam installation.
+pub fn discover_steam_path() -> Option<PathBuf> {
+    discover()
+}
+
+#[cfg(windows)]
+fn discover() -> Option<PathBuf> {
+    use std::ffi::CString;
+
+    use lazy_static::lazy_static;
+    use winapi::shared::minwindef;
+    use winapi::shared::winerror;
+    use winapi::um::winreg;
+
+    lazy_static! {
+        static ref LP_SUB_KEY: CString = CString::new(r"Software\Valve\Steam").unwrap();
+        static ref LP_VALUE: CString = CString::new("SteamExe").unwrap();
+    }
+    let mut data_size: minwindef::DWORD = 0;
+    let status: i32 = unsafe {winreg::RegGetValueA(
+        winreg::HKEY_CURRENT_USER,
+        LP_SUB_KEY.as_ptr(),
+        LP_VALUE.as_ptr(),
+        winreg::RRF_RT_REG_SZ,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        &mut data_size,
+    )};
+    if status as u32 != winerror::ERROR_SUCCESS || data_size == 0 {
+        return None
+    }
+    let mut data: Vec<u8> = vec![0; data_size as usize];
+    let status: i32 = unsafe {winreg::RegGetValueA(
+        winreg::HKEY_CURRENT_USER,
+        LP_SUB_KEY.as_ptr(),
+        LP_VALUE.as_ptr(),
+        winreg::RRF_RT_REG_SZ,
+        std::ptr::null_mut(),
+        data.as_mut_ptr() as *mut _,
+        &mut data_size,
+    )};
+    if status as u32 != winerror::ERROR_SUCCESS {
+        return None
+    }
+    data.truncate(data.iter().position(|b| *b == 0).unwrap_or(data.len()));
+    let exe_path: PathBuf = PathBuf::from(String::from_utf8_lossy(&data).to_string());
+    match exe_path.is_file() {
+        true => Some(exe_path),
+        false => None,
+    }
+}
+
+#[cfg(not(windows))]
+fn discover() -> Option<PathBuf> {
+    let home: PathBuf = std::env::var_os("HOME").map(PathBuf::from)?;
+    discover_in_home(&home)
+}
+
+// Native installs, followed by the Flatpak install.
+#[cfg(not(windows))]
+static STEAM_ROOTS: &[&[&str]] = &[
+    &[".steam", "steam"],
+    &[".local", "share", "Steam"],
+    &[".var", "app", "com.valvesoftware.Steam", ".local", "share", "Steam"],
+];
+
+#[cfg(not(windows))]
+pub (crate) fn discover_in_home(home: &std::path::Path) -> Option<PathBuf> {
+    for steam_root in STEAM_ROOTS {
+        let exe_path: PathBuf = steam_root.iter()
+            .fold(home.to_path_buf(), |path, component| path.join(component))
+            .join("steam.sh");
+        if exe_path.is_file() {
+            return Some(exe_path)
+        }
+    }
+    None
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::Path;
+
+    // Creates `steam.sh` under the steam root at `components` within `home`
+    fn install(home: &Path, components: &[&str]) -> PathBuf {
+        let steam_root: PathBuf = components.iter().fold(home.to_path_buf(), |path, c| path.join(c));
+        fs::create_dir_all(&steam_root).unwrap();
+        fs::write(steam_root.join("steam.sh"), "").unwrap();
+        steam_root.join("steam.sh")
+    }
+
+    #[test]
+    fn finds_nothing_in_an_empty_home() {
+        let home: tempfile::TempDir = tempfile::tempdir().unwrap();
+        assert_eq!(discover_in_home(home.path()), None);
+    }
+
+    #[test]
+    fn finds_dot_steam_install() {
+        let home: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let exe_path: PathBuf = install(home.path(), &[".steam", "steam"]);
+        assert_eq!(discover_in_home(home.path()), Some(exe_path));
+    }
+
+    #[test]
+    fn finds_local_share_install() {
+        let home: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let exe_path: PathBuf = install(home.path(), &[".local", "share", "Steam"]);
+        assert_eq!(discover_in_home(home.path()), Some(exe_path));
+    }
+
+    #[test]
+    fn finds_flatpak_install() {
+        let home: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let exe_path: PathBuf = install(home.path(), &[".var", "app", "com.valvesoftware.Steam", ".local", "share", "Steam"]);
+        assert_eq!(discover_in_home(home.path()), Some(exe_path));
+    }
+
+    #[test]
+    fn prefers_dot_steam_over_local_share_and_flatpak() {
+        let home: tempfile::TempDir = tempfile::tempdir().unwrap();
+        install(home.path(), &[".var", "app", "com.valvesoftware.Steam", ".local", "share", "Steam"]);
+        let local_share: PathBuf = install(home.path(), &[".local", "share", "Steam"]);
+        assert_eq!(discover_in_home(home.path()), Some(local_share));
+        let dot_steam: PathBuf = install(home.path(), &[".steam", "steam"]);
+        assert_eq!(discover_in_home(home.path()), Some(dot_steam));
+    }
+
+    #[test]
+    fn skips_a_root_without_steam_sh() {
+        let home: tempfile::TempDir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(home.path().join(".steam").join("steam")).unwrap();
+        let exe_path: PathBuf = install(home.path(), &[".local", "share", "Steam"]);
+        assert_eq!(discover_in_home(home.path()), Some(exe_path));
+    }
+
+    // The Steam installer links ~/.steam/steam to the real root
+    #[test]
+    fn finds_install_through_symlinked_root() {
+        let home: tempfile::TempDir = tempfile::tempdir().unwrap();
+        install(home.path(), &[".local", "share", "Steam"]);
+        fs::create_dir_all(home.path().join(".steam")).unwrap();
+        std::os::unix::fs::symlink(
+            home.path().join(".local").join("share").join("Steam"),
+            home.path().join(".steam").join("steam"),
+        ).unwrap();
+        let expected: PathBuf = home.path().join(".steam").join("steam").join("steam.sh");
+        assert_eq!(discover_in_home(home.path()), Some(expected));
+    }
+}
\ No newline at end of file
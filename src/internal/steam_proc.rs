@@ -0,0 +1,285 @@
+use super::steam::{SteamState, SteamModifier};
+use super::command::{traced_command, traced_spawn, ExitCodes};
+#[cfg(feature = "async")]
+use super::command::traced_command_async;
+use super::command_runner::{CommandRunner, CommandSpec, ProcessRunner};
+use super::cancellation::CancellationToken;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::fs;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+static BIG_PICTURE_WINDOW_NAME: &str = "Steam Big Picture Mode";
+
+/// Steam backend for Linux, using /proc to find Steam and xdotool to find
+/// the Big Picture window.
+#[derive(Debug)]
+pub struct ProcModifier {
+    pub (crate) exe_path: PathBuf,
+    pub (crate) registry_path: PathBuf,
+    pub (crate) runner: Arc<dyn CommandRunner>,
+    pub (crate) cancellation: Option<CancellationToken>,
+}
+
+impl SteamModifier for ProcModifier {
+    fn get_system_state(&self) -> Result<SteamState, crate::Error> {
+        if !is_steam_running()? {
+            Ok(SteamState::NotRunning)
+        } else if is_big_picture_running(&*self.runner) {
+            Ok(SteamState::RunningBigPicture)
+        } else {
+            Ok(SteamState::RunningNormal)
+        }
+    }
+
+    fn get_running_app(&self) -> Result<Option<u32>, crate::Error> {
+        Ok(get_running_app(&self.registry_path))
+    }
+
+    fn cancellation_token(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    fn request_shutdown(&self) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, &[], &["-shutdown"])
+    }
+
+    fn kill_steam(&self) -> Result<(), crate::Error> {
+        // pkill exits 1 when no process matched.
+        traced_command(&*self.runner, &pkill_steam(), ExitCodes::OneOf(&[0, 1])).map(|_| ())
+    }
+
+    fn launch_steam(&self, args: &[String]) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, args, &[])
+    }
+
+    fn launch_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, args, &["steam://open/bigpicture"])
+    }
+
+    fn launch_app(&self, app_id: u32, args: &[String]) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, args, &[&format!("steam://rungameid/{app_id}")])
+    }
+
+    fn enable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        self.launch_big_picture(args)
+    }
+
+    fn disable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, args, &["steam://close/bigpicture"])
+    }
+}
+
+#[cfg(feature = "async")]
+impl super::async_modifier::AsyncSteamModifier for ProcModifier {
+    async fn get_system_state(&self) -> Result<SteamState, crate::Error> {
+        if !is_steam_running()? {
+            return Ok(SteamState::NotRunning)
+        }
+        match traced_command_async(&*self.runner, &big_picture_search(), ExitCodes::OneOf(&[0, 1])).await {
+            Ok(output) if output.status.success() && !output.stdout.is_empty() => Ok(SteamState::RunningBigPicture),
+            _ => Ok(SteamState::RunningNormal),
+        }
+    }
+
+    async fn get_running_app(&self) -> Result<Option<u32>, crate::Error> {
+        Ok(get_running_app(&self.registry_path))
+    }
+
+    async fn request_shutdown(&self) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, &[], &["-shutdown"])
+    }
+
+    async fn kill_steam(&self) -> Result<(), crate::Error> {
+        traced_command_async(&*self.runner, &pkill_steam(), ExitCodes::OneOf(&[0, 1])).await.map(|_| ())
+    }
+
+    async fn launch_steam(&self, args: &[String]) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, args, &[])
+    }
+
+    async fn launch_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, args, &["steam://open/bigpicture"])
+    }
+
+    async fn launch_app(&self, app_id: u32, args: &[String]) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, args, &[&format!("steam://rungameid/{app_id}")])
+    }
+
+    async fn enable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, args, &["steam://open/bigpicture"])
+    }
+
+    async fn disable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        steam_spawn(&*self.runner, &self.exe_path, args, &["steam://close/bigpicture"])
+    }
+}
+
+impl ProcModifier {
+    pub fn new(exe_path: std::path::PathBuf) -> Self {
+        let registry_path: PathBuf = std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_default()
+            .join(".steam")
+            .join("registry.vdf");
+        ProcModifier {
+            exe_path: exe_path,
+            registry_path: registry_path,
+            runner: Arc::new(ProcessRunner::new()),
+            cancellation: None,
+        }
+    }
+
+    pub fn with_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.runner = runner
+    }
+
+    /// Stops waiting for Steam to shut down once `token` is cancelled.
+    pub fn with_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token)
+    }
+}
+
+// Steam keeps running after launch, so it is spawned rather than waited on.
+fn steam_spawn(runner: &dyn CommandRunner, exe_path: &Path, args: &[String], extra_args: &[&str]) -> Result<(), crate::Error> {
+    traced_spawn(runner, &steam_command(exe_path, args, extra_args))
+}
+
+fn steam_command(exe_path: &Path, args: &[String], extra_args: &[&str]) -> CommandSpec {
+    let mut all_args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    all_args.extend(extra_args);
+    CommandSpec::new(exe_path.to_path_buf(), &all_args)
+}
+
+fn pkill_steam() -> CommandSpec {
+    CommandSpec::new(PathBuf::from("pkill"), &["-KILL", "-x", "steam"])
+}
+
+fn big_picture_search() -> CommandSpec {
+    CommandSpec::new(PathBuf::from("xdotool"), &["search", "--name", BIG_PICTURE_WINDOW_NAME])
+}
+
+fn is_steam_running() -> Result<bool, crate::Error> {
+    for entry in fs::read_dir("/proc").map_err(|e| crate::Error::CommandError(e))? {
+        let comm_path: PathBuf = match entry {
+            Ok(entry) => entry.path().join("comm"),
+            Err(_) => continue,
+        };
+        if let Ok(comm) = fs::read_to_string(comm_path) {
+            if comm.trim_end() == "steam" {
+                return Ok(true)
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn is_big_picture_running(runner: &dyn CommandRunner) -> bool {
+    match runner.run(&big_picture_search()) {
+        Ok(output) => output.status.success() && !output.stdout.is_empty(),
+        Err(_) => false,
+    }
+}
+
+fn get_running_app(registry_path: &Path) -> Option<u32> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r#""RunningAppID"\s+"(\d+)""#).unwrap();
+    }
+    let registry: String = fs::read_to_string(registry_path).ok()?;
+    match RE.captures(&registry).map(|c| c[1].parse::<u32>()) {
+        Some(Ok(0)) | Some(Err(_)) | None => None,
+        Some(Ok(app_id)) => Some(app_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::command_runner::{ReplayRunner, CommandRecord, CommandOutcome};
+
+    fn spawned(program: &str, args: &[&str]) -> CommandRecord {
+        CommandRecord {
+            command: CommandSpec::new(PathBuf::from(program), args),
+            outcome: CommandOutcome::Spawned,
+        }
+    }
+
+    fn proc_modifier(records: Vec<CommandRecord>, registry_path: PathBuf) -> (ProcModifier, Arc<ReplayRunner>) {
+        let runner: Arc<ReplayRunner> = Arc::new(ReplayRunner::new(records));
+        let mut proc_modifier: ProcModifier = ProcModifier::new(PathBuf::from("/opt/steam/steam.sh"));
+        proc_modifier.with_runner(runner.clone());
+        proc_modifier.registry_path = registry_path;
+        (proc_modifier, runner)
+    }
+
+    #[test]
+    fn spawns_steam_with_arguments() {
+        let (proc_modifier, runner) = proc_modifier(vec![
+            spawned("/opt/steam/steam.sh", &["-silent"]),
+            spawned("/opt/steam/steam.sh", &["-silent", "steam://open/bigpicture"]),
+            spawned("/opt/steam/steam.sh", &["-silent", "steam://rungameid/570"]),
+            spawned("/opt/steam/steam.sh", &["-silent", "steam://close/bigpicture"]),
+            spawned("/opt/steam/steam.sh", &["-shutdown"]),
+        ], PathBuf::new());
+        let args: Vec<String> = vec!["-silent".to_string()];
+        proc_modifier.launch_steam(&args).unwrap();
+        proc_modifier.enable_big_picture(&args).unwrap();
+        proc_modifier.launch_app(570, &args).unwrap();
+        proc_modifier.disable_big_picture(&args).unwrap();
+        proc_modifier.request_shutdown().unwrap();
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn kill_accepts_no_matching_process() {
+        let (proc_modifier, _) = proc_modifier(vec![CommandRecord {
+            command: pkill_steam(),
+            outcome: CommandOutcome::Exited { exit_code: 1, stdout: Vec::new(), stderr: Vec::new() },
+        }], PathBuf::new());
+        proc_modifier.kill_steam().unwrap();
+    }
+
+    #[test]
+    fn reads_running_app_from_registry() {
+        let dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let registry_path: PathBuf = dir.path().join("registry.vdf");
+        let (proc_modifier, _) = proc_modifier(Vec::new(), registry_path.clone());
+        assert_eq!(proc_modifier.get_running_app().unwrap(), None);
+        fs::write(&registry_path, "\"Registry\"\n{\n\t\"Steam\"\n\t{\n\t\t\"RunningAppID\"\t\t\"570\"\n\t}\n}\n").unwrap();
+        assert_eq!(proc_modifier.get_running_app().unwrap(), Some(570));
+        fs::write(&registry_path, "\"Registry\"\n{\n\t\"Steam\"\n\t{\n\t\t\"RunningAppID\"\t\t\"0\"\n\t}\n}\n").unwrap();
+        assert_eq!(proc_modifier.get_running_app().unwrap(), None);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn launches_app_once_in_desired_state_async() {
+        use crate::internal::async_modifier::AsyncSteamModifier;
+        use crate::internal::steam::{SteamOptions, SteamStatus};
+        let (proc_modifier, runner) = proc_modifier(vec![
+            spawned("/opt/steam/steam.sh", &["-silent", "steam://open/bigpicture"]),
+            spawned("/opt/steam/steam.sh", &["-silent", "steam://rungameid/570"]),
+            CommandRecord {
+                command: pkill_steam(),
+                outcome: CommandOutcome::Exited { exit_code: 1, stdout: Vec::new(), stderr: Vec::new() },
+            },
+        ], PathBuf::new());
+        let options: SteamOptions = SteamOptions {
+            app_id: Some(570),
+            args: vec!["-silent".to_string()],
+            shutdown_grace_period: None,
+        };
+        let desired_state: SteamState = SteamState::RunningBigPicture;
+        let running_normal: SteamStatus = SteamStatus::new(SteamState::RunningNormal, None);
+        let running_big_picture: SteamStatus = SteamStatus::new(SteamState::RunningBigPicture, None);
+        assert!(AsyncSteamModifier::modify_observed(&proc_modifier, &desired_state, &options, &running_normal).await.unwrap());
+        assert!(AsyncSteamModifier::modify_observed(&proc_modifier, &desired_state, &options, &running_big_picture).await.unwrap());
+        let running_app: SteamStatus = SteamStatus::new(SteamState::RunningBigPicture, Some(570));
+        assert!(!AsyncSteamModifier::modify_observed(&proc_modifier, &desired_state, &options, &running_app).await.unwrap());
+        AsyncSteamModifier::kill_steam(&proc_modifier).await.unwrap();
+        assert!(runner.remaining().is_empty());
+    }
+}
\ No newline at end of file
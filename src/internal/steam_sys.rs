@@ -1,16 +1,26 @@
 use super::steam::{SteamState, SteamModifier};
+use super::command::{traced_command, traced_spawn, ExitCodes};
+use super::command_runner::{CommandRunner, CommandSpec, ProcessRunner};
+use super::cancellation::CancellationToken;
 
 use std::path::{Path, PathBuf};
 use std::ffi::CString;
 use std::process;
+use std::sync::Arc;
 
 use lazy_static::lazy_static;
+use winapi::shared::minwindef;
 use winapi::shared::windef;
+use winapi::shared::winerror;
+use winapi::um::winreg;
 use winapi::um::winuser;
 
+/// Steam backend using the Steam executable and user32 window queries.
 #[derive(Debug)]
 pub struct U32Modifier {
     pub (crate) exe_path: PathBuf,
+    pub (crate) runner: Arc<dyn CommandRunner>,
+    pub (crate) cancellation: Option<CancellationToken>,
 }
 
 impl SteamModifier for U32Modifier {
@@ -18,31 +28,55 @@ impl SteamModifier for U32Modifier {
         Ok(get_system_state())
     }
 
+    fn get_running_app(&self) -> Result<Option<u32>, crate::Error> {
+        Ok(get_running_app())
+    }
+
+    fn cancellation_token(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    fn request_shutdown(&self) -> Result<(), crate::Error> {
+        request_shutdown(&*self.runner, &self.exe_path)
+    }
+
     fn kill_steam(&self) -> Result<(), crate::Error> {
-        kill_steam()
+        kill_steam(&*self.runner)
+    }
+
+    fn launch_steam(&self, args: &[String]) -> Result<(), crate::Error> {
+        launch_steam(&*self.runner, &self.exe_path, args)
     }
 
-    fn launch_steam(&self) -> Result<(), crate::Error> {
-        launch_steam(&self.exe_path)
+    fn launch_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        launch_big_picture(&*self.runner, &self.exe_path, args)
     }
 
-    fn launch_big_picture(&self) -> Result<(), crate::Error> {
-        launch_big_picture(&self.exe_path)
+    fn launch_app(&self, app_id: u32, args: &[String]) -> Result<(), crate::Error> {
+        launch_app(&*self.runner, &self.exe_path, app_id, args)
     }
 
-    fn enable_big_picture(&self) -> Result<(), crate::Error> {
-        self.launch_big_picture()
+    fn enable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        self.launch_big_picture(args)
     }
 
-    fn disable_big_picture(&self) -> Result<(), crate::Error> {
-        self.kill_steam()?;
-        self.launch_steam()
+    fn disable_big_picture(&self, args: &[String]) -> Result<(), crate::Error> {
+        close_big_picture(&*self.runner, &self.exe_path, args)
     }
 }
 
 impl U32Modifier {
     pub fn new(exe_path: std::path::PathBuf) -> Self {
-        U32Modifier { exe_path: exe_path }
+        U32Modifier { exe_path: exe_path, runner: Arc::new(ProcessRunner::new()), cancellation: None }
+    }
+
+    pub fn with_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.runner = runner
+    }
+
+    /// Stops waiting for Steam to shut down once `token` is cancelled.
+    pub fn with_cancellation(&mut self, token: CancellationToken) {
+        self.cancellation = Some(token)
     }
 }
 
@@ -82,24 +116,103 @@ unsafe fn is_steam_running(lp_window_name_ptr: *const i8) -> bool {
     }
 }
 
-fn command_run(arg: &str, extra_args: &[&str]) -> Result<process::Output, crate::Error> {
-    process::Command::new(arg)
-        .args(extra_args)
-        .stdin(process::Stdio::piped())
-        .stdout(process::Stdio::piped())
-        .stderr(process::Stdio::piped())
-        .output()
-        .map_err(|e| crate::Error::CommandError(e))
+fn get_running_app() -> Option<u32> {
+    lazy_static! {
+        static ref LP_SUB_KEY: CString = CString::new(r"Software\Valve\Steam").unwrap();
+        static ref LP_VALUE: CString = CString::new("RunningAppID").unwrap();
+    }
+    let mut data: minwindef::DWORD = 0;
+    let mut data_size: minwindef::DWORD = std::mem::size_of::<minwindef::DWORD>() as minwindef::DWORD;
+    let status: i32 = unsafe {winreg::RegGetValueA(
+        winreg::HKEY_CURRENT_USER,
+        LP_SUB_KEY.as_ptr(),
+        LP_VALUE.as_ptr(),
+        winreg::RRF_RT_REG_DWORD,
+        std::ptr::null_mut(),
+        &mut data as *mut minwindef::DWORD as *mut _,
+        &mut data_size,
+    )};
+    match (status as u32 == winerror::ERROR_SUCCESS, data) {
+        (true, 0) | (false, _) => None,
+        (true, app_id) => Some(app_id),
+    }
+}
+
+fn command_run(runner: &dyn CommandRunner, arg: &str, extra_args: &[&str], exit_codes: ExitCodes) -> Result<process::Output, crate::Error> {
+    traced_command(runner, &CommandSpec::new(PathBuf::from(arg), extra_args), exit_codes)
+}
+
+fn steam_command(exe_path: &Path, args: &[String], extra_args: &[&str]) -> CommandSpec {
+    let mut all_args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    all_args.extend(extra_args);
+    CommandSpec::new(exe_path.to_path_buf(), &all_args)
+}
+
+// Launches start Steam, which keeps running, so they are spawned rather than
+// waited on.
+fn steam_spawn(runner: &dyn CommandRunner, exe_path: &Path, args: &[String], extra_args: &[&str]) -> Result<(), crate::Error> {
+    traced_spawn(runner, &steam_command(exe_path, args, extra_args))
+}
+
+fn request_shutdown(runner: &dyn CommandRunner, exe_path: &Path) -> Result<(), crate::Error> {
+    traced_command(runner, &steam_command(exe_path, &[], &["-shutdown"]), ExitCodes::Zero).map(|_| ())
+}
+
+fn kill_steam(runner: &dyn CommandRunner) -> Result<(), crate::Error> {
+    // taskkill exits 128 when no process matched.
+    command_run(runner, "taskkill", &["/F", "/IM", "steam.exe"], ExitCodes::OneOf(&[0, 128])).map(|_| ())
+}
+
+fn launch_steam(runner: &dyn CommandRunner, exe_path: &Path, args: &[String]) -> Result<(), crate::Error> {
+    steam_spawn(runner, exe_path, args, &[])
 }
 
-fn kill_steam() -> Result<(), crate::Error> {
-    command_run("taskkill", &["/F", "/IM", "steam.exe"]).map(|_| ())
+fn launch_big_picture(runner: &dyn CommandRunner, exe_path: &Path, args: &[String]) -> Result<(), crate::Error> {
+    steam_spawn(runner, exe_path, args, &["-start", "steam://open/bigpicture"])
 }
 
-fn launch_steam(exe_path: &Path) -> Result<(), crate::Error> {
-    command_run(&exe_path.to_string_lossy(), &[]).map(|_| ())
+fn close_big_picture(runner: &dyn CommandRunner, exe_path: &Path, args: &[String]) -> Result<(), crate::Error> {
+    steam_spawn(runner, exe_path, args, &["-start", "steam://close/bigpicture"])
 }
 
-fn launch_big_picture(exe_path: &Path) -> Result<(), crate::Error> {
-    command_run(&exe_path.to_string_lossy(), &["-start", "steam://open/bigpicture"]).map(|_| ())
+fn launch_app(runner: &dyn CommandRunner, exe_path: &Path, app_id: u32, args: &[String]) -> Result<(), crate::Error> {
+    steam_spawn(runner, exe_path, args, &["-start", &format!("steam://rungameid/{app_id}")])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::command_runner::{ReplayRunner, CommandRecord, CommandOutcome};
+
+    fn steam(args: &[&str], outcome: CommandOutcome) -> CommandRecord {
+        CommandRecord::new(CommandSpec::new(PathBuf::from("C:\\Steam\\steam.exe"), args), outcome)
+    }
+
+    fn exited(exit_code: i32) -> CommandOutcome {
+        CommandOutcome::Exited { exit_code: exit_code, stdout: Vec::new(), stderr: Vec::new() }
+    }
+
+    #[test]
+    fn spawns_launches_and_runs_shutdown() {
+        let runner: Arc<ReplayRunner> = Arc::new(ReplayRunner::new(vec![
+            steam(&["-silent"], CommandOutcome::Spawned),
+            steam(&["-silent", "-start", "steam://open/bigpicture"], CommandOutcome::Spawned),
+            steam(&["-silent", "-start", "steam://rungameid/570"], CommandOutcome::Spawned),
+            steam(&["-silent", "-start", "steam://close/bigpicture"], CommandOutcome::Spawned),
+            steam(&["-shutdown"], exited(0)),
+            CommandRecord::new(CommandSpec::new(PathBuf::from("taskkill"), &["/F", "/IM", "steam.exe"]), exited(128)),
+        ]));
+        let mut u32_modifier: U32Modifier = U32Modifier::new(PathBuf::from("C:\\Steam\\steam.exe"));
+        u32_modifier.with_runner(runner.clone());
+        let args: Vec<String> = vec!["-silent".to_string()];
+        // A ReplayRunner refuses to run a command recorded as spawned
+        u32_modifier.launch_steam(&args).unwrap();
+        u32_modifier.enable_big_picture(&args).unwrap();
+        u32_modifier.launch_app(570, &args).unwrap();
+        u32_modifier.disable_big_picture(&args).unwrap();
+        u32_modifier.request_shutdown().unwrap();
+        u32_modifier.kill_steam().unwrap();
+        assert!(runner.remaining().is_empty());
+    }
 }
\ No newline at end of file
@@ -1,29 +1,40 @@
 use super::display_sys::MMTModifier;
 use super::audio_sys::ADCModifier;
 use super::steam_sys::U32Modifier;
-use super::task::Task;
+use super::task::{Task, TaskDiagnostic};
+use super::assets::{MMT, ADC, asset_path_or_default};
+
+use super::command_runner::{CommandRunner, ProcessRunner};
+use super::cancellation::CancellationToken;
+use super::observer::RunObserver;
 
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time;
 
 type InnerSystemModifier = super::system_modifier_inner::InnerSystemModifier<MMTModifier, ADCModifier, U32Modifier>;
 
-static MMT_PATH: &[&str] = &["assets", "MultiMonitorTool.exe"];
-static ADC_PATH: &[&str] = &["assets", "AudioDeviceCmdlets.dll"];
-
-const DEFAULT_SLEEP_INTERVAL_SECS: u64 = 5;
-const DEFAULT_MAX_ATTEMPTS: usize = 5;
-
+/// Runs tasks with the bundled MultiMonitorTool, AudioDeviceCmdlets and Steam backends.
 pub struct SystemModifier {
     inner: InnerSystemModifier,
 }
 
-#[allow(dead_code)]
 impl SystemModifier {
     pub fn run(&self, task: &Task) -> Result<bool, crate::Error> {
         self.inner.run(task)
     }
 
+    pub fn run_with_confirm<F>(&self, task: &Task, timeout: time::Duration, confirm: F) -> Result<bool, crate::Error>
+    where
+        F: FnOnce(time::Duration) -> bool,
+    {
+        self.inner.run_with_confirm(task, timeout, confirm)
+    }
+
+    pub fn validate(&self, task: &Task) -> Result<Vec<TaskDiagnostic>, crate::Error> {
+        self.inner.validate(task)
+    }
+
     pub fn display_id_readout(&self) -> Result<String, crate::Error> {
         self.inner.display_id_readout()
     }
@@ -32,14 +43,22 @@ impl SystemModifier {
         self.inner.audio_id_readout()
     }
     
+    /// Stops runs, and kills their commands, once cancelled.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        self.inner.cancellation_token()
+    }
+
     pub fn new(steam_exe_path: PathBuf) -> SystemModifier {
-        SystemModifier { inner: InnerSystemModifier {
-            display_modifier: MMTModifier::new(MMT_PATH.iter().collect()),
-            audio_modifier: ADCModifier::new(ADC_PATH.iter().collect()),
-            steam_modifier: U32Modifier::new(steam_exe_path),
-            max_attempts: DEFAULT_MAX_ATTEMPTS,
-            sleep_interval: time::Duration::from_secs(DEFAULT_SLEEP_INTERVAL_SECS),
-        }}
+        let mut system_modifier: SystemModifier = SystemModifier { inner: InnerSystemModifier::new(
+            MMTModifier::new(asset_path_or_default(&MMT)),
+            ADCModifier::new(asset_path_or_default(&ADC)),
+            U32Modifier::new(steam_exe_path),
+        )};
+        let cancellation: CancellationToken = system_modifier.inner.cancellation.clone();
+        system_modifier.with_command_runner(Arc::new(process_runner(None, &cancellation)));
+        system_modifier.inner.audio_modifier.with_cancellation(cancellation.clone());
+        system_modifier.inner.steam_modifier.with_cancellation(cancellation);
+        system_modifier
     }
 
     pub fn with_mmt_path(&mut self, path: PathBuf) {
@@ -47,17 +66,40 @@ impl SystemModifier {
     }
 
     pub fn with_adc_path(&mut self, path: PathBuf) {
-        self.inner.audio_modifier.module_path = path
+        self.inner.audio_modifier.session.module_path = path
     }
 
     pub fn with_steam_path(&mut self, path: PathBuf) {
         self.inner.steam_modifier.exe_path = path
     }
 
+    /// Runs MultiMonitorTool and Steam with `runner`.
+    pub fn with_command_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.inner.display_modifier.with_runner(runner.clone());
+        self.inner.steam_modifier.with_runner(runner)
+    }
+
+    /// Kills MultiMonitorTool, PowerShell and Steam commands if they have not
+    /// exited after `timeout`. This replaces a runner given with `with_command_runner`.
+    pub fn with_command_timeout(&mut self, timeout: time::Duration) {
+        let runner: ProcessRunner = process_runner(Some(timeout), &self.inner.cancellation);
+        self.with_command_runner(Arc::new(runner));
+        self.inner.audio_modifier.with_command_timeout(timeout)
+    }
+
+    pub fn with_run_timeout(&mut self, run_timeout: time::Duration) {
+        self.inner.run_timeout = Some(run_timeout)
+    }
+
     pub fn with_max_attempts(&mut self, max_attempts: usize) {
         self.inner.max_attempts = max_attempts
     }
 
+    /// Reports the progress of runs to `observer`.
+    pub fn with_observer(&mut self, observer: Arc<dyn RunObserver>) {
+        self.inner.observer = Some(observer)
+    }
+
     pub fn with_sleep_interval(&mut self, sleep_interval: time::Duration) {
         self.inner.sleep_interval = sleep_interval
     }
@@ -65,4 +107,11 @@ impl SystemModifier {
     pub fn finalize(self) -> SystemModifier {
         self
     }
+}
+
+fn process_runner(timeout: Option<time::Duration>, cancellation: &CancellationToken) -> ProcessRunner {
+    let mut runner: ProcessRunner = ProcessRunner::new();
+    if let Some(d) = timeout { runner.with_timeout(d) }
+    runner.with_cancellation(cancellation.clone());
+    runner
 }
\ No newline at end of file
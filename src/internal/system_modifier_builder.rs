@@ -0,0 +1,479 @@
+use super::display::DisplayModifier;
+use super::audio::AudioModifier;
+use super::steam::SteamModifier;
+use super::display_sys::MMTModifier;
+use super::display_xrandr::XrandrModifier;
+use super::audio_sys::ADCModifier;
+use super::audio_pactl::PactlModifier;
+use super::mock::{MockDisplayModifier, MockAudioModifier};
+use super::display::DisplayState;
+use super::audio::AudioState;
+use super::checksum::verify_sha256;
+use super::steam_discovery::discover_steam_path;
+use super::system_modifier_inner::InnerSystemModifier;
+use super::command_runner::{CommandRunner, ProcessRunner};
+use super::cancellation::CancellationToken;
+use super::observer::RunObserver;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time;
+
+use serde::Deserialize;
+
+/// A system modifier whose backends were chosen at runtime.
+pub type DynSystemModifier = InnerSystemModifier<
+    Box<dyn DisplayModifier>,
+    Box<dyn AudioModifier>,
+    Box<dyn SteamModifier>,
+>;
+
+/// `Mock` is an in-memory backend with no displays, which changes nothing on the system.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayBackend {
+    #[default]
+    Auto,
+    Mmt,
+    Xrandr,
+    Mock,
+}
+
+/// `Mock` is an in-memory backend with no devices, which changes nothing on the system.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioBackend {
+    #[default]
+    Auto,
+    Adc,
+    Pactl,
+    Mock,
+}
+
+impl std::fmt::Display for DisplayBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayBackend::Auto => "auto".fmt(f),
+            DisplayBackend::Mmt => "mmt".fmt(f),
+            DisplayBackend::Xrandr => "xrandr".fmt(f),
+            DisplayBackend::Mock => "mock".fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for DisplayBackend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(DisplayBackend::Auto),
+            "mmt" => Ok(DisplayBackend::Mmt),
+            "xrandr" => Ok(DisplayBackend::Xrandr),
+            "mock" => Ok(DisplayBackend::Mock),
+            _ => Err(format!("Unknown display backend: {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for AudioBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioBackend::Auto => "auto".fmt(f),
+            AudioBackend::Adc => "adc".fmt(f),
+            AudioBackend::Pactl => "pactl".fmt(f),
+            AudioBackend::Mock => "mock".fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for AudioBackend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(AudioBackend::Auto),
+            "adc" => Ok(AudioBackend::Adc),
+            "pactl" => Ok(AudioBackend::Pactl),
+            "mock" => Ok(AudioBackend::Mock),
+            _ => Err(format!("Unknown audio backend: {s}")),
+        }
+    }
+}
+
+/// Builds a `DynSystemModifier` from given backends, or from backends
+/// selected by kind. `Auto` picks the first backend available on this platform.
+pub struct SystemModifierBuilder {
+    display_modifier: Option<Box<dyn DisplayModifier>>,
+    audio_modifier: Option<Box<dyn AudioModifier>>,
+    steam_modifier: Option<Box<dyn SteamModifier>>,
+    display_backend: DisplayBackend,
+    audio_backend: AudioBackend,
+    steam_path: Option<PathBuf>,
+    mmt_path: Option<PathBuf>,
+    adc_path: Option<PathBuf>,
+    mmt_sha256: Option<String>,
+    adc_sha256: Option<String>,
+    verify_checksums: bool,
+    runner: Option<Arc<dyn CommandRunner>>,
+    command_timeout: Option<time::Duration>,
+    max_attempts: Option<usize>,
+    sleep_interval: Option<time::Duration>,
+    run_timeout: Option<time::Duration>,
+    observer: Option<Arc<dyn RunObserver>>,
+}
+
+impl SystemModifierBuilder {
+    pub fn new() -> SystemModifierBuilder {
+        SystemModifierBuilder {
+            display_modifier: None,
+            audio_modifier: None,
+            steam_modifier: None,
+            display_backend: DisplayBackend::Auto,
+            audio_backend: AudioBackend::Auto,
+            steam_path: None,
+            mmt_path: None,
+            adc_path: None,
+            mmt_sha256: None,
+            adc_sha256: None,
+            verify_checksums: false,
+            runner: None,
+            command_timeout: None,
+            max_attempts: None,
+            sleep_interval: None,
+            run_timeout: None,
+            observer: None,
+        }
+    }
+
+    pub fn with_display_modifier(&mut self, display_modifier: Box<dyn DisplayModifier>) {
+        self.display_modifier = Some(display_modifier)
+    }
+
+    pub fn with_audio_modifier(&mut self, audio_modifier: Box<dyn AudioModifier>) {
+        self.audio_modifier = Some(audio_modifier)
+    }
+
+    pub fn with_steam_modifier(&mut self, steam_modifier: Box<dyn SteamModifier>) {
+        self.steam_modifier = Some(steam_modifier)
+    }
+
+    pub fn with_display_backend(&mut self, display_backend: DisplayBackend) {
+        self.display_backend = display_backend
+    }
+
+    pub fn with_audio_backend(&mut self, audio_backend: AudioBackend) {
+        self.audio_backend = audio_backend
+    }
+
+    pub fn with_steam_path(&mut self, path: PathBuf) {
+        self.steam_path = Some(path)
+    }
+
+    pub fn with_mmt_path(&mut self, path: PathBuf) {
+        self.mmt_path = Some(path)
+    }
+
+    pub fn with_adc_path(&mut self, path: PathBuf) {
+        self.adc_path = Some(path)
+    }
+
+    /// Refuses a MultiMonitorTool whose SHA-256, in hex, is not `sha256`.
+    pub fn with_mmt_sha256(&mut self, sha256: String) {
+        self.mmt_sha256 = Some(sha256)
+    }
+
+    /// Refuses an AudioDeviceCmdlets whose SHA-256, in hex, is not `sha256`.
+    pub fn with_adc_sha256(&mut self, sha256: String) {
+        self.adc_sha256 = Some(sha256)
+    }
+
+    /// Refuses a MultiMonitorTool or AudioDeviceCmdlets without a configured
+    /// checksum unless it matches the checksum pinned for the bundled copy.
+    pub fn with_verify_checksums(&mut self, verify_checksums: bool) {
+        self.verify_checksums = verify_checksums
+    }
+
+    /// Runs the commands of backends selected by kind with `runner`, instead
+    /// of a `ProcessRunner` sharing the system modifier's cancellation token.
+    /// AudioDeviceCmdlets requests are then run as PowerShell commands of their
+    /// own, rather than in a long-lived PowerShell process.
+    pub fn with_command_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.runner = Some(runner)
+    }
+
+    /// Kills commands of backends selected by kind, other than those of a
+    /// runner given with `with_command_runner`, if they have not exited after `timeout`.
+    pub fn with_command_timeout(&mut self, timeout: time::Duration) {
+        self.command_timeout = Some(timeout)
+    }
+
+    pub fn with_max_attempts(&mut self, max_attempts: usize) {
+        self.max_attempts = Some(max_attempts)
+    }
+
+    pub fn with_sleep_interval(&mut self, sleep_interval: time::Duration) {
+        self.sleep_interval = Some(sleep_interval)
+    }
+
+    pub fn with_run_timeout(&mut self, run_timeout: time::Duration) {
+        self.run_timeout = Some(run_timeout)
+    }
+
+    pub fn with_observer(&mut self, observer: Arc<dyn RunObserver>) {
+        self.observer = Some(observer)
+    }
+
+    pub fn finalize(self) -> Result<DynSystemModifier, crate::Error> {
+        let cancellation: CancellationToken = CancellationToken::new();
+        // Only a runner given to the builder replaces AudioDeviceCmdlets' PowerShell session
+        let session_runner: Option<Arc<dyn CommandRunner>> = self.runner.clone();
+        let runner: Arc<dyn CommandRunner> = match self.runner {
+            Some(r) => r,
+            None => {
+                let mut process_runner: ProcessRunner = ProcessRunner::new();
+                if let Some(d) = self.command_timeout { process_runner.with_timeout(d) }
+                process_runner.with_cancellation(cancellation.clone());
+                Arc::new(process_runner)
+            },
+        };
+        let display_modifier: Box<dyn DisplayModifier> = match self.display_modifier {
+            Some(m) => m,
+            None => select_display_modifier(
+                self.display_backend,
+                self.mmt_path,
+                expected_sha256(self.mmt_sha256, self.verify_checksums, Tool::Mmt),
+                runner.clone(),
+            )?,
+        };
+        let audio_modifier: Box<dyn AudioModifier> = match self.audio_modifier {
+            Some(m) => m,
+            None => select_audio_modifier(
+                self.audio_backend,
+                self.adc_path,
+                expected_sha256(self.adc_sha256, self.verify_checksums, Tool::Adc),
+                runner.clone(),
+                session_runner,
+                self.command_timeout,
+                cancellation.clone(),
+            )?,
+        };
+        let steam_modifier: Box<dyn SteamModifier> = match self.steam_modifier {
+            Some(m) => m,
+            None => select_steam_modifier(self.steam_path, runner, cancellation.clone())?,
+        };
+        let mut system_modifier: DynSystemModifier = InnerSystemModifier::new(
+            display_modifier,
+            audio_modifier,
+            steam_modifier,
+        );
+        if let Some(u) = self.max_attempts { system_modifier.with_max_attempts(u) }
+        if let Some(d) = self.sleep_interval { system_modifier.with_sleep_interval(d) }
+        if let Some(d) = self.run_timeout { system_modifier.with_run_timeout(d) }
+        if let Some(o) = self.observer { system_modifier.with_observer(o) }
+        system_modifier.with_cancellation_token(cancellation);
+        Ok(system_modifier)
+    }
+}
+
+fn select_display_modifier(
+    display_backend: DisplayBackend,
+    mmt_path: Option<PathBuf>,
+    mmt_sha256: Option<String>,
+    runner: Arc<dyn CommandRunner>,
+) -> Result<Box<dyn DisplayModifier>, crate::Error> {
+    let display_backend: DisplayBackend = match display_backend {
+        DisplayBackend::Auto if cfg!(windows) => DisplayBackend::Mmt,
+        DisplayBackend::Auto if !cfg!(windows) && find_on_path("xrandr").is_some() => DisplayBackend::Xrandr,
+        DisplayBackend::Auto => return Err(crate::Error::BackendUnavailable("no display backend available")),
+        b => b,
+    };
+    match display_backend {
+        DisplayBackend::Mmt => mmt_modifier(mmt_path, mmt_sha256, runner),
+        DisplayBackend::Xrandr => match find_on_path("xrandr") {
+            Some(p) => {
+                let mut xrandr_modifier: XrandrModifier = XrandrModifier::new(p);
+                xrandr_modifier.with_runner(runner);
+                Ok(Box::new(xrandr_modifier))
+            },
+            None => Err(crate::Error::BackendUnavailable("xrandr not found")),
+        },
+        DisplayBackend::Mock => Ok(Box::new(MockDisplayModifier::new(DisplayState::new()))),
+        DisplayBackend::Auto => unreachable!(),
+    }
+}
+
+fn select_audio_modifier(
+    audio_backend: AudioBackend,
+    adc_path: Option<PathBuf>,
+    adc_sha256: Option<String>,
+    runner: Arc<dyn CommandRunner>,
+    session_runner: Option<Arc<dyn CommandRunner>>,
+    command_timeout: Option<time::Duration>,
+    cancellation: CancellationToken,
+) -> Result<Box<dyn AudioModifier>, crate::Error> {
+    let audio_backend: AudioBackend = match audio_backend {
+        AudioBackend::Auto if cfg!(windows) => AudioBackend::Adc,
+        AudioBackend::Auto if !cfg!(windows) && find_on_path("pactl").is_some() => AudioBackend::Pactl,
+        AudioBackend::Auto => return Err(crate::Error::BackendUnavailable("no audio backend available")),
+        b => b,
+    };
+    match audio_backend {
+        AudioBackend::Adc => adc_modifier(adc_path, adc_sha256, session_runner, command_timeout, cancellation),
+        AudioBackend::Pactl => match find_on_path("pactl") {
+            Some(p) => {
+                let mut pactl_modifier: PactlModifier = PactlModifier::new(p);
+                pactl_modifier.with_runner(runner);
+                Ok(Box::new(pactl_modifier))
+            },
+            None => Err(crate::Error::BackendUnavailable("pactl not found")),
+        },
+        AudioBackend::Mock => Ok(Box::new(MockAudioModifier::new(AudioState::new(), Vec::new()))),
+        AudioBackend::Auto => unreachable!(),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Tool {
+    Mmt,
+    Adc,
+}
+
+// A configured checksum is always checked. Otherwise the pinned checksum is
+// checked if verification is on.
+fn expected_sha256(sha256: Option<String>, verify_checksums: bool, tool: Tool) -> Option<String> {
+    match (sha256, verify_checksums) {
+        (Some(s), _) => Some(s),
+        (None, true) => pinned_sha256(tool).map(|s| s.to_string()),
+        (None, false) => None,
+    }
+}
+
+#[cfg(windows)]
+fn pinned_sha256(tool: Tool) -> Option<&'static str> {
+    match tool {
+        Tool::Mmt => Some(super::assets::MMT.sha256),
+        Tool::Adc => Some(super::assets::ADC.sha256),
+    }
+}
+
+#[cfg(not(windows))]
+fn pinned_sha256(_tool: Tool) -> Option<&'static str> {
+    None
+}
+
+#[cfg(windows)]
+fn bundled_path(tool: Tool) -> Result<PathBuf, crate::Error> {
+    match tool {
+        Tool::Mmt => super::assets::asset_path(&super::assets::MMT),
+        Tool::Adc => super::assets::asset_path(&super::assets::ADC),
+    }
+}
+
+#[cfg(not(windows))]
+fn bundled_path(tool: Tool) -> Result<PathBuf, crate::Error> {
+    match tool {
+        Tool::Mmt => Err(crate::Error::BackendUnavailable("MultiMonitorTool is only bundled on Windows, give its path")),
+        Tool::Adc => Err(crate::Error::BackendUnavailable("AudioDeviceCmdlets is only bundled on Windows, give its path")),
+    }
+}
+
+// Without a path, the bundled MultiMonitorTool is used
+fn mmt_modifier(
+    mmt_path: Option<PathBuf>,
+    mmt_sha256: Option<String>,
+    runner: Arc<dyn CommandRunner>,
+) -> Result<Box<dyn DisplayModifier>, crate::Error> {
+    let mmt_path: PathBuf = match mmt_path {
+        Some(p) => p,
+        None => bundled_path(Tool::Mmt)?,
+    };
+    // Checked here to fail early, and again by the backend before every run
+    if let Some(s) = &mmt_sha256 {
+        verify_sha256(&mmt_path, s)?;
+    }
+    let mut mmt_modifier: MMTModifier = MMTModifier::new(mmt_path);
+    mmt_modifier.with_runner(runner);
+    if let Some(s) = mmt_sha256 { mmt_modifier.with_sha256(s) }
+    Ok(Box::new(mmt_modifier))
+}
+
+// Without a path, the bundled AudioDeviceCmdlets is used
+fn adc_modifier(
+    adc_path: Option<PathBuf>,
+    adc_sha256: Option<String>,
+    session_runner: Option<Arc<dyn CommandRunner>>,
+    command_timeout: Option<time::Duration>,
+    cancellation: CancellationToken,
+) -> Result<Box<dyn AudioModifier>, crate::Error> {
+    let adc_path: PathBuf = match adc_path {
+        Some(p) => p,
+        None => bundled_path(Tool::Adc)?,
+    };
+    // Checked here to fail early, and again every time PowerShell imports it
+    if let Some(s) = &adc_sha256 {
+        verify_sha256(&adc_path, s)?;
+    }
+    let mut adc_modifier: ADCModifier = ADCModifier::new(adc_path);
+    if let Some(s) = adc_sha256 { adc_modifier.with_sha256(s) }
+    if let Some(r) = session_runner { adc_modifier.with_runner(r) }
+    if let Some(d) = command_timeout { adc_modifier.with_command_timeout(d) }
+    adc_modifier.with_cancellation(cancellation);
+    Ok(Box::new(adc_modifier))
+}
+
+fn select_steam_modifier(
+    steam_path: Option<PathBuf>,
+    runner: Arc<dyn CommandRunner>,
+    cancellation: CancellationToken,
+) -> Result<Box<dyn SteamModifier>, crate::Error> {
+    let steam_path: PathBuf = match steam_path.or_else(discover_steam_path) {
+        Some(p) => p,
+        None => return Err(crate::Error::BackendUnavailable("steam installation not found")),
+    };
+    #[cfg(windows)]
+    let mut steam_modifier: super::steam_sys::U32Modifier = super::steam_sys::U32Modifier::new(steam_path);
+    #[cfg(not(windows))]
+    let mut steam_modifier: super::steam_proc::ProcModifier = super::steam_proc::ProcModifier::new(steam_path);
+    steam_modifier.with_runner(runner);
+    steam_modifier.with_cancellation(cancellation);
+    Ok(Box::new(steam_modifier))
+}
+
+fn find_on_path(program: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|p| p.join(program))
+        .find(|p| p.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::mock::MockSteamModifier;
+    use crate::internal::steam::SteamState;
+    use crate::internal::task::Task;
+
+    #[test]
+    fn parses_backend_names() {
+        for display_backend in [DisplayBackend::Auto, DisplayBackend::Mmt, DisplayBackend::Xrandr, DisplayBackend::Mock] {
+            assert_eq!(display_backend.to_string().parse::<DisplayBackend>(), Ok(display_backend));
+        }
+        for audio_backend in [AudioBackend::Auto, AudioBackend::Adc, AudioBackend::Pactl, AudioBackend::Mock] {
+            assert_eq!(audio_backend.to_string().parse::<AudioBackend>(), Ok(audio_backend));
+        }
+        assert!("ccd".parse::<DisplayBackend>().is_err());
+    }
+
+    #[test]
+    fn builds_with_mock_backends() {
+        let mut builder: SystemModifierBuilder = SystemModifierBuilder::new();
+        builder.with_display_backend(DisplayBackend::Mock);
+        builder.with_audio_backend(AudioBackend::Mock);
+        builder.with_steam_modifier(Box::new(MockSteamModifier::new(SteamState::NotRunning)));
+        builder.with_sleep_interval(time::Duration::ZERO);
+        let system_modifier: DynSystemModifier = builder.finalize().unwrap();
+        let mut task: Task = Task::new();
+        task.set_volume(40);
+        task.set_muted(true);
+        assert!(system_modifier.run(&task).unwrap());
+        assert_eq!(system_modifier.audio_modifier().get_system_state().unwrap(), AudioState::from_parts(String::new(), Some(40), Some(true)));
+    }
+}
\ No newline at end of file
@@ -1,18 +1,34 @@
 use super::display::{DisplayState, DisplayModifier};
 use super::audio::{AudioState, AudioModifier};
-use super::steam::{SteamState, SteamModifier};
-use super::task::Task;
+use super::steam::{SteamState, SteamStatus, SteamOptions, SteamModifier};
+use super::task::{Task, TaskDiagnostic, DeviceInventory};
+use super::config::{layout_path, is_layout_name};
+use super::cancellation::{self, CancellationToken};
+use super::observer::{RunObserver, RunEvent, Subsystem, Convergence};
 
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time;
 use std::thread;
+use std::fs;
+use std::io;
 
-#[derive(Debug, Copy, Clone)]
+pub (crate) const DEFAULT_SLEEP_INTERVAL_SECS: u64 = 5;
+pub (crate) const DEFAULT_MAX_ATTEMPTS: usize = 5;
+
+/// Runs tasks against any display, audio and Steam backends.
+///
+/// `SystemModifier` is this type with the bundled Windows backends.
+#[derive(Debug, Clone)]
 pub struct InnerSystemModifier<D, A, S> {
     pub (crate) display_modifier: D,
     pub (crate) audio_modifier: A,
     pub (crate) steam_modifier: S,
     pub (crate) max_attempts: usize,
     pub (crate) sleep_interval: time::Duration,
+    pub (crate) run_timeout: Option<time::Duration>,
+    pub (crate) cancellation: CancellationToken,
+    pub (crate) observer: Option<Arc<dyn RunObserver>>,
 }
 
 impl<D, A, S> InnerSystemModifier<D, A, S>
@@ -21,6 +37,64 @@ where
     A: AudioModifier,
     S: SteamModifier,
 {
+    pub fn new(display_modifier: D, audio_modifier: A, steam_modifier: S) -> InnerSystemModifier<D, A, S> {
+        InnerSystemModifier {
+            display_modifier: display_modifier,
+            audio_modifier: audio_modifier,
+            steam_modifier: steam_modifier,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            sleep_interval: time::Duration::from_secs(DEFAULT_SLEEP_INTERVAL_SECS),
+            run_timeout: None,
+            cancellation: CancellationToken::new(),
+            observer: None,
+        }
+    }
+
+    pub fn with_max_attempts(&mut self, max_attempts: usize) {
+        self.max_attempts = max_attempts
+    }
+
+    pub fn with_sleep_interval(&mut self, sleep_interval: time::Duration) {
+        self.sleep_interval = sleep_interval
+    }
+
+    /// Fails `run` with `Error::Timeout` if the system state has not matched
+    /// the task after `run_timeout`. `validate` gets the same deadline.
+    pub fn with_run_timeout(&mut self, run_timeout: time::Duration) {
+        self.run_timeout = Some(run_timeout)
+    }
+
+    /// Stops runs with `Error::Cancelled` once `token` is cancelled. Give the
+    /// backends' runners the same token for their commands to be killed too.
+    pub fn with_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = token
+    }
+
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Reports the progress of runs to `observer`.
+    pub fn with_observer(&mut self, observer: Arc<dyn RunObserver>) {
+        self.observer = Some(observer)
+    }
+
+    pub fn finalize(self) -> InnerSystemModifier<D, A, S> {
+        self
+    }
+
+    pub fn display_modifier(&self) -> &D {
+        &self.display_modifier
+    }
+
+    pub fn audio_modifier(&self) -> &A {
+        &self.audio_modifier
+    }
+
+    pub fn steam_modifier(&self) -> &S {
+        &self.steam_modifier
+    }
+
     pub fn display_id_readout(&self) -> Result<String, crate::Error> {
         self.display_modifier.get_id_readout()
     }
@@ -29,29 +103,582 @@ where
         self.audio_modifier.get_id_readout()
     }
 
+    pub fn device_inventory(&self, task: &Task) -> Result<DeviceInventory, crate::Error> {
+        Ok(DeviceInventory {
+            display_device_ids: match task.display_state.is_empty() {
+                true => None,
+                false => Some(self.display_modifier.get_device_ids()?),
+            },
+            audio_device_ids: match task.audio_state.primary_device_id.is_empty() {
+                true => None,
+                false => Some(self.audio_modifier.get_device_ids()?),
+            },
+        })
+    }
+
+    /// Checks the task for contradictions and for device IDs unknown to the backends.
+    pub fn validate(&self, task: &Task) -> Result<Vec<TaskDiagnostic>, crate::Error> {
+        let inventory: DeviceInventory = {
+            let _deadline = cancellation::enter_deadline(cancellation::deadline_after(self.run_timeout));
+            self.device_inventory(task)?
+        };
+        let mut diagnostics: Vec<TaskDiagnostic> = task.validate(&inventory);
+        if let Some(name) = &task.display_layout {
+            if !layout_path(name).is_some_and(|p| p.is_file()) {
+                diagnostics.push(TaskDiagnostic::UnknownLayout(name.clone()));
+            }
+        }
+        Ok(diagnostics)
+    }
+
+    /// Saves the current display configuration as a layout named `name`,
+    /// which tasks can load with `Task::set_display_layout`.
+    pub fn save_layout(&self, name: &str) -> Result<PathBuf, crate::Error> {
+        let path: PathBuf = layout_file(name)?;
+        if let Some(layouts_dir) = path.parent() {
+            fs::create_dir_all(layouts_dir).map_err(|e| crate::Error::LayoutIoError(e))?;
+        }
+        self.display_modifier.save_layout(&path)?;
+        Ok(path)
+    }
+
+    /// Runs the task until the system state matches it, or `max_attempts` is reached.
+    /// Returns false if the system state never matched.
+    ///
+    /// Tasks with contradictions are refused with `Error::InvalidTask`.
     pub fn run(&self, task: &Task) -> Result<bool, crate::Error> {
-        for _ in 0..self.max_attempts {
-            let continue_run: bool = self.check_and_modify(&task.display_state, &task.audio_state, &task.steam_state)?;
+        let diagnostics: Vec<TaskDiagnostic> = task.check_consistency();
+        let result: Result<bool, crate::Error> = match diagnostics.is_empty() {
+            true => {
+                let _deadline = cancellation::enter_deadline(cancellation::deadline_after(self.run_timeout));
+                self.run_attempts(task)
+            },
+            false => Err(crate::Error::InvalidTask(diagnostics)),
+        };
+        self.emit(RunEvent::RunFinished { result: result.as_ref().map(|b| *b).map_err(|e| format!("{e:?}")) });
+        result
+    }
+
+    fn run_attempts(&self, task: &Task) -> Result<bool, crate::Error> {
+        let mut convergence: Convergence = Convergence::default();
+        for attempt in 0..self.max_attempts {
+            let _span = tracing::info_span!("attempt", attempt = attempt + 1).entered();
+            self.cancellation.check()?;
+            self.emit(RunEvent::AttemptStarted { attempt: attempt + 1, max_attempts: self.max_attempts });
+            let continue_run: bool = self.check_and_modify(task, &mut convergence)?;
             if !continue_run {
                 return Ok(true)
             }
-            thread::sleep(self.sleep_interval);
+            self.cancellation.sleep(self.sleep_interval)?;
         }
         Ok(false)
     }
 
-    fn check_and_modify(
+    /// Runs the task, then asks `confirm` to keep any display changes.
+    /// `confirm` should return false if the user did not confirm within `timeout`,
+    /// in which case the previous display state is restored and false is returned.
+    ///
+    /// If the run fails after changing the displays, they are restored without
+    /// asking and the run's error is returned. Fails with `Error::DisplayRevertFailed`
+    /// if the previous display state could not be restored.
+    pub fn run_with_confirm<F>(
         &self,
-        display_state: &DisplayState,
-        audio_state: &AudioState,
-        steam_state: &SteamState,
-    ) -> Result<bool, crate::Error> {
-        let display_result: bool = self.display_modifier.check_and_modify(display_state)?;
-        let audio_result: bool = self.audio_modifier.check_and_modify(audio_state)?;
-        let steam_result: bool = self.steam_modifier.check_and_modify(steam_state)?;
+        task: &Task,
+        timeout: time::Duration,
+        confirm: F,
+    ) -> Result<bool, crate::Error>
+    where
+        F: FnOnce(time::Duration) -> bool,
+    {
+        if task.display_state.is_empty() && task.display_layout.is_none() {
+            return self.run(task)
+        }
+        let previous_state: DisplayState = self.display_modifier.get_system_state()?;
+        let result: Result<bool, crate::Error> = self.run(task);
+        let changed: bool = match (&result, self.display_modifier.get_system_state()) {
+            (_, Ok(s)) => s != previous_state,
+            (Ok(_), Err(e)) => return Err(e),
+            (Err(_), Err(_)) => return result,
+        };
+        match (result, changed) {
+            (result, false) => result,
+            (Ok(b), true) if confirm(timeout) => Ok(b),
+            (result, true) => match self.revert_display(&previous_state)? {
+                true => result.and(Ok(false)),
+                false => Err(crate::Error::DisplayRevertFailed(crate::error::DisplayRevertError {
+                    previous_state: previous_state,
+                    run_error: result.err().map(Box::new),
+                })),
+            },
+        }
+    }
+
+    fn revert_display(&self, previous_state: &DisplayState) -> Result<bool, crate::Error> {
+        for _ in 0..self.max_attempts {
+            self.cancellation.check()?;
+            if !self.display_modifier.check_and_modify(previous_state)? {
+                return Ok(true)
+            }
+            self.cancellation.sleep(self.sleep_interval)?;
+        }
+        Ok(false)
+    }
+
+    // The subsystems are queried concurrently, then modified one at a time:
+    // displays first, since enabling a display can add its audio device and
+    // Big Picture opens on the primary display. Audio or Steam state made
+    // stale by a display change is corrected on the next pass.
+    fn check_and_modify(&self, task: &Task, convergence: &mut Convergence) -> Result<bool, crate::Error> {
+        let audio_state: &AudioState = &task.audio_state;
+        let steam_state: &SteamState = &task.steam_state;
+        let steam_options: &SteamOptions = &task.steam_options;
+        let layout: Option<(PathBuf, DisplayState)> = match &task.display_layout {
+            Some(name) => {
+                let path: PathBuf = layout_file(name)?;
+                let layout_state: DisplayState = self.display_modifier.read_layout(&path)?;
+                Some((path, layout_state))
+            },
+            None => None,
+        };
+        let observed: ObservedState = self.observe(
+            layout.is_some() || !task.display_state.is_empty(),
+            !audio_state.is_empty(),
+            !steam_state.is_empty() || steam_options.app_id.is_some(),
+        )?;
+        self.emit(RunEvent::StateObserved {
+            display: observed.display.clone(),
+            audio: observed.audio.clone(),
+            steam: observed.steam,
+        });
+
+        let start: time::Instant = time::Instant::now();
+        let display_result: bool = match (&layout, &observed.display) {
+            (Some((path, layout_state)), Some(s)) => self.modified(
+                convergence,
+                Subsystem::Display,
+                self.display_modifier.load_layout_observed(path, layout_state, s),
+            )?,
+            (None, Some(s)) => self.modified(
+                convergence,
+                Subsystem::Display,
+                self.display_modifier.modify_observed(&task.display_state, s),
+            )?,
+            (_, None) => false,
+        };
+        let display_ms: u64 = start.elapsed().as_millis() as u64;
+        let audio_result: bool = match &observed.audio {
+            Some(s) => self.modified(convergence, Subsystem::Audio, self.audio_modifier.modify_observed(audio_state, s))?,
+            None => false,
+        };
+        let audio_ms: u64 = start.elapsed().as_millis() as u64 - display_ms;
+        let steam_result: bool = match &observed.steam {
+            Some(s) => self.modified(
+                convergence,
+                Subsystem::Steam,
+                self.steam_modifier.modify_observed(steam_state, steam_options, s),
+            )?,
+            None => false,
+        };
+        let steam_ms: u64 = start.elapsed().as_millis() as u64 - display_ms - audio_ms;
+        tracing::debug!(display_ms, audio_ms, steam_ms, "modified system state");
+
         match (display_result, audio_result, steam_result) {
             (false, false, false) => Ok(false),
             _ => Ok(true),
         }
     }
+
+    fn emit(&self, event: RunEvent) {
+        if let Some(observer) = &self.observer {
+            observer.on_event(&event)
+        }
+    }
+
+    // Reports the result of modifying a subsystem, and passes it on
+    fn modified(
+        &self,
+        convergence: &mut Convergence,
+        subsystem: Subsystem,
+        result: Result<bool, crate::Error>,
+    ) -> Result<bool, crate::Error> {
+        if let Some(event) = convergence.event(subsystem, &result) {
+            self.emit(event)
+        }
+        result
+    }
+
+    // Queries the state of each subsystem asked for on its own thread
+    fn observe(&self, query_display: bool, query_audio: bool, query_steam: bool) -> Result<ObservedState, crate::Error> {
+        let span: tracing::Span = tracing::Span::current();
+        let start: time::Instant = time::Instant::now();
+        let (display_query, audio_query, steam_query) = thread::scope(|scope| {
+            let display_handle = query_display.then(|| spawn_query(scope, &span, || self.display_modifier.get_system_state()));
+            let audio_handle = query_audio.then(|| spawn_query(scope, &span, || self.audio_modifier.get_system_state()));
+            let steam_handle = query_steam.then(|| spawn_query(scope, &span, || self.steam_modifier.get_status()));
+            (join_query(display_handle), join_query(audio_handle), join_query(steam_handle))
+        });
+        tracing::debug!(
+            display_ms = display_query.as_ref().map(|(_, ms)| *ms),
+            audio_ms = audio_query.as_ref().map(|(_, ms)| *ms),
+            steam_ms = steam_query.as_ref().map(|(_, ms)| *ms),
+            total_ms = start.elapsed().as_millis() as u64,
+            "queried system state",
+        );
+        Ok(ObservedState {
+            display: display_query.map(|(r, _)| r).transpose()?,
+            audio: audio_query.map(|(r, _)| r).transpose()?,
+            steam: steam_query.map(|(r, _)| r).transpose()?,
+        })
+    }
+}
+
+// The state of each subsystem the task changes
+#[derive(Debug)]
+pub (crate) struct ObservedState {
+    pub (crate) display: Option<DisplayState>,
+    pub (crate) audio: Option<AudioState>,
+    pub (crate) steam: Option<SteamStatus>,
+}
+
+type QueryHandle<'scope, T> = thread::ScopedJoinHandle<'scope, (Result<T, crate::Error>, u64)>;
+
+// Runs `query` within `span` and the run's deadline on a scoped thread,
+// timing it in milliseconds
+fn spawn_query<'scope, T, F>(
+    scope: &'scope thread::Scope<'scope, '_>,
+    span: &tracing::Span,
+    query: F,
+) -> QueryHandle<'scope, T>
+where
+    T: Send + 'scope,
+    F: FnOnce() -> Result<T, crate::Error> + Send + 'scope,
+{
+    let span: tracing::Span = span.clone();
+    let deadline: cancellation::Deadline = cancellation::current_deadline();
+    scope.spawn(move || {
+        let _span = span.entered();
+        let _deadline = cancellation::enter_deadline(deadline);
+        let start: time::Instant = time::Instant::now();
+        let result: Result<T, crate::Error> = query();
+        (result, start.elapsed().as_millis() as u64)
+    })
+}
+
+fn join_query<T>(handle: Option<QueryHandle<'_, T>>) -> Option<(Result<T, crate::Error>, u64)> {
+    handle.map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+}
+
+pub (crate) fn layout_file(name: &str) -> Result<PathBuf, crate::Error> {
+    if !is_layout_name(name) {
+        return Err(crate::Error::LayoutIoError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("layout name {name:?} should not be empty, . or .., nor contain a path separator"),
+        )))
+    }
+    layout_path(name).ok_or(crate::Error::LayoutIoError(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no config directory to keep layouts in",
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::mock::{MockDisplayModifier, MockAudioModifier, MockSteamModifier};
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // A mock display which ignores actions once frozen
+    #[derive(Debug)]
+    struct FreezableDisplay {
+        mock: MockDisplayModifier,
+        frozen: AtomicBool,
+    }
+
+    impl DisplayModifier for FreezableDisplay {
+        fn get_id_readout(&self) -> Result<String, crate::Error> {
+            self.mock.get_id_readout()
+        }
+
+        fn get_system_state(&self) -> Result<DisplayState, crate::Error> {
+            self.mock.get_system_state()
+        }
+
+        fn enable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+            match self.frozen.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => self.mock.enable_monitors(device_ids),
+            }
+        }
+
+        fn disable_monitors(&self, device_ids: &[&str]) -> Result<(), crate::Error> {
+            match self.frozen.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => self.mock.disable_monitors(device_ids),
+            }
+        }
+
+        fn set_primary_device(&self, id: &str) -> Result<(), crate::Error> {
+            match self.frozen.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => self.mock.set_primary_device(id),
+            }
+        }
+    }
+
+    // An audio backend whose actions fail
+    #[derive(Debug)]
+    struct FailingAudio;
+
+    impl AudioModifier for FailingAudio {
+        fn get_id_readout(&self) -> Result<String, crate::Error> {
+            Ok(String::new())
+        }
+
+        fn get_system_state(&self) -> Result<AudioState, crate::Error> {
+            Ok(AudioState::from_parts("speakers".to_string(), Some(50), Some(false)))
+        }
+
+        fn get_device_ids(&self) -> Result<Vec<String>, crate::Error> {
+            Ok(vec!["speakers".to_string()])
+        }
+
+        fn set_primary_device(&self, _id: &str) -> Result<(), crate::Error> {
+            Err(crate::Error::BackendUnavailable("audio backend is failing"))
+        }
+
+        fn set_volume(&self, _volume: u8) -> Result<(), crate::Error> {
+            Err(crate::Error::BackendUnavailable("audio backend is failing"))
+        }
+
+        fn set_muted(&self, _muted: bool) -> Result<(), crate::Error> {
+            Err(crate::Error::BackendUnavailable("audio backend is failing"))
+        }
+    }
+
+    fn desk() -> DisplayState {
+        DisplayState::from_device_ids("A".to_string(), vec!["A".to_string()], vec!["B".to_string()])
+    }
+
+    fn tv_task() -> Task {
+        let mut task: Task = Task::new();
+        task.set_primary_display("B".to_string());
+        task.enable_display("B".to_string());
+        task.disable_display("A".to_string());
+        task
+    }
+
+    fn system_modifier<A: AudioModifier>(audio_modifier: A) -> InnerSystemModifier<FreezableDisplay, A, MockSteamModifier> {
+        let display_modifier: FreezableDisplay = FreezableDisplay {
+            mock: MockDisplayModifier::new(desk()),
+            frozen: AtomicBool::new(false),
+        };
+        let mut system_modifier = InnerSystemModifier::new(
+            display_modifier,
+            audio_modifier,
+            MockSteamModifier::new(SteamState::NotRunning),
+        );
+        system_modifier.with_max_attempts(3);
+        system_modifier.with_sleep_interval(time::Duration::ZERO);
+        system_modifier
+    }
+
+    fn mock_audio() -> MockAudioModifier {
+        MockAudioModifier::new(AudioState::new(), Vec::new())
+    }
+
+    #[test]
+    fn confirmed_changes_are_kept() {
+        let system_modifier = system_modifier(mock_audio());
+        let result: Result<bool, crate::Error> = system_modifier.run_with_confirm(&tv_task(), time::Duration::ZERO, |_| true);
+        assert!(result.unwrap());
+        assert_eq!(system_modifier.display_modifier().mock.state().primary_device_id(), "B");
+    }
+
+    #[test]
+    fn unconfirmed_changes_are_reverted() {
+        let system_modifier = system_modifier(mock_audio());
+        let result: Result<bool, crate::Error> = system_modifier.run_with_confirm(&tv_task(), time::Duration::ZERO, |_| false);
+        assert!(!result.unwrap());
+        assert_eq!(system_modifier.display_modifier().mock.state(), desk());
+    }
+
+    #[test]
+    fn unchanged_displays_are_not_confirmed() {
+        let system_modifier = system_modifier(mock_audio());
+        let mut task: Task = Task::new();
+        task.set_primary_display("A".to_string());
+        let result: Result<bool, crate::Error> = system_modifier.run_with_confirm(&task, time::Duration::ZERO, |_| panic!("asked to confirm"));
+        assert!(result.unwrap());
+    }
+
+    #[test]
+    fn failed_revert_is_an_error() {
+        let system_modifier = system_modifier(mock_audio());
+        let result: Result<bool, crate::Error> = system_modifier.run_with_confirm(&tv_task(), time::Duration::ZERO, |_| {
+            system_modifier.display_modifier().frozen.store(true, Ordering::SeqCst);
+            false
+        });
+        match result {
+            Err(crate::Error::DisplayRevertFailed(e)) => {
+                assert_eq!(e.previous_state(), &desk());
+                assert!(e.run_error().is_none());
+            },
+            r => panic!("expected DisplayRevertFailed, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn failed_run_reverts_changed_displays() {
+        let system_modifier = system_modifier(FailingAudio);
+        let mut task: Task = tv_task();
+        task.set_volume(80);
+        let result: Result<bool, crate::Error> = system_modifier.run_with_confirm(&task, time::Duration::ZERO, |_| panic!("asked to confirm"));
+        assert!(matches!(result, Err(crate::Error::BackendUnavailable(_))));
+        assert_eq!(system_modifier.display_modifier().mock.state(), desk());
+    }
+
+    #[test]
+    fn refuses_layout_names_outside_layouts_dir() {
+        let system_modifier = system_modifier(mock_audio());
+        for name in ["", "..", "../../.ssh/authorized_keys", "a\\b"] {
+            match system_modifier.save_layout(name) {
+                Err(crate::Error::LayoutIoError(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+                r => panic!("expected LayoutIoError, got {r:?}"),
+            }
+            let mut task: Task = Task::new();
+            task.set_display_layout(name.to_string());
+            assert!(matches!(system_modifier.run(&task), Err(crate::Error::LayoutIoError(_))));
+            assert_eq!(system_modifier.validate(&task).unwrap(), vec![TaskDiagnostic::UnknownLayout(name.to_string())]);
+        }
+    }
+
+    // A display whose state queries run `sleep` through a runner sharing the token
+    #[cfg(unix)]
+    #[derive(Debug)]
+    struct SlowDisplay {
+        runner: crate::internal::command_runner::ProcessRunner,
+        seconds: &'static str,
+    }
+
+    #[cfg(unix)]
+    impl DisplayModifier for SlowDisplay {
+        fn get_id_readout(&self) -> Result<String, crate::Error> {
+            Ok(String::new())
+        }
+
+        fn get_system_state(&self) -> Result<DisplayState, crate::Error> {
+            use crate::internal::command_runner::{CommandRunner, CommandSpec};
+            self.runner.run(&CommandSpec::new(std::path::PathBuf::from("sleep"), &[self.seconds]))?;
+            Ok(desk())
+        }
+
+        fn enable_monitors(&self, _device_ids: &[&str]) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn disable_monitors(&self, _device_ids: &[&str]) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn set_primary_device(&self, _id: &str) -> Result<(), crate::Error> {
+            Ok(())
+        }
+    }
+
+    #[cfg(unix)]
+    fn slow_system_modifier(
+        seconds: &'static str,
+        token: &CancellationToken,
+    ) -> InnerSystemModifier<SlowDisplay, MockAudioModifier, MockSteamModifier> {
+        let mut runner: crate::internal::command_runner::ProcessRunner = crate::internal::command_runner::ProcessRunner::new();
+        runner.with_cancellation(token.clone());
+        let mut system_modifier = InnerSystemModifier::new(
+            SlowDisplay { runner: runner, seconds: seconds },
+            mock_audio(),
+            MockSteamModifier::new(SteamState::NotRunning),
+        );
+        system_modifier.with_sleep_interval(time::Duration::ZERO);
+        system_modifier.with_cancellation_token(token.clone());
+        system_modifier
+    }
+
+    #[cfg(unix)]
+    fn desk_task() -> Task {
+        let mut task: Task = Task::new();
+        task.set_primary_display("A".to_string());
+        task
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_timeout_kills_running_commands() {
+        let mut system_modifier = slow_system_modifier("5", &CancellationToken::new());
+        system_modifier.with_run_timeout(time::Duration::from_millis(100));
+        let start: time::Instant = time::Instant::now();
+        match system_modifier.run(&desk_task()) {
+            Err(crate::Error::Timeout(e)) => assert_eq!(e.timeout(), time::Duration::from_millis(100)),
+            r => panic!("expected Timeout, got {r:?}"),
+        }
+        assert!(start.elapsed() < time::Duration::from_secs(2));
+        // The deadline ends with the run
+        assert!(cancellation::current_deadline().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_deadlines_are_per_run() {
+        let token: CancellationToken = CancellationToken::new();
+        let mut hurried = slow_system_modifier("5", &token);
+        hurried.with_run_timeout(time::Duration::from_millis(100));
+        let patient = slow_system_modifier("0.5", &token);
+        thread::scope(|scope| {
+            let hurried_run = scope.spawn(|| hurried.run(&desk_task()));
+            let patient_run = scope.spawn(|| patient.run(&desk_task()));
+            assert!(matches!(hurried_run.join().unwrap(), Err(crate::Error::Timeout(_))));
+            assert!(patient_run.join().unwrap().unwrap());
+        });
+    }
+    fn layout_and_displays_task() -> Task {
+        let mut task: Task = tv_task();
+        task.set_display_layout("couch".to_string());
+        task
+    }
+
+    #[test]
+    fn observer_reports_run_events() {
+        let (tx, rx) = std::sync::mpsc::channel::<RunEvent>();
+        let mut system_modifier = system_modifier(mock_audio());
+        system_modifier.with_observer(Arc::new(tx));
+
+        assert!(system_modifier.run(&tv_task()).unwrap());
+        let events: Vec<RunEvent> = rx.try_iter().collect();
+        assert_eq!(events.first(), Some(&RunEvent::AttemptStarted { attempt: 1, max_attempts: 3 }));
+        assert!(events.contains(&RunEvent::ActionIssued { subsystem: Subsystem::Display }));
+        assert!(events.contains(&RunEvent::SubsystemConverged { subsystem: Subsystem::Display }));
+        assert_eq!(events.last(), Some(&RunEvent::RunFinished { result: Ok(true) }));
+
+        // Refused tasks still finish the run
+        assert!(matches!(system_modifier.run(&layout_and_displays_task()), Err(crate::Error::InvalidTask(_))));
+        let events: Vec<RunEvent> = rx.try_iter().collect();
+        assert!(matches!(events.as_slice(), [RunEvent::RunFinished { result: Err(e) }] if e.contains("InvalidTask")));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn observer_reports_refused_tasks_async() {
+        use crate::internal::async_modifier::Blocking;
+        use crate::internal::async_system_modifier::AsyncInnerSystemModifier;
+
+        let (tx, rx) = std::sync::mpsc::channel::<RunEvent>();
+        let mut system_modifier = AsyncInnerSystemModifier::new(
+            Blocking::new(MockDisplayModifier::new(desk())),
+            Blocking::new(mock_audio()),
+            Blocking::new(MockSteamModifier::new(SteamState::NotRunning)),
+        );
+        system_modifier.with_observer(Arc::new(tx));
+        assert!(matches!(system_modifier.run(&layout_and_displays_task()).await, Err(crate::Error::InvalidTask(_))));
+        let events: Vec<RunEvent> = rx.try_iter().collect();
+        assert!(matches!(events.as_slice(), [RunEvent::RunFinished { result: Err(e) }] if e.contains("InvalidTask")));
+    }
 }
\ No newline at end of file
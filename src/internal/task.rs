@@ -1,12 +1,98 @@
-use super::display::DisplayState;
+use super::display::{DisplayState, DisplayPosition};
 use super::audio::AudioState;
-use super::steam::SteamState;
+use super::steam::{SteamState, SteamOptions};
+use super::config::Aliases;
 
+use std::collections::BTreeSet;
+use std::time;
+
+/// The version of the serialized task schema. Documents with another
+/// `version` are refused, so that fields never change meaning silently.
+///
+/// Version 1 is an object with these fields, all but `version` optional:
+///
+/// - `version`: `1`
+/// - `display_state`: `primary_device_id`, `enabled_device_ids`,
+///   `disabled_device_ids`, and `positions` as `{"device_id", "x", "y"}` objects
+/// - `display_layout`: the name of a saved layout
+/// - `audio_state`: `primary_device_id`, `volume` from 0 to 100, and `muted`
+/// - `steam_state`: `"none"`, `"not_running"`, `"running_normal"` or `"running_big_picture"`
+/// - `steam_options`: `app_id`, `args`, and `shutdown_grace_period_secs`
+///
+/// Unknown fields are refused.
+#[cfg(feature = "serde")]
+pub const TASK_SCHEMA_VERSION: u32 = 1;
+
+// The serialized form of a task
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskDocument {
+    version: u32,
+    #[serde(default = "DisplayState::new", skip_serializing_if = "DisplayState::is_empty")]
+    display_state: DisplayState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    display_layout: Option<String>,
+    #[serde(default = "AudioState::new", skip_serializing_if = "AudioState::is_empty")]
+    audio_state: AudioState,
+    #[serde(default = "SteamState::new", skip_serializing_if = "SteamState::is_empty")]
+    steam_state: SteamState,
+    #[serde(default = "SteamOptions::new")]
+    steam_options: SteamOptions,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<TaskDocument> for Task {
+    type Error = String;
+    fn try_from(document: TaskDocument) -> Result<Self, Self::Error> {
+        if document.version != TASK_SCHEMA_VERSION {
+            return Err(format!(
+                "unsupported task schema version {}, expected {}",
+                document.version,
+                TASK_SCHEMA_VERSION,
+            ))
+        }
+        Ok(Task {
+            display_state: document.display_state,
+            display_layout: document.display_layout,
+            audio_state: document.audio_state,
+            steam_state: document.steam_state,
+            steam_options: document.steam_options,
+            aliases: Aliases::new(),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Task> for TaskDocument {
+    fn from(task: Task) -> Self {
+        TaskDocument {
+            version: TASK_SCHEMA_VERSION,
+            display_state: task.display_state,
+            display_layout: task.display_layout,
+            audio_state: task.audio_state,
+            steam_state: task.steam_state,
+            steam_options: task.steam_options,
+        }
+    }
+}
+
+/// The desired display, audio and Steam state.
+///
+/// Device IDs given to the setters may be aliases, see `Task::with_aliases`.
+///
+/// With the `serde` feature, a task is (de)serialized as a versioned
+/// document, see `TASK_SCHEMA_VERSION`. Aliases are not part of it.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "TaskDocument", into = "TaskDocument"))]
 pub struct Task {
     pub (crate) display_state: DisplayState,
+    pub (crate) display_layout: Option<String>,
     pub (crate) audio_state: AudioState,
     pub (crate) steam_state: SteamState,
+    pub (crate) steam_options: SteamOptions,
+    pub (crate) aliases: Aliases,
 }
 
 #[allow(dead_code)]
@@ -14,45 +100,114 @@ impl Task {
     pub fn new() -> Task {
         Task {
             display_state: DisplayState::new(),
+            display_layout: None,
             audio_state: AudioState::new(),
             steam_state: SteamState::new(),
+            steam_options: SteamOptions::new(),
+            aliases: Aliases::new(),
+        }
+    }
+
+    /// A task whose setters resolve aliases to device IDs.
+    pub fn with_aliases(aliases: Aliases) -> Task {
+        let mut task: Task = Task::new();
+        task.aliases = aliases;
+        task
+    }
+
+    /// Reads a task from a JSON document, see `TASK_SCHEMA_VERSION`.
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Task, crate::Error> {
+        serde_json::from_str(json).map_err(|e| crate::Error::TaskParseError(e))
+    }
+
+    /// Reads a task from a JSON document whose device IDs may be aliases.
+    /// Later setters resolve aliases too, as with `Task::with_aliases`.
+    #[cfg(feature = "serde")]
+    pub fn from_json_with_aliases(json: &str, aliases: Aliases) -> Result<Task, crate::Error> {
+        let mut task: Task = Task::from_json(json)?;
+        let display_state: &mut DisplayState = &mut task.display_state;
+        if !display_state.primary_device_id.is_empty() {
+            display_state.primary_device_id = aliases.resolve(&display_state.primary_device_id);
+        }
+        for device_id in display_state.enabled_device_ids.iter_mut().chain(&mut display_state.disabled_device_ids) {
+            *device_id = aliases.resolve(device_id);
+        }
+        for position in &mut display_state.positions {
+            position.device_id = aliases.resolve(&position.device_id);
         }
+        if !task.audio_state.primary_device_id.is_empty() {
+            task.audio_state.primary_device_id = aliases.resolve(&task.audio_state.primary_device_id);
+        }
+        task.aliases = aliases;
+        Ok(task)
+    }
+
+    pub fn display_state(&self) -> &DisplayState {
+        &self.display_state
+    }
+
+    /// The name of a saved display layout to load, see `layout_path`.
+    pub fn display_layout(&self) -> Option<&str> {
+        self.display_layout.as_deref()
+    }
+
+    pub fn audio_state(&self) -> &AudioState {
+        &self.audio_state
+    }
+
+    pub fn steam_state(&self) -> &SteamState {
+        &self.steam_state
+    }
+
+    pub fn steam_options(&self) -> &SteamOptions {
+        &self.steam_options
     }
 
     pub fn set_primary_display(&mut self, id: String) {
-        self.display_state.primary_device_id = id
+        self.display_state.primary_device_id = self.aliases.resolve(&id)
     }
 
     pub fn enable_display(&mut self, id: String) {
-        self.display_state.enabled_device_ids.push(id)
+        self.display_state.enabled_device_ids.push(self.aliases.resolve(&id))
     }
 
     pub fn enable_displays(&mut self, ids: Vec<String>) {
         for id in ids {
-            self.display_state.enabled_device_ids.push(id)
+            self.display_state.enabled_device_ids.push(self.aliases.resolve(&id))
         }
     }
 
     pub fn disable_display(&mut self, id: String) {
-        self.display_state.disabled_device_ids.push(id)
+        self.display_state.disabled_device_ids.push(self.aliases.resolve(&id))
     }
 
     pub fn disable_displays(&mut self, ids: Vec<String>) {
         for id in ids {
-            self.display_state.disabled_device_ids.push(id)
+            self.display_state.disabled_device_ids.push(self.aliases.resolve(&id))
         }
     }
 
+    /// Moves the display's top left corner to x, y on the desktop. Displays
+    /// without a position are left where they are.
+    pub fn set_display_position(&mut self, id: String, x: i32, y: i32) {
+        let device_id: String = self.aliases.resolve(&id);
+        self.display_state.positions.retain(|p| p.device_id != device_id);
+        self.display_state.positions.push(DisplayPosition::new(device_id, x, y))
+    }
+
+    /// Loads a display layout saved with `InnerSystemModifier::save_layout`
+    /// instead of changing displays one by one.
+    pub fn set_display_layout(&mut self, name: String) {
+        self.display_layout = Some(name)
+    }
+
     pub fn set_primary_audio(&mut self, id: String) {
-        self.audio_state.primary_device_id = id
+        self.audio_state.primary_device_id = self.aliases.resolve(&id)
     }
 
     pub fn set_volume(&mut self, volume: u8) {
-        let v: u8 = match volume > 100 {
-            true => 100,
-            false => volume,
-        };
-        self.audio_state.volume = Some(v)
+        self.audio_state.volume = Some(volume)
     }
 
     pub fn set_muted(&mut self, muted: bool) {
@@ -71,7 +226,310 @@ impl Task {
         self.steam_state = SteamState::RunningBigPicture
     }
 
+    pub fn set_steam_app(&mut self, app_id: u32) {
+        self.steam_options.app_id = Some(app_id)
+    }
+
+    pub fn add_steam_arg(&mut self, arg: String) {
+        self.steam_options.args.push(arg)
+    }
+
+    pub fn add_steam_args(&mut self, args: Vec<String>) {
+        for arg in args {
+            self.steam_options.args.push(arg)
+        }
+    }
+
+    pub fn set_steam_shutdown_grace_period(&mut self, grace_period: time::Duration) {
+        self.steam_options.shutdown_grace_period = Some(grace_period)
+    }
+
     pub fn finalize(self) -> Task {
         self
     }
+
+    /// Diagnostics for contradictions within the task itself, each reported once.
+    pub fn check_consistency(&self) -> Vec<TaskDiagnostic> {
+        self.consistency_diagnostics().into_iter().collect()
+    }
+
+    fn consistency_diagnostics(&self) -> BTreeSet<TaskDiagnostic> {
+        let mut diagnostics: BTreeSet<TaskDiagnostic> = BTreeSet::new();
+        let display_state: &DisplayState = &self.display_state;
+        for device_id in &display_state.enabled_device_ids {
+            if display_state.disabled_device_ids.contains(device_id) {
+                diagnostics.insert(TaskDiagnostic::DisplayEnabledAndDisabled(device_id.clone()));
+            }
+        }
+        if display_state.disabled_device_ids.contains(&display_state.primary_device_id) {
+            diagnostics.insert(TaskDiagnostic::PrimaryDisplayDisabled(display_state.primary_device_id.clone()));
+        }
+        for position in &display_state.positions {
+            if display_state.disabled_device_ids.contains(&position.device_id) {
+                diagnostics.insert(TaskDiagnostic::PositionedDisplayDisabled(position.device_id.clone()));
+            }
+        }
+        if let Some(name) = &self.display_layout {
+            if !display_state.is_empty() {
+                diagnostics.insert(TaskDiagnostic::LayoutWithDisplayChanges(name.clone()));
+            }
+        }
+        if let Some(volume) = self.audio_state.volume {
+            if volume > 100 {
+                diagnostics.insert(TaskDiagnostic::VolumeOutOfRange(volume));
+            }
+        }
+        if let (SteamState::NotRunning, Some(app_id)) = (self.steam_state, self.steam_options.app_id) {
+            diagnostics.insert(TaskDiagnostic::SteamAppWithSteamOff(app_id));
+        }
+        diagnostics
+    }
+
+    /// Diagnostics for contradictions within the task, and for device IDs
+    /// missing from the inventory, each reported once.
+    pub fn validate(&self, inventory: &DeviceInventory) -> Vec<TaskDiagnostic> {
+        let mut diagnostics: BTreeSet<TaskDiagnostic> = self.consistency_diagnostics();
+        if let Some(device_ids) = &inventory.display_device_ids {
+            let display_state: &DisplayState = &self.display_state;
+            let mut task_device_ids: Vec<&String> = Vec::new();
+            if !display_state.primary_device_id.is_empty() {
+                task_device_ids.push(&display_state.primary_device_id);
+            }
+            task_device_ids.extend(&display_state.enabled_device_ids);
+            task_device_ids.extend(&display_state.disabled_device_ids);
+            task_device_ids.extend(display_state.positions.iter().map(|p| &p.device_id));
+            for device_id in task_device_ids {
+                if !device_ids.contains(device_id) {
+                    diagnostics.insert(TaskDiagnostic::UnknownDisplay(device_id.clone()));
+                }
+            }
+        }
+        if let Some(device_ids) = &inventory.audio_device_ids {
+            let device_id: &String = &self.audio_state.primary_device_id;
+            if !device_id.is_empty() && !device_ids.contains(device_id) {
+                diagnostics.insert(TaskDiagnostic::UnknownAudioDevice(device_id.clone()));
+            }
+        }
+        diagnostics.into_iter().collect()
+    }
+}
+
+/// A reason a task cannot be run as given.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskDiagnostic {
+    DisplayEnabledAndDisabled(String),
+    PrimaryDisplayDisabled(String),
+    PositionedDisplayDisabled(String),
+    LayoutWithDisplayChanges(String),
+    UnknownLayout(String),
+    VolumeOutOfRange(u8),
+    UnknownDisplay(String),
+    UnknownAudioDevice(String),
+    SteamAppWithSteamOff(u32),
+}
+
+impl std::fmt::Display for TaskDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskDiagnostic::DisplayEnabledAndDisabled(id) => write!(f, "display {id} is both enabled and disabled"),
+            TaskDiagnostic::PrimaryDisplayDisabled(id) => write!(f, "primary display {id} is also disabled"),
+            TaskDiagnostic::PositionedDisplayDisabled(id) => write!(f, "display {id} is given a position but also disabled"),
+            TaskDiagnostic::LayoutWithDisplayChanges(name) => write!(f, "layout {name} cannot be combined with other display changes"),
+            TaskDiagnostic::UnknownLayout(name) => write!(f, "layout {name} has not been saved"),
+            TaskDiagnostic::VolumeOutOfRange(v) => write!(f, "volume {v} is greater than 100"),
+            TaskDiagnostic::UnknownDisplay(id) => write!(f, "display {id} does not match any display"),
+            TaskDiagnostic::UnknownAudioDevice(id) => write!(f, "audio device {id} does not match any audio device"),
+            TaskDiagnostic::SteamAppWithSteamOff(id) => write!(f, "app {id} cannot be launched while steam is off"),
+        }
+    }
+}
+
+/// Device IDs known to the backends. `None` means the inventory was not queried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInventory {
+    pub (crate) display_device_ids: Option<Vec<String>>,
+    pub (crate) audio_device_ids: Option<Vec<String>>,
+}
+
+impl DeviceInventory {
+    pub fn new(display_device_ids: Option<Vec<String>>, audio_device_ids: Option<Vec<String>>) -> DeviceInventory {
+        DeviceInventory {
+            display_device_ids: display_device_ids,
+            audio_device_ids: audio_device_ids,
+        }
+    }
+
+    pub fn display_device_ids(&self) -> Option<&[String]> {
+        self.display_device_ids.as_deref()
+    }
+
+    pub fn audio_device_ids(&self) -> Option<&[String]> {
+        self.audio_device_ids.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inventory(display_device_ids: &[&str], audio_device_ids: &[&str]) -> DeviceInventory {
+        DeviceInventory::new(
+            Some(display_device_ids.iter().map(|s| s.to_string()).collect()),
+            Some(audio_device_ids.iter().map(|s| s.to_string()).collect()),
+        )
+    }
+
+    #[test]
+    fn consistent_task_has_no_diagnostics() {
+        let mut task: Task = Task::new();
+        task.set_primary_display("A".to_string());
+        task.enable_display("A".to_string());
+        task.disable_display("B".to_string());
+        task.set_volume(100);
+        task.set_steam_running_big_picture();
+        task.set_steam_app(570);
+        assert_eq!(task.check_consistency(), vec![]);
+    }
+
+    #[test]
+    fn reports_contradictions() {
+        let mut task: Task = Task::new();
+        task.set_primary_display("A".to_string());
+        task.enable_display("B".to_string());
+        task.disable_displays(vec!["A".to_string(), "B".to_string(), "C".to_string()]);
+        task.set_display_position("C".to_string(), 0, 0);
+        task.set_volume(101);
+        task.set_steam_not_running();
+        task.set_steam_app(570);
+        assert_eq!(task.check_consistency(), vec![
+            TaskDiagnostic::DisplayEnabledAndDisabled("B".to_string()),
+            TaskDiagnostic::PrimaryDisplayDisabled("A".to_string()),
+            TaskDiagnostic::PositionedDisplayDisabled("C".to_string()),
+            TaskDiagnostic::VolumeOutOfRange(101),
+            TaskDiagnostic::SteamAppWithSteamOff(570),
+        ]);
+    }
+
+    #[test]
+    fn reports_layout_with_display_changes() {
+        let mut task: Task = Task::new();
+        task.set_display_layout("tv".to_string());
+        task.enable_display("A".to_string());
+        assert_eq!(task.check_consistency(), vec![TaskDiagnostic::LayoutWithDisplayChanges("tv".to_string())]);
+    }
+
+    #[test]
+    fn repeated_contradictions_are_reported_once() {
+        let mut task: Task = Task::new();
+        task.enable_displays(vec!["A".to_string(), "A".to_string()]);
+        task.disable_displays(vec!["A".to_string(), "A".to_string()]);
+        assert_eq!(task.check_consistency(), vec![TaskDiagnostic::DisplayEnabledAndDisabled("A".to_string())]);
+    }
+
+    #[test]
+    fn validate_reports_unknown_devices_once() {
+        let mut task: Task = Task::new();
+        task.set_primary_display("X".to_string());
+        task.enable_display("X".to_string());
+        task.set_display_position("X".to_string(), 1920, 0);
+        task.disable_display("A".to_string());
+        task.set_primary_audio("headphones".to_string());
+        assert_eq!(task.validate(&inventory(&["A", "B"], &["speakers"])), vec![
+            TaskDiagnostic::UnknownDisplay("X".to_string()),
+            TaskDiagnostic::UnknownAudioDevice("headphones".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn validate_includes_contradictions() {
+        let mut task: Task = Task::new();
+        task.enable_display("A".to_string());
+        task.disable_display("A".to_string());
+        assert_eq!(task.validate(&inventory(&["A"], &[])), vec![TaskDiagnostic::DisplayEnabledAndDisabled("A".to_string())]);
+    }
+
+    #[test]
+    fn validate_skips_inventories_not_queried() {
+        let mut task: Task = Task::new();
+        task.enable_display("X".to_string());
+        task.set_primary_audio("headphones".to_string());
+        assert_eq!(task.validate(&DeviceInventory::new(None, None)), vec![]);
+    }
+
+    #[cfg(feature = "serde")]
+    fn task_parse_error(json: &str) -> String {
+        match Task::from_json(json) {
+            Err(crate::Error::TaskParseError(e)) => e.to_string(),
+            r => panic!("expected TaskParseError, got {r:?}"),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_through_json() {
+        let mut task: Task = Task::new();
+        task.set_primary_display("tv".to_string());
+        task.enable_display("tv".to_string());
+        task.disable_display("desk".to_string());
+        task.set_display_position("tv".to_string(), 0, 0);
+        task.set_primary_audio("tv-audio".to_string());
+        task.set_volume(60);
+        task.set_muted(false);
+        task.set_steam_running_big_picture();
+        task.set_steam_app(570);
+        task.add_steam_arg("-silent".to_string());
+        task.set_steam_shutdown_grace_period(time::Duration::from_secs(15));
+        let json: String = serde_json::to_string(&task).unwrap();
+        assert_eq!(Task::from_json(&json).unwrap(), task);
+
+        let mut layout_task: Task = Task::new();
+        layout_task.set_display_layout("couch".to_string());
+        layout_task.set_steam_running_big_picture();
+        let json: String = serde_json::to_string(&layout_task).unwrap();
+        assert_eq!(Task::from_json(&json).unwrap(), layout_task);
+
+        // The documents in the README, in order
+        let readme_tasks: Vec<Task> = readme_task_documents().iter().map(|d| Task::from_json(d).unwrap()).collect();
+        assert_eq!(readme_tasks, vec![task, layout_task]);
+    }
+
+    // The JSON blocks of the README's Task JSON section
+    #[cfg(feature = "serde")]
+    fn readme_task_documents() -> Vec<&'static str> {
+        let readme: &str = include_str!("../../README.md");
+        let section: &str = readme.split("\n## Task JSON\n").nth(1).unwrap().split("\n## ").next().unwrap();
+        section.split("```json\n").skip(1).map(|block| block.split("```").next().unwrap()).collect()
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn readme_task_documents_are_consistent() {
+        let documents: Vec<&str> = readme_task_documents();
+        assert!(!documents.is_empty());
+        for document in documents {
+            assert!(Task::from_json(document).unwrap().check_consistency().is_empty(), "{document}");
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn empty_fields_are_left_out() {
+        let json: serde_json::Value = serde_json::to_value(Task::new()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "version": TASK_SCHEMA_VERSION,
+            "steam_options": { "app_id": null, "args": [], "shutdown_grace_period_secs": null },
+        }));
+        assert_eq!(Task::from_json(r#"{"version": 1}"#).unwrap(), Task::new());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn refuses_other_schema_versions() {
+        assert!(task_parse_error("{}").contains("missing field `version`"));
+        assert!(task_parse_error(r#"{"version": 2}"#).contains("unsupported task schema version 2, expected 1"));
+        assert!(task_parse_error(r#"{"version": 0}"#).contains("unsupported task schema version 0"));
+        assert!(task_parse_error(r#"{"version": "1"}"#).contains("invalid type"));
+        assert!(task_parse_error(r#"{"version": -1}"#).contains("invalid value"));
+        assert!(task_parse_error(r#"{"version": 1, "steam": "none"}"#).contains("unknown field `steam`"));
+    }
 }
\ No newline at end of file
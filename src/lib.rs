@@ -1,6 +1,43 @@
+//! Switch monitors, audio devices and Steam between desk and Big Picture setups.
+//!
+//! A [`Task`] describes the desired state, and `SystemModifier` applies it
+//! with the bundled Windows backends, which only exist on Windows targets. Custom backends implement
+//! [`DisplayModifier`], [`AudioModifier`] and [`SteamModifier`], and are run
+//! with [`InnerSystemModifier`], or chosen at runtime with [`SystemModifierBuilder`].
+//! With the `async` feature, `AsyncInnerSystemModifier` runs tasks on tokio.
+//!
+//! Everything re-exported here is the public API, and follows semver as
+//! recorded in CHANGELOG.md.
+
 mod error;
-pub use error::{Error, MMTParseError, ADCParseError};
+pub use error::{Error, MMTParseError, MMTLayoutParseError, ADCParseError, DisplaySafetyError, DisplayRevertError, XrandrParseError, PactlParseError, CommandFailedError, PowershellSessionError, ChecksumMismatchError, TimeoutError, UnexpectedCommandError};
 
 mod internal;
+#[cfg(windows)]
 pub use internal::system_modifier::SystemModifier;
-pub use internal::task::Task;
\ No newline at end of file
+pub use internal::system_modifier_inner::InnerSystemModifier;
+pub use internal::system_modifier_builder::{SystemModifierBuilder, DynSystemModifier, DisplayBackend, AudioBackend};
+pub use internal::config::{Config, Aliases, config_dir, cache_dir, layouts_dir, layout_path, layout_names};
+pub use internal::display::{DisplayState, DisplayPosition, DisplayModifier};
+pub use internal::audio::{AudioState, AudioModifier, AudioDevice, AudioDeviceType};
+pub use internal::steam::{SteamState, SteamStatus, SteamOptions, SteamModifier};
+pub use internal::display_sys::MMTModifier;
+pub use internal::audio_sys::ADCModifier;
+pub use internal::powershell_session::PowershellSession;
+pub use internal::cancellation::CancellationToken;
+pub use internal::observer::{RunObserver, RunEvent, Subsystem};
+pub use internal::command_runner::{CommandRunner, CommandSpec, ProcessRunner, RecordingRunner, ReplayRunner, CommandRecord, CommandOutcome};
+#[cfg(windows)]
+pub use internal::steam_sys::U32Modifier;
+pub use internal::display_xrandr::XrandrModifier;
+pub use internal::audio_pactl::PactlModifier;
+pub use internal::steam_proc::ProcModifier;
+#[cfg(feature = "async")]
+pub use internal::async_modifier::{AsyncDisplayModifier, AsyncAudioModifier, AsyncSteamModifier, Blocking};
+#[cfg(feature = "async")]
+pub use internal::async_system_modifier::AsyncInnerSystemModifier;
+pub use internal::mock::{MockDisplayModifier, MockAudioModifier, MockSteamModifier};
+pub use internal::steam_discovery::discover_steam_path;
+pub use internal::task::{Task, TaskDiagnostic, DeviceInventory};
+#[cfg(feature = "serde")]
+pub use internal::task::TASK_SCHEMA_VERSION;
\ No newline at end of file
@@ -1,14 +1,29 @@
-mod error;
-use error::Error;
-
-mod internal;
-use internal::system_modifier::SystemModifier;
-use internal::task::Task;
+use steamwig::{
+    DisplayModifier,
+    AudioModifier,
+    Error,
+    Config,
+    RunObserver,
+    RunEvent,
+    CancellationToken,
+    SystemModifierBuilder,
+    DynSystemModifier,
+    DisplayBackend,
+    AudioBackend,
+    Task,
+    TaskDiagnostic,
+    layout_names,
+};
 
 use std::path::PathBuf;
 use std::time::Duration;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::io;
+use std::fs;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum Steam {
@@ -39,12 +54,71 @@ impl std::str::FromStr for Steam {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Text => "text".fmt(f),
+            LogFormat::Json => "json".fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format: {s}")),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage device aliases from the config file
+    Aliases {
+        #[command(subcommand)]
+        command: AliasesCommand,
+    },
+    /// Manage saved display layouts
+    Layouts {
+        #[command(subcommand)]
+        command: LayoutsCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AliasesCommand {
+    /// Report aliases which no longer match any display or audio device
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+enum LayoutsCommand {
+    /// Save the current display configuration as a layout
+    Save {
+        name: String,
+    },
+    /// List saved layouts
+    List,
+}
+
 #[derive(Parser, Debug)]
 #[command(version)]
 struct Args {
-    /// [path]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// [path] Discovered from the Steam installation if omitted
     #[arg(long)]
-    steam_path: PathBuf,
+    steam_path: Option<PathBuf>,
 
     /// [path]
     #[arg(long)]
@@ -54,6 +128,22 @@ struct Args {
     #[arg(long)]
     adc_path: Option<PathBuf>,
 
+    /// Refuse MultiMonitorTool and AudioDeviceCmdlets unless their SHA-256 matches the pinned or configured checksum
+    #[arg(long)]
+    verify_checksums: bool,
+
+    /// [path] Defaults to config.toml in the steamwig config directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// [possible values: auto, mmt, xrandr, mock]
+    #[arg(long)]
+    display_backend: Option<DisplayBackend>,
+
+    /// [possible values: auto, adc, pactl, mock]
+    #[arg(long)]
+    audio_backend: Option<AudioBackend>,
+
     /// [integer]
     #[arg(long)]
     max_attempts: Option<usize>,
@@ -62,19 +152,40 @@ struct Args {
     #[arg(long)]
     sleep_interval: Option<u64>,
 
-    /// [ID code]
+    /// [integer] Seconds after which a hung backend command is killed
+    #[arg(long)]
+    command_timeout: Option<u64>,
+
+    /// [integer] Seconds after which the run gives up
+    #[arg(long)]
+    run_timeout: Option<u64>,
+
+    /// [ID code or alias]
     #[arg(long)]
     primary_display: Option<String>,
 
-    /// [ID code]
+    /// [ID code or alias]
     #[arg(long)]
     enable_display: Option<Vec<String>>,
 
-    /// [ID code]
+    /// [ID code or alias]
     #[arg(long)]
     disable_display: Option<Vec<String>>,
 
-    /// [ID code]
+    /// [path] Start from a JSON task document, or - for stdin. Other task flags apply on top
+    #[cfg(feature = "serde")]
+    #[arg(long)]
+    task_json: Option<PathBuf>,
+
+    /// [name] Load a layout saved with `steamwig layouts save`
+    #[arg(long)]
+    display_layout: Option<String>,
+
+    /// [ID=X,Y] Desktop position of a display's top left corner, e.g. DISPLAY2=-1920,0
+    #[arg(long, value_parser = parse_display_position, allow_hyphen_values = true)]
+    display_position: Option<Vec<(String, i32, i32)>>,
+
+    /// [ID code or alias]
     #[arg(long)]
     primary_audio: Option<String>,
 
@@ -89,20 +200,210 @@ struct Args {
     #[arg(long)]
     steam: Option<Steam>,
 
+    /// [integer] Steam app ID to launch
+    #[arg(long)]
+    steam_app: Option<u32>,
+
+    /// [argument] Passed to Steam when launching it, e.g. -silent
+    #[arg(long, allow_hyphen_values = true)]
+    steam_arg: Option<Vec<String>>,
+
+    /// [integer] Seconds to wait for Steam to shut down before killing it, default 15
+    #[arg(long)]
+    steam_shutdown_grace_period: Option<u64>,
+
+    /// Revert display changes unless confirmed within [integer] seconds
+    #[arg(long)]
+    confirm_timeout: Option<u64>,
+
     /// Print all Display and Audio IDs
     #[arg(short, long)]
     readout: bool,
+
+    /// Log backend commands and states, -vv to include command output
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// [path] Write logs to this file instead of stderr
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// [possible values: text, json]
+    #[arg(long, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+fn parse_display_position(s: &str) -> Result<(String, i32, i32), String> {
+    let (id, position) = s.rsplit_once('=').ok_or(format!("Expected ID=X,Y: {s}"))?;
+    let (x, y) = position.split_once(',').ok_or(format!("Expected ID=X,Y: {s}"))?;
+    match (x.trim().parse::<i32>(), y.trim().parse::<i32>()) {
+        (Ok(x), Ok(y)) => Ok((id.to_string(), x, y)),
+        _ => Err(format!("Expected integer coordinates: {position}")),
+    }
+}
+
+fn init_logging(verbose: u8, log_file: Option<&PathBuf>, log_format: LogFormat) {
+    let level: tracing::Level = match verbose {
+        0 => tracing::Level::WARN,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    let writer: BoxMakeWriter = match log_file {
+        Some(p) => match fs::File::create(p) {
+            Ok(f) => BoxMakeWriter::new(Mutex::new(f)),
+            Err(e) => panic!("{:?}", e),
+        },
+        None => BoxMakeWriter::new(io::stderr),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(log_file.is_none())
+        .with_writer(writer);
+    match log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+// Prints the progress of runs
+#[derive(Debug)]
+struct ProgressPrinter;
+
+impl RunObserver for ProgressPrinter {
+    fn on_event(&self, event: &RunEvent) {
+        match event {
+            RunEvent::AttemptStarted { attempt, max_attempts } => println!("Attempt {attempt} of {max_attempts}"),
+            RunEvent::ActionIssued { subsystem } => println!("  {subsystem}: changed"),
+            RunEvent::ActionFailed { subsystem, .. } => eprintln!("  {subsystem}: failed"),
+            RunEvent::SubsystemConverged { subsystem } => println!("  {subsystem}: matches"),
+            _ => (),
+        }
+    }
+}
+
+fn confirm_displays(timeout: Duration) -> bool {
+    println!("Keep these display settings? Reverting in {} seconds [y/N]", timeout.as_secs());
+    let (tx, rx) = mpsc::channel::<String>();
+    thread::spawn(move || {
+        let mut line: String = String::new();
+        if io::stdin().read_line(&mut line).is_ok() {
+            let _ = tx.send(line);
+        }
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(line) if line.trim().eq_ignore_ascii_case("y") => true,
+        _ => {
+            println!("Reverting display settings");
+            false
+        },
+    }
+}
+
+// Reports failures of external tools and exits, or panics on any other error
+fn exit_on_error(error: Error) -> ! {
+    match error {
+        Error::CommandFailed(e) => eprintln!("Command failed: {}", e),
+        Error::Timeout(e) => eprintln!("Timed out: {}", e),
+        Error::DisplayRevertFailed(_) => eprintln!("Could not restore the previous display state"),
+        Error::Cancelled => {
+            eprintln!("Cancelled");
+            std::process::exit(130);
+        },
+        e => panic!("{:?}", e),
+    }
+    std::process::exit(1);
+}
+
+#[cfg(feature = "serde")]
+fn read_task_json(path: &PathBuf, config: &Config) -> Task {
+    let read: io::Result<String> = match path.to_str() {
+        Some("-") => io::read_to_string(io::stdin()),
+        _ => fs::read_to_string(path),
+    };
+    let json: String = read.unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {e}", path.display());
+        std::process::exit(1);
+    });
+    match Task::from_json_with_aliases(&json, config.aliases().clone()) {
+        Ok(task) => task,
+        Err(Error::TaskParseError(e)) => {
+            eprintln!("Invalid task JSON: {e}");
+            std::process::exit(1);
+        },
+        Err(e) => panic!("{:?}", e),
+    }
+}
+
+fn check_aliases(system_modifier: &DynSystemModifier, config: &Config) {
+    let mut device_ids: Vec<String> = match system_modifier.display_modifier().get_device_ids() {
+        Ok(ids) => ids,
+        Err(e) => panic!("{:?}", e),
+    };
+    match system_modifier.audio_modifier().get_device_ids() {
+        Ok(ids) => device_ids.extend(ids),
+        Err(e) => panic!("{:?}", e),
+    }
+    let stale_aliases: Vec<(&str, &str)> = config.aliases().check(&device_ids);
+    if stale_aliases.is_empty() {
+        println!("All aliases match a device");
+        return ()
+    }
+    for (name, device_id) in stale_aliases {
+        println!("{name} = {device_id} does not match any device");
+    }
+    std::process::exit(1);
 }
 
 fn main() {
     let args = Args::parse();
 
-    println!("{:?}", args);
+    init_logging(args.verbose, args.log_file.as_ref(), args.log_format);
+
+    tracing::debug!(?args, "parsed arguments");
 
-    let mut system_modifier: SystemModifier = SystemModifier::new(args.steam_path.clone());
+    let config: Config = match &args.config {
+        Some(p) => Config::load(p),
+        None => Config::load_default(),
+    }.unwrap_or_else(|e| panic!("{:?}", e));
 
-    if let Some(p) = args.mmt_path { system_modifier.with_mmt_path(p) }
-    if let Some(p) = args.adc_path { system_modifier.with_adc_path(p) }
+    let mut builder: SystemModifierBuilder = SystemModifierBuilder::new();
+
+    builder.with_display_backend(args.display_backend.unwrap_or(config.display_backend()));
+    builder.with_audio_backend(args.audio_backend.unwrap_or(config.audio_backend()));
+    if let Some(p) = args.steam_path { builder.with_steam_path(p) }
+    if let Some(p) = args.mmt_path { builder.with_mmt_path(p) }
+    if let Some(p) = args.adc_path { builder.with_adc_path(p) }
+    if let Some(s) = config.mmt_sha256() { builder.with_mmt_sha256(s.to_string()) }
+    if let Some(s) = config.adc_sha256() { builder.with_adc_sha256(s.to_string()) }
+    builder.with_verify_checksums(args.verify_checksums || config.verify_checksums());
+    if let Some(u) = args.max_attempts { builder.with_max_attempts(u) }
+    if let Some(u) = args.sleep_interval { builder.with_sleep_interval(Duration::from_secs(u)) }
+    if let Some(d) = args.command_timeout.map(Duration::from_secs).or(config.command_timeout()) {
+        builder.with_command_timeout(d)
+    }
+    if let Some(d) = args.run_timeout.map(Duration::from_secs).or(config.run_timeout()) {
+        builder.with_run_timeout(d)
+    }
+    builder.with_observer(Arc::new(ProgressPrinter));
+
+    let system_modifier: DynSystemModifier = match builder.finalize() {
+        Ok(m) => m,
+        Err(Error::BackendUnavailable(s)) => {
+            eprintln!("Backend unavailable: {s}");
+            std::process::exit(1);
+        },
+        Err(Error::ChecksumMismatch(e)) => {
+            eprintln!("Checksum mismatch: {e}");
+            std::process::exit(1);
+        },
+        Err(e) => panic!("{:?}", e),
+    };
+
+    // Ctrl-C kills any running command and stops the run
+    let cancellation: CancellationToken = system_modifier.cancellation_token().clone();
+    if let Err(e) = ctrlc::set_handler(move || cancellation.cancel()) {
+        tracing::warn!(error = ?e, "could not set the Ctrl-C handler");
+    }
 
     if args.readout {
         let display_readout: String = match system_modifier.display_id_readout() {
@@ -114,23 +415,52 @@ fn main() {
             Err(e) => panic!("{:?}", e),
         };
         println!("Display Readout");
-        println!("");
+        println!();
         println!("{}", display_readout);
-        println!("");
+        println!();
         println!("Audio Readout");
-        println!("");
+        println!();
         println!("{}", audio_readout);
         return ()
     }
 
-    if let Some(u) = args.max_attempts { system_modifier.with_max_attempts(u) }
-    if let Some(u) = args.sleep_interval { system_modifier.with_sleep_interval(Duration::from_secs(u)) }
+    match args.command {
+        Some(Command::Aliases { command: AliasesCommand::Check }) => {
+            check_aliases(&system_modifier, &config);
+            return ()
+        },
+        Some(Command::Layouts { command: LayoutsCommand::Save { name } }) => {
+            match system_modifier.save_layout(&name) {
+                Ok(p) => println!("Saved layout {name} to {}", p.display()),
+                Err(e) => panic!("{:?}", e),
+            }
+            return ()
+        },
+        Some(Command::Layouts { command: LayoutsCommand::List }) => {
+            match layout_names() {
+                Ok(names) => for name in names { println!("{name}") },
+                Err(e) => panic!("{:?}", e),
+            }
+            return ()
+        },
+        None => (),
+    }
 
-    let mut task: Task = Task::new();
+    #[cfg(feature = "serde")]
+    let mut task: Task = match &args.task_json {
+        Some(p) => read_task_json(p, &config),
+        None => Task::with_aliases(config.aliases().clone()),
+    };
+    #[cfg(not(feature = "serde"))]
+    let mut task: Task = Task::with_aliases(config.aliases().clone());
 
+    if let Some(name) = args.display_layout { task.set_display_layout(name) }
     if let Some(id) = args.primary_display { task.set_primary_display(id) }
     if let Some(ids) = args.enable_display { task.enable_displays(ids) }
     if let Some(ids) = args.disable_display { task.disable_displays(ids) }
+    if let Some(positions) = args.display_position {
+        for (id, x, y) in positions { task.set_display_position(id, x, y) }
+    }
     if let Some(id) = args.primary_audio { task.set_primary_audio(id) }
     if let Some(v) = args.volume { task.set_volume(v) }
     if let Some(m) = args.muted { task.set_muted(m) }
@@ -142,9 +472,30 @@ fn main() {
         }
     }
 
-    match system_modifier.run(&task) {
+    if let Some(id) = args.steam_app { task.set_steam_app(id) }
+    if let Some(a) = args.steam_arg { task.add_steam_args(a) }
+    if let Some(u) = args.steam_shutdown_grace_period { task.set_steam_shutdown_grace_period(Duration::from_secs(u)) }
+
+    let diagnostics: Vec<TaskDiagnostic> = match system_modifier.validate(&task) {
+        Ok(d) => d,
+        Err(e) => exit_on_error(e),
+    };
+    if !diagnostics.is_empty() {
+        eprintln!("Invalid task");
+        for diagnostic in diagnostics {
+            eprintln!("  {}", diagnostic);
+        }
+        std::process::exit(1);
+    }
+
+    let result: Result<bool, Error> = match args.confirm_timeout {
+        Some(u) => system_modifier.run_with_confirm(&task, Duration::from_secs(u), confirm_displays),
+        None => system_modifier.run(&task),
+    };
+
+    match result {
         Ok(true) => println!("Success"),
         Ok(false) => println!("Failure"),
-        Err(e) => panic!("{:?}", e),
+        Err(e) => exit_on_error(e),
     }
 }
\ No newline at end of file